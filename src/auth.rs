@@ -0,0 +1,499 @@
+use crate::signing;
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::error;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+// Node enrollment settings of the server
+#[derive(Clone, Debug)]
+pub struct AuthConfig {
+    // Bearer token of the admin API
+    pub admin_token: String,
+    // How long a join token stays valid unless the admin asks otherwise
+    pub join_token_ttl: Duration,
+    // How long a credential stays valid; without it only revocation ends a credential
+    pub credential_ttl: Option<Duration>,
+    // File the signing key, open join tokens and credentials are kept in across restarts
+    pub state_path: Option<PathBuf>,
+}
+
+// Reason a request was not authenticated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthError {
+    // No bearer token was presented
+    Missing,
+    // The token is malformed, forged, expired, used up or unknown
+    Invalid,
+    // The credential was revoked by an admin
+    Revoked,
+    // The token belongs to another node than the one named in the request
+    WrongNode,
+    // An update's signature is missing or does not match the node's registered key
+    BadSignature,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "Missing bearer token"),
+            AuthError::Invalid => write!(f, "Invalid or expired token"),
+            AuthError::Revoked => write!(f, "Credential has been revoked"),
+            AuthError::WrongNode => write!(f, "Token was issued to another node"),
+            AuthError::BadSignature => write!(f, "Missing or invalid update signature"),
+        }
+    }
+}
+
+// A join token waiting to be exchanged for a credential
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JoinToken {
+    // Node address the token is restricted to, if any
+    pub node_addr: Option<String>,
+    // Unix time after which the token is refused
+    pub expires_at: u64,
+}
+
+// A node credential as listed by the admin API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CredentialInfo {
+    pub id: String,
+    pub node_addr: String,
+    // Hex-encoded ed25519 key the node signs its updates with
+    pub public_key: String,
+    pub issued_at: u64,
+    // Unix time after which the credential is refused, if it expires
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    pub revoked: bool,
+}
+
+// What a node keeps after enrolling
+#[derive(Serialize, Deserialize)]
+pub struct NodeCredential {
+    // Bearer token sent with every message
+    pub credential: String,
+    // Hex-encoded ed25519 key the node signs its updates with
+    pub signing_key: String,
+    // Hex-encoded ed25519 key global models from the server are checked against
+    pub server_key: String,
+}
+
+// Claims of a credential, a JWT signed with HS256
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    // Node address the credential is bound to
+    sub: String,
+    // Credential ID, checked against revocations
+    jti: String,
+    iat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+}
+
+// What the server keeps across restarts
+#[derive(Serialize, Deserialize, Default)]
+struct AuthState {
+    // Hex-encoded HMAC key credentials are signed with
+    secret: String,
+    // Hex-encoded ed25519 key global models are signed with
+    signing_key: String,
+    // Open join tokens, keyed by the SHA-256 hash of the token
+    join_tokens: HashMap<String, JoinToken>,
+    // Issued credentials by ID
+    credentials: BTreeMap<String, CredentialInfo>,
+}
+
+// Issues join tokens and credentials and checks the credentials nodes present
+pub struct Enrollment {
+    config: AuthConfig,
+    admin_token_hash: String,
+    signing_key: SigningKey,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    validation: Validation,
+    state: Mutex<AuthState>,
+}
+
+impl Enrollment {
+    pub fn new(config: AuthConfig) -> Result<Self> {
+        let state = match &config.state_path {
+            Some(path) if path.exists() => {
+                let json = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+                serde_json::from_str(&json)
+                    .map_err(|e| anyhow!("Invalid enrollment state {}: {}", path.display(), e))?
+            }
+            _ => AuthState {
+                secret: random_hex(32),
+                signing_key: signing::encode_signing_key(&signing::generate_key()),
+                ..AuthState::default()
+            },
+        };
+        let signing_key = signing::parse_signing_key(&state.signing_key)
+            .map_err(|e| anyhow!("Invalid model signing key in the enrollment state: {}", e))?;
+        let secret = hex::decode(&state.secret)
+            .map_err(|e| anyhow!("Invalid signing key in the enrollment state: {}", e))?;
+
+        // Credentials expire only with a credential TTL; all of them end when revoked
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["sub"]);
+
+        let enrollment = Self {
+            admin_token_hash: sha256_hex(&config.admin_token),
+            signing_key,
+            encoding_key: EncodingKey::from_secret(&secret),
+            decoding_key: DecodingKey::from_secret(&secret),
+            validation,
+            config,
+            state: Mutex::new(state),
+        };
+        enrollment.save(&enrollment.state.lock().unwrap_or_else(|e| e.into_inner()));
+        Ok(enrollment)
+    }
+
+    // Check the admin API's bearer token
+    pub fn authorize_admin(&self, bearer: Option<&str>) -> Result<(), AuthError> {
+        let token = bearer.ok_or(AuthError::Missing)?;
+        // Compare hashes in constant time so the comparison time says nothing about the token
+        let matches: bool = sha256_hex(token)
+            .as_bytes()
+            .ct_eq(self.admin_token_hash.as_bytes())
+            .into();
+        if !matches {
+            return Err(AuthError::Invalid);
+        }
+        Ok(())
+    }
+
+    // Create a single-use join token, optionally restricted to one node address
+    pub fn issue_join_token(
+        &self,
+        node_addr: Option<String>,
+        ttl: Option<Duration>,
+    ) -> (String, JoinToken) {
+        let token = random_hex(32);
+        let join_token = JoinToken {
+            node_addr,
+            expires_at: now() + ttl.unwrap_or(self.config.join_token_ttl).as_secs(),
+        };
+        let mut state = self.lock();
+        let now = now();
+        state.join_tokens.retain(|_, t| t.expires_at > now);
+        state
+            .join_tokens
+            .insert(sha256_hex(&token), join_token.clone());
+        self.save(&state);
+        (token, join_token)
+    }
+
+    // Key the server signs global models with
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.signing_key
+    }
+
+    // Exchange a join token for a credential bound to the node address,
+    // registering the key the node signs its updates with
+    pub fn enroll(
+        &self,
+        token: &str,
+        node_addr: &str,
+        public_key: &str,
+    ) -> Result<(String, CredentialInfo), AuthError> {
+        signing::parse_verifying_key(public_key).map_err(|_| AuthError::Invalid)?;
+        let mut state = self.lock();
+        let hash = sha256_hex(token);
+        let join_token = state.join_tokens.get(&hash).ok_or(AuthError::Invalid)?;
+        if join_token.expires_at <= now() {
+            state.join_tokens.remove(&hash);
+            self.save(&state);
+            return Err(AuthError::Invalid);
+        }
+        if join_token
+            .node_addr
+            .as_ref()
+            .is_some_and(|addr| addr != node_addr)
+        {
+            return Err(AuthError::WrongNode);
+        }
+        state.join_tokens.remove(&hash);
+
+        let issued_at = now();
+        let info = CredentialInfo {
+            id: random_hex(16),
+            node_addr: node_addr.to_string(),
+            public_key: public_key.to_string(),
+            issued_at,
+            expires_at: self
+                .config
+                .credential_ttl
+                .map(|ttl| issued_at + ttl.as_secs()),
+            revoked: false,
+        };
+        let claims = Claims {
+            sub: info.node_addr.clone(),
+            jti: info.id.clone(),
+            iat: info.issued_at,
+            exp: info.expires_at,
+        };
+        let credential = encode(&Header::new(Algorithm::HS256), &claims, &self.encoding_key)
+            .map_err(|e| {
+                error!("Failed to sign credential: {}", e);
+                AuthError::Invalid
+            })?;
+        state.credentials.insert(info.id.clone(), info.clone());
+        self.save(&state);
+        Ok((credential, info))
+    }
+
+    // Verify a node credential, returning the node address and update key it is bound to
+    pub fn authenticate(&self, bearer: Option<&str>) -> Result<(String, VerifyingKey), AuthError> {
+        let token = bearer.ok_or(AuthError::Missing)?;
+        let claims = decode::<Claims>(token, &self.decoding_key, &self.validation)
+            .map_err(|_| AuthError::Invalid)?
+            .claims;
+        match self.lock().credentials.get(&claims.jti) {
+            Some(info) if info.revoked => Err(AuthError::Revoked),
+            Some(info) if info.node_addr == claims.sub => {
+                let key = signing::parse_verifying_key(&info.public_key)
+                    .map_err(|_| AuthError::Invalid)?;
+                Ok((claims.sub, key))
+            }
+            _ => Err(AuthError::Invalid),
+        }
+    }
+
+    pub fn credentials(&self) -> Vec<CredentialInfo> {
+        self.lock().credentials.values().cloned().collect()
+    }
+
+    // Revoke a credential so that its node is no longer accepted
+    pub fn revoke(&self, id: &str) -> Option<CredentialInfo> {
+        let mut state = self.lock();
+        let info = state.credentials.get_mut(id)?;
+        info.revoked = true;
+        let info = info.clone();
+        self.save(&state);
+        Some(info)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AuthState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Write the state to the state file, if configured. A failed write is
+    // logged; the server keeps working with the state in memory.
+    fn save(&self, state: &AuthState) {
+        let Some(path) = &self.config.state_path else {
+            return;
+        };
+        let result = serde_json::to_vec_pretty(state)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                let tmp = path.with_extension("tmp");
+                write_private(&tmp, &json)?;
+                std::fs::rename(&tmp, path)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            error!(
+                "Failed to save enrollment state to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+// Write a file holding secret keys, readable and writable by its owner only
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, so tighten an existing one as well
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0; bytes];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: &str = "https://node-a.example:8002";
+
+    fn enrollment() -> Enrollment {
+        Enrollment::new(AuthConfig {
+            admin_token: "admin-secret".to_string(),
+            join_token_ttl: Duration::from_secs(60),
+            credential_ttl: None,
+            state_path: None,
+        })
+        .unwrap()
+    }
+
+    fn public_key() -> String {
+        signing::encode_verifying_key(&signing::generate_key().verifying_key())
+    }
+
+    // Enroll NODE, returning its credential and credential ID
+    fn enroll(enrollment: &Enrollment) -> (String, String) {
+        let (token, _) = enrollment.issue_join_token(None, None);
+        let (credential, info) = enrollment.enroll(&token, NODE, &public_key()).unwrap();
+        (credential, info.id)
+    }
+
+    fn claims(id: &str, exp: Option<u64>) -> Claims {
+        Claims {
+            sub: NODE.to_string(),
+            jti: id.to_string(),
+            iat: now(),
+            exp,
+        }
+    }
+
+    #[test]
+    fn accepts_an_enrolled_credential() {
+        let enrollment = enrollment();
+        let (credential, _) = enroll(&enrollment);
+        let (node_addr, _) = enrollment.authenticate(Some(&credential)).unwrap();
+        assert_eq!(node_addr, NODE);
+        assert_eq!(enrollment.authenticate(None), Err(AuthError::Missing));
+    }
+
+    #[test]
+    fn rejects_expired_credentials() {
+        let enrollment = enrollment();
+        let (_, id) = enroll(&enrollment);
+        let header = Header::new(Algorithm::HS256);
+        let expired = encode(
+            &header,
+            &claims(&id, Some(now() - 1)),
+            &enrollment.encoding_key,
+        );
+        assert_eq!(
+            enrollment.authenticate(Some(&expired.unwrap())),
+            Err(AuthError::Invalid)
+        );
+        let valid = encode(
+            &header,
+            &claims(&id, Some(now() + 60)),
+            &enrollment.encoding_key,
+        );
+        assert!(enrollment.authenticate(Some(&valid.unwrap())).is_ok());
+    }
+
+    #[test]
+    fn rejects_revoked_credentials() {
+        let enrollment = enrollment();
+        let (credential, id) = enroll(&enrollment);
+        assert!(enrollment.revoke(&id).unwrap().revoked);
+        assert_eq!(
+            enrollment.authenticate(Some(&credential)),
+            Err(AuthError::Revoked)
+        );
+    }
+
+    #[test]
+    fn rejects_credentials_signed_with_another_key() {
+        let enrollment = enrollment();
+        let (credential, id) = enroll(&enrollment);
+
+        // Same claims, signed by another server
+        let forged = encode(
+            &Header::new(Algorithm::HS256),
+            &claims(&id, None),
+            &EncodingKey::from_secret(b"another secret"),
+        )
+        .unwrap();
+        assert_eq!(
+            enrollment.authenticate(Some(&forged)),
+            Err(AuthError::Invalid)
+        );
+        assert_eq!(
+            enrollment.authenticate(Some(&credential[..credential.len() - 2])),
+            Err(AuthError::Invalid)
+        );
+        // A credential is only good for the node it was issued to
+        let mut moved = claims(&id, None);
+        moved.sub = "https://node-b.example:8002".to_string();
+        let moved = encode(
+            &Header::new(Algorithm::HS256),
+            &moved,
+            &enrollment.encoding_key,
+        )
+        .unwrap();
+        assert_eq!(
+            enrollment.authenticate(Some(&moved)),
+            Err(AuthError::Invalid)
+        );
+    }
+
+    #[test]
+    fn join_tokens_are_single_use_and_bound_to_their_node() {
+        let enrollment = enrollment();
+        let (token, _) = enrollment.issue_join_token(Some(NODE.to_string()), None);
+        assert_eq!(
+            enrollment
+                .enroll(&token, "https://node-b.example:8002", &public_key())
+                .unwrap_err(),
+            AuthError::WrongNode
+        );
+        enrollment.enroll(&token, NODE, &public_key()).unwrap();
+        assert_eq!(
+            enrollment.enroll(&token, NODE, &public_key()).unwrap_err(),
+            AuthError::Invalid
+        );
+
+        let (token, _) = enrollment.issue_join_token(None, Some(Duration::ZERO));
+        assert_eq!(
+            enrollment.enroll(&token, NODE, &public_key()).unwrap_err(),
+            AuthError::Invalid
+        );
+    }
+
+    #[test]
+    fn authorizes_only_the_admin_token() {
+        let enrollment = enrollment();
+        assert!(enrollment.authorize_admin(Some("admin-secret")).is_ok());
+        assert_eq!(enrollment.authorize_admin(None), Err(AuthError::Missing));
+        for wrong in ["", "admin-secre", "admin-secret ", "ADMIN-SECRET"] {
+            assert_eq!(
+                enrollment.authorize_admin(Some(wrong)),
+                Err(AuthError::Invalid)
+            );
+        }
+    }
+}
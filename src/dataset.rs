@@ -0,0 +1,443 @@
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// Labelled samples in a flat row-major layout, one row of features and labels per sample
+#[derive(Clone, Debug, Default)]
+pub struct Samples {
+    pub data: Vec<f32>,
+    pub labels: Vec<f32>,
+}
+
+impl Samples {
+    // Take the given rows, with `features` data values and `label_width` labels per row
+    fn select(&self, rows: &[usize], features: usize, label_width: usize) -> Samples {
+        let mut selected = Samples::default();
+        for &row in rows {
+            selected
+                .data
+                .extend_from_slice(&self.data[row * features..(row + 1) * features]);
+            selected
+                .labels
+                .extend_from_slice(&self.labels[row * label_width..(row + 1) * label_width]);
+        }
+        selected
+    }
+}
+
+// A node's local data, split once into a training part and a validation part
+// used for federated evaluation
+#[derive(Clone, Debug)]
+pub struct LocalDataset {
+    pub features: usize,
+    pub train: Samples,
+    pub validation: Samples,
+}
+
+impl LocalDataset {
+    // Hold out a shuffled `validation_fraction` of the samples
+    pub fn split(
+        samples: Samples,
+        features: usize,
+        validation_fraction: f32,
+        seed: u64,
+    ) -> Result<Self> {
+        if features == 0 || !samples.data.len().is_multiple_of(features) {
+            return Err(anyhow!(
+                "Data length {} is not a multiple of {} features",
+                samples.data.len(),
+                features
+            ));
+        }
+        let rows = samples.data.len() / features;
+        if rows == 0 {
+            return Err(anyhow!("Dataset has no samples"));
+        }
+        if !samples.labels.len().is_multiple_of(rows) {
+            return Err(anyhow!(
+                "{} labels do not divide evenly over {} samples",
+                samples.labels.len(),
+                rows
+            ));
+        }
+        let label_width = samples.labels.len() / rows;
+
+        let mut order: Vec<usize> = (0..rows).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(seed));
+        let held_out = ((rows as f32 * validation_fraction).round() as usize).min(rows);
+        let (validation_rows, train_rows) = order.split_at(held_out);
+
+        Ok(Self {
+            features,
+            train: samples.select(train_rows, features, label_width),
+            validation: samples.select(validation_rows, features, label_width),
+        })
+    }
+
+    // Scale features with statistics of the training split, applied to both splits
+    pub fn normalize(&mut self, normalization: Normalization) {
+        let features = self.features;
+        let rows = self.train.data.len() / features;
+        if normalization == Normalization::None || rows == 0 {
+            return;
+        }
+
+        let columns = |data: &[f32], c: usize| {
+            data.iter()
+                .skip(c)
+                .step_by(features)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        // Per-feature (shift, scale) so that x' = (x - shift) / scale
+        let transform: Vec<(f32, f32)> = (0..features)
+            .map(|c| {
+                let values = columns(&self.train.data, c);
+                let (shift, scale) = match normalization {
+                    Normalization::Standard => {
+                        let mean = values.iter().sum::<f32>() / rows as f32;
+                        let variance =
+                            values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / rows as f32;
+                        (mean, variance.sqrt())
+                    }
+                    Normalization::MinMax | Normalization::None => {
+                        let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+                        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                        (min, max - min)
+                    }
+                };
+                // Constant features are only shifted
+                (shift, if scale > 0.0 { scale } else { 1.0 })
+            })
+            .collect();
+
+        for data in [&mut self.train.data, &mut self.validation.data] {
+            for (i, value) in data.iter_mut().enumerate() {
+                let (shift, scale) = transform[i % features];
+                *value = (*value - shift) / scale;
+            }
+        }
+    }
+}
+
+// Column of a CSV file, by header name or zero-based index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    fn resolve(&self, headers: Option<&csv::StringRecord>) -> Result<usize> {
+        match self {
+            Column::Index(index) => Ok(*index),
+            Column::Name(name) => headers
+                .and_then(|headers| headers.iter().position(|h| h.trim() == name))
+                .ok_or_else(|| anyhow!("Column '{}' not found in the CSV header", name)),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(anyhow!("Empty column name"));
+        }
+        Ok(match s.parse() {
+            Ok(index) => Column::Index(index),
+            Err(_) => Column::Name(s.to_string()),
+        })
+    }
+}
+
+// Per-feature scaling fitted on the training split
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    #[default]
+    None,
+    // Zero mean and unit variance
+    Standard,
+    // Scale to [0, 1]
+    MinMax,
+}
+
+impl FromStr for Normalization {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Normalization::None),
+            "standard" | "zscore" => Ok(Normalization::Standard),
+            "minmax" | "min_max" => Ok(Normalization::MinMax),
+            other => Err(anyhow!("Unknown normalization: {}", other)),
+        }
+    }
+}
+
+// How to read a node's local CSV file
+#[derive(Clone, Debug)]
+pub struct CsvConfig {
+    pub path: PathBuf,
+    pub has_header: bool,
+    pub delimiter: u8,
+    // Feature columns in model input order; None takes every column except the label
+    pub features: Option<Vec<Column>>,
+    // Label column; None takes the last column
+    pub label: Option<Column>,
+    pub normalization: Normalization,
+    // Share of rows held out for validation
+    pub validation_fraction: f32,
+    // Seed of the train/validation shuffle, None to derive it from the node's seed
+    pub split_seed: Option<u64>,
+}
+
+// Read the selected columns of a CSV file, returning the samples and the number of features
+pub fn read_csv(config: &CsvConfig) -> Result<(Samples, usize)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(config.has_header)
+        .delimiter(config.delimiter)
+        .trim(csv::Trim::All)
+        .from_path(&config.path)
+        .map_err(|e| anyhow!("Failed to open {}: {}", config.path.display(), e))?;
+    let headers = if config.has_header {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    };
+
+    let mut samples = Samples::default();
+    let mut columns: Option<(Vec<usize>, usize)> = None;
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| anyhow!("Failed to read CSV record: {}", e))?;
+        // Rows are numbered as in the file, counting the header line
+        let line = i + 1 + config.has_header as usize;
+
+        // Resolve the columns against the first record's width
+        let (feature_columns, label_column) = match &columns {
+            Some(columns) => columns,
+            None => columns.insert(resolve_columns(config, headers.as_ref(), record.len())?),
+        };
+
+        for &column in feature_columns {
+            samples.data.push(parse_field(&record, column, line)?);
+        }
+        samples
+            .labels
+            .push(parse_field(&record, *label_column, line)?);
+    }
+
+    let features = columns.map(|(features, _)| features.len()).unwrap_or(0);
+    if samples.labels.is_empty() {
+        return Err(anyhow!("{} has no data rows", config.path.display()));
+    }
+    Ok((samples, features))
+}
+
+fn resolve_columns(
+    config: &CsvConfig,
+    headers: Option<&csv::StringRecord>,
+    width: usize,
+) -> Result<(Vec<usize>, usize)> {
+    let label = match &config.label {
+        Some(column) => column.resolve(headers)?,
+        None => width
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("CSV rows have no columns"))?,
+    };
+    let features = match &config.features {
+        Some(columns) => columns
+            .iter()
+            .map(|column| column.resolve(headers))
+            .collect::<Result<Vec<_>>>()?,
+        None => (0..width).filter(|&c| c != label).collect(),
+    };
+
+    if let Some(&column) = features.iter().chain([&label]).find(|&&c| c >= width) {
+        return Err(anyhow!(
+            "Column index {} is out of range for {} columns",
+            column,
+            width
+        ));
+    }
+    if features.is_empty() {
+        return Err(anyhow!("No feature columns selected"));
+    }
+    if features.contains(&label) {
+        return Err(anyhow!("The label column cannot also be a feature"));
+    }
+    Ok((features, label))
+}
+
+fn parse_field(record: &csv::StringRecord, column: usize, line: usize) -> Result<f32> {
+    let field = record
+        .get(column)
+        .ok_or_else(|| anyhow!("Line {} has no column {}", line, column))?;
+    let value: f32 = field.parse().map_err(|_| {
+        anyhow!(
+            "Line {}, column {}: '{}' is not a number",
+            line,
+            column,
+            field
+        )
+    })?;
+    if !value.is_finite() {
+        return Err(anyhow!(
+            "Line {}, column {}: value is not finite",
+            line,
+            column
+        ));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write `contents` to a fresh file and return a config reading it with a header
+    fn csv_file(name: &str, contents: &str) -> CsvConfig {
+        let path =
+            std::env::temp_dir().join(format!("dataset-{}-{}.csv", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        CsvConfig {
+            path,
+            has_header: true,
+            delimiter: b',',
+            features: None,
+            label: None,
+            normalization: Normalization::None,
+            validation_fraction: 0.2,
+            split_seed: None,
+        }
+    }
+
+    fn read(config: &CsvConfig) -> Result<(Samples, usize)> {
+        let result = read_csv(config);
+        std::fs::remove_file(&config.path).unwrap();
+        result
+    }
+
+    #[test]
+    fn reads_selected_columns() {
+        let mut config = csv_file("columns", "a,b,y,c\n1,2,0,3\n4,5,1,6\n");
+        config.features = Some(vec![Column::Name("c".into()), Column::Index(0)]);
+        config.label = Some(Column::Name("y".into()));
+        let (samples, features) = read(&config).unwrap();
+        assert_eq!(features, 2);
+        assert_eq!(samples.data, vec![3.0, 1.0, 6.0, 4.0]);
+        assert_eq!(samples.labels, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn rejects_ragged_rows() {
+        let config = csv_file("ragged", "a,b,y\n1,2,0\n3,4\n");
+        let error = read(&config).unwrap_err().to_string();
+        assert!(error.starts_with("Failed to read CSV record"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_non_numeric_cells() {
+        let config = csv_file("text", "a,b,y\n1,2,0\n3,four,1\n");
+        assert_eq!(
+            read(&config).unwrap_err().to_string(),
+            "Line 3, column 1: 'four' is not a number"
+        );
+        let config = csv_file("nan", "a,y\nNaN,0\n");
+        assert_eq!(
+            read(&config).unwrap_err().to_string(),
+            "Line 2, column 0: value is not finite"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_column_selection() {
+        let mut config = csv_file("missing", "a,y\n1,0\n");
+        config.label = Some(Column::Name("label".into()));
+        assert!(read(&config).is_err());
+
+        let mut config = csv_file("overlap", "a,y\n1,0\n");
+        config.features = Some(vec![Column::Index(0), Column::Index(1)]);
+        assert!(read(&config).is_err());
+
+        let config = csv_file("empty", "a,y\n");
+        assert!(read(&config).is_err());
+    }
+
+    #[test]
+    fn split_holds_out_the_validation_fraction() {
+        // Ten rows of two features and one label; label i belongs to row i
+        let samples = Samples {
+            data: (0..20).map(|v| v as f32).collect(),
+            labels: (0..10).map(|v| v as f32).collect(),
+        };
+        let dataset = LocalDataset::split(samples.clone(), 2, 0.3, 5).unwrap();
+        assert_eq!(dataset.train.labels.len(), 7);
+        assert_eq!(dataset.validation.labels.len(), 3);
+        assert_eq!(dataset.train.data.len(), 14);
+        assert_eq!(dataset.validation.data.len(), 6);
+
+        // Every row lands in exactly one split and keeps its features
+        let mut rows: Vec<f32> = Vec::new();
+        for split in [&dataset.train, &dataset.validation] {
+            for (row, label) in split.data.chunks(2).zip(&split.labels) {
+                assert_eq!(row, [label * 2.0, label * 2.0 + 1.0]);
+                rows.push(*label);
+            }
+        }
+        rows.sort_by(f32::total_cmp);
+        assert_eq!(rows, samples.labels);
+
+        // The seed fixes which rows are held out
+        let again = LocalDataset::split(samples.clone(), 2, 0.3, 5).unwrap();
+        assert_eq!(again.validation.labels, dataset.validation.labels);
+        let other = LocalDataset::split(samples.clone(), 2, 0.3, 6).unwrap();
+        assert_ne!(other.validation.labels, dataset.validation.labels);
+
+        assert!(LocalDataset::split(samples, 3, 0.3, 5).is_err());
+    }
+
+    fn normalized(normalization: Normalization) -> LocalDataset {
+        let mut dataset = LocalDataset {
+            features: 2,
+            train: Samples {
+                data: vec![1.0, 5.0, 3.0, 5.0, 5.0, 5.0],
+                labels: vec![0.0; 3],
+            },
+            validation: Samples {
+                data: vec![7.0, 6.0],
+                labels: vec![0.0],
+            },
+        };
+        dataset.normalize(normalization);
+        dataset
+    }
+
+    #[test]
+    fn standard_normalization_uses_training_statistics() {
+        // First feature: mean 3, std sqrt(8/3); the constant second feature is only shifted
+        let dataset = normalized(Normalization::Standard);
+        let std = (8.0f32 / 3.0).sqrt();
+        let expected = [-2.0 / std, 0.0, 0.0, 0.0, 2.0 / std, 0.0];
+        for (value, expected) in dataset.train.data.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+        assert!((dataset.validation.data[0] - 4.0 / std).abs() < 1e-6);
+        assert_eq!(dataset.validation.data[1], 1.0);
+    }
+
+    #[test]
+    fn minmax_normalization_uses_training_range() {
+        let dataset = normalized(Normalization::MinMax);
+        assert_eq!(dataset.train.data, vec![0.0, 0.0, 0.5, 0.0, 1.0, 0.0]);
+        // Validation values outside the training range are not clamped
+        assert_eq!(dataset.validation.data, vec![1.5, 1.0]);
+
+        assert_eq!(normalized(Normalization::None).train.data[0], 1.0);
+    }
+}
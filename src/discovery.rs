@@ -44,6 +44,7 @@ pub async fn register_node(
 }
 
 // Discover all nodes from etcd
+#[allow(dead_code)]
 pub async fn discover_nodes(etcd_endpoints: &[String]) -> Result<Vec<String>> {
     let mut client = Client::connect(etcd_endpoints, None).await?;
 
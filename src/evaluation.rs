@@ -0,0 +1,301 @@
+use crate::loss::Loss;
+use crate::model::{prepare_data, EvalMetrics, FederatedModel};
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+// Metrics a node reported for the global model on its held-out split
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeEvaluation {
+    pub node_addr: String,
+    pub metrics: EvalMetrics,
+}
+
+// Evaluation of the global model after a round
+#[derive(Clone, Debug, Serialize)]
+pub struct RoundEvaluation {
+    pub round: u64,
+    // Node reports averaged by sample count, once at least one node reported
+    pub federated: Option<EvalMetrics>,
+    pub nodes: Vec<NodeEvaluation>,
+    // Metrics on the server's holdout set, if one is loaded
+    pub central: Option<EvalMetrics>,
+}
+
+impl RoundEvaluation {
+    fn new(round: u64) -> Self {
+        Self {
+            round,
+            federated: None,
+            nodes: Vec::new(),
+            central: None,
+        }
+    }
+
+    // Add a node's report, replacing an earlier one from the same node. Reports are
+    // kept in node order so the averages do not depend on arrival order.
+    fn record(&mut self, node_addr: String, metrics: EvalMetrics) {
+        match self
+            .nodes
+            .binary_search_by(|e| e.node_addr.as_str().cmp(&node_addr))
+        {
+            Ok(index) => self.nodes[index].metrics = metrics,
+            Err(index) => self
+                .nodes
+                .insert(index, NodeEvaluation { node_addr, metrics }),
+        }
+
+        // Recompute the sample-weighted averages over all reports
+        let reports = || self.nodes.iter().map(|e| &e.metrics);
+        self.federated = Some(EvalMetrics {
+            loss: weighted_mean(reports().map(|m| (m.loss, m.num_samples))).unwrap_or(0.0),
+            accuracy: weighted_mean(
+                reports().filter_map(|m| m.accuracy.map(|a| (a, m.num_samples))),
+            ),
+            // RMSE is averaged over squared errors
+            rmse: weighted_mean(reports().filter_map(|m| m.rmse.map(|r| (r * r, m.num_samples))))
+                .map(f32::sqrt),
+            num_samples: reports().map(|m| m.num_samples).sum(),
+        });
+    }
+}
+
+// Mean of (value, weight) pairs, or None when there are none
+fn weighted_mean(values: impl Iterator<Item = (f32, usize)>) -> Option<f32> {
+    let (sum, weight, count) = values.fold((0.0, 0, 0), |(sum, weight, count), (v, n)| {
+        (sum + v * n as f32, weight + n, count + 1)
+    });
+    (count > 0).then(|| sum / weight.max(1) as f32)
+}
+
+// Per-round evaluation results of the federation, oldest first
+#[derive(Default)]
+pub struct EvaluationHistory {
+    rounds: Vec<RoundEvaluation>,
+}
+
+impl EvaluationHistory {
+    // Store a node's evaluation for a round and return the updated round summary
+    pub fn record(
+        &mut self,
+        round: u64,
+        node_addr: String,
+        metrics: EvalMetrics,
+    ) -> &RoundEvaluation {
+        let entry = self.entry(round);
+        entry.record(node_addr, metrics);
+        entry
+    }
+
+    // Store the server-side holdout evaluation for a round
+    pub fn record_central(&mut self, round: u64, metrics: EvalMetrics) {
+        self.entry(round).central = Some(metrics);
+    }
+
+    pub fn rounds(&self) -> &[RoundEvaluation] {
+        &self.rounds
+    }
+
+    fn entry(&mut self, round: u64) -> &mut RoundEvaluation {
+        let index = match self.rounds.binary_search_by_key(&round, |r| r.round) {
+            Ok(index) => index,
+            Err(index) => {
+                self.rounds.insert(index, RoundEvaluation::new(round));
+                index
+            }
+        };
+        &mut self.rounds[index]
+    }
+}
+
+// Labelled dataset in the same flat layout as `Samples`
+#[derive(Deserialize)]
+struct Dataset {
+    data: Vec<f32>,
+    labels: Vec<f32>,
+}
+
+// Load a JSON holdout set `{ "data": [...], "labels": [...] }` shaped for `model`
+pub fn load_holdout_file(
+    path: &Path,
+    model: &dyn FederatedModel,
+) -> Result<(Array2<f32>, Array2<f32>)> {
+    let bytes =
+        std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let dataset: Dataset = serde_json::from_slice(&bytes)
+        .map_err(|e| anyhow!("Invalid holdout set {}: {}", path.display(), e))?;
+    let (x, y) = prepare_data(&dataset.data, &dataset.labels, model)?;
+    if x.nrows() == 0 {
+        return Err(anyhow!("Holdout set {} is empty", path.display()));
+    }
+    Ok((x, y))
+}
+
+// Central metric watched for early stopping
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopMetric {
+    Loss,
+    Accuracy,
+    Rmse,
+}
+
+impl StopMetric {
+    // Value of the metric, None if the model does not report it
+    fn value(&self, metrics: &EvalMetrics) -> Option<f32> {
+        match self {
+            StopMetric::Loss => Some(metrics.loss),
+            StopMetric::Accuracy => metrics.accuracy,
+            StopMetric::Rmse => metrics.rmse,
+        }
+    }
+
+    // Whether models trained with `loss` report the metric
+    fn reported_for(&self, loss: Loss) -> bool {
+        match self {
+            StopMetric::Loss => true,
+            StopMetric::Accuracy => loss.is_classification(),
+            StopMetric::Rmse => !loss.is_classification(),
+        }
+    }
+
+    // Whether larger values are better
+    fn maximize(&self) -> bool {
+        matches!(self, StopMetric::Accuracy)
+    }
+}
+
+impl fmt::Display for StopMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            StopMetric::Loss => "loss",
+            StopMetric::Accuracy => "accuracy",
+            StopMetric::Rmse => "rmse",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for StopMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "loss" => Ok(StopMetric::Loss),
+            "accuracy" => Ok(StopMetric::Accuracy),
+            "rmse" => Ok(StopMetric::Rmse),
+            other => Err(anyhow!("Unknown early stopping metric: {}", other)),
+        }
+    }
+}
+
+// Stops training once the central metric has not improved for `patience` rounds
+#[derive(Clone, Debug)]
+pub struct EarlyStopping {
+    pub metric: StopMetric,
+    pub patience: u64,
+    // Smallest change that counts as an improvement
+    pub min_delta: f32,
+    best: Option<f32>,
+    rounds_without_improvement: u64,
+}
+
+impl EarlyStopping {
+    pub fn new(metric: StopMetric, patience: u64, min_delta: f32) -> Self {
+        Self {
+            metric,
+            patience,
+            min_delta,
+            best: None,
+            rounds_without_improvement: 0,
+        }
+    }
+
+    // Refuse a metric the model does not report, which would never stop training
+    pub fn validate(&self, loss: Loss) -> Result<()> {
+        if !self.metric.reported_for(loss) {
+            return Err(anyhow!(
+                "Early stopping on {} needs a model that reports it, but loss {} does not",
+                self.metric,
+                loss
+            ));
+        }
+        Ok(())
+    }
+
+    // Record a round's central metrics and tell whether training should stop
+    pub fn should_stop(&mut self, metrics: &EvalMetrics) -> bool {
+        let Some(value) = self.metric.value(metrics) else {
+            return false;
+        };
+
+        let improved = match self.best {
+            None => true,
+            Some(best) if self.metric.maximize() => value > best + self.min_delta,
+            Some(best) => value < best - self.min_delta,
+        };
+        if improved {
+            self.best = Some(value);
+            self.rounds_without_improvement = 0;
+        } else {
+            self.rounds_without_improvement += 1;
+        }
+        self.rounds_without_improvement >= self.patience
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(loss: f32, accuracy: Option<f32>) -> EvalMetrics {
+        EvalMetrics {
+            loss,
+            accuracy,
+            rmse: None,
+            num_samples: 10,
+        }
+    }
+
+    #[test]
+    fn early_stopping_waits_for_patience_rounds_without_improvement() {
+        let mut early_stopping = EarlyStopping::new(StopMetric::Loss, 2, 0.0);
+        assert!(!early_stopping.should_stop(&metrics(1.0, None)));
+        assert!(!early_stopping.should_stop(&metrics(1.0, None)));
+        // An improvement resets the count
+        assert!(!early_stopping.should_stop(&metrics(0.9, None)));
+        assert!(!early_stopping.should_stop(&metrics(0.95, None)));
+        assert!(early_stopping.should_stop(&metrics(0.95, None)));
+    }
+
+    #[test]
+    fn early_stopping_ignores_changes_below_min_delta() {
+        let mut early_stopping = EarlyStopping::new(StopMetric::Accuracy, 1, 0.05);
+        assert!(!early_stopping.should_stop(&metrics(1.0, Some(0.5))));
+        assert!(!early_stopping.should_stop(&metrics(1.0, Some(0.6))));
+        assert!(early_stopping.should_stop(&metrics(1.0, Some(0.63))));
+
+        // A missing metric never counts as a round without improvement
+        let mut early_stopping = EarlyStopping::new(StopMetric::Rmse, 1, 0.0);
+        assert!(!early_stopping.should_stop(&metrics(1.0, Some(0.5))));
+        assert!(!early_stopping.should_stop(&metrics(1.0, Some(0.5))));
+    }
+
+    #[test]
+    fn early_stopping_needs_a_reported_metric() {
+        assert!(EarlyStopping::new(StopMetric::Accuracy, 1, 0.0)
+            .validate(Loss::CategoricalCrossEntropy)
+            .is_ok());
+        assert!(EarlyStopping::new(StopMetric::Accuracy, 1, 0.0)
+            .validate(Loss::Mse)
+            .is_err());
+        assert!(EarlyStopping::new(StopMetric::Rmse, 1, 0.0)
+            .validate(Loss::Mse)
+            .is_ok());
+        assert!(EarlyStopping::new(StopMetric::Loss, 1, 0.0)
+            .validate(Loss::BinaryCrossEntropy)
+            .is_ok());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::model::FederatedModel;
+use anyhow::{anyhow, Result};
+use safetensors::tensor::{Dtype, TensorView};
+use safetensors::SafeTensors;
+use std::path::Path;
+
+// Serialize model tensors into the safetensors format, named after the parameter schema
+pub fn to_safetensors(model: &dyn FederatedModel) -> Result<Vec<u8>> {
+    let params = model.get_params();
+    let schema = model.param_schema();
+
+    // Little-endian f32 bytes for each tensor, in schema order
+    let mut buffers = Vec::with_capacity(schema.len());
+    let mut offset = 0;
+    for spec in &schema {
+        let size = spec.num_values();
+        let bytes: Vec<u8> = params[offset..offset + size]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        buffers.push(bytes);
+        offset += size;
+    }
+
+    let mut views = Vec::with_capacity(schema.len());
+    for (spec, bytes) in schema.iter().zip(&buffers) {
+        let view = TensorView::new(Dtype::F32, spec.shape.clone(), bytes)
+            .map_err(|e| anyhow!("Failed to build tensor {}: {:?}", spec.name, e))?;
+        views.push((spec.name.clone(), view));
+    }
+
+    safetensors::serialize(views, &None)
+        .map_err(|e| anyhow!("Failed to serialize safetensors: {:?}", e))
+}
+
+// Load model tensors from safetensors bytes, checking names, dtypes and shapes
+pub fn load_safetensors(model: &mut dyn FederatedModel, bytes: &[u8]) -> Result<()> {
+    let tensors = SafeTensors::deserialize(bytes)
+        .map_err(|e| anyhow!("Failed to parse safetensors: {:?}", e))?;
+
+    let mut params = Vec::new();
+    for spec in model.param_schema() {
+        let tensor = tensors
+            .tensor(&spec.name)
+            .map_err(|_| anyhow!("Missing tensor {}", spec.name))?;
+
+        if tensor.dtype() != Dtype::F32 {
+            return Err(anyhow!(
+                "Tensor {} has dtype {:?}, expected F32",
+                spec.name,
+                tensor.dtype()
+            ));
+        }
+        if tensor.shape() != spec.shape.as_slice() {
+            return Err(anyhow!(
+                "Tensor {} has shape {:?}, expected {:?}",
+                spec.name,
+                tensor.shape(),
+                spec.shape
+            ));
+        }
+
+        params.extend(
+            tensor
+                .data()
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+    }
+
+    model.set_params(&params)
+}
+
+// Load model tensors from a safetensors file on disk
+pub fn load_safetensors_file(model: &mut dyn FederatedModel, path: &Path) -> Result<()> {
+    let bytes =
+        std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    load_safetensors(model, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::ModelSpec;
+    use crate::loss::Loss;
+    use crate::model::build_model;
+
+    #[test]
+    fn safetensors_round_trip_is_exact() {
+        let spec = ModelSpec::parse(3, "4:relu,2:linear", Loss::Mse).unwrap();
+        let mut model = build_model(&spec, 7);
+        // Biases start at zero, so give every parameter a distinct value, including a subnormal
+        let mut params = model.get_params();
+        for (i, p) in params.iter_mut().enumerate() {
+            *p += i as f32 * 0.1;
+        }
+        params[0] = f32::from_bits(1);
+        model.set_params(&params).unwrap();
+        let bytes = to_safetensors(model.as_ref()).unwrap();
+
+        // Header: little-endian u64 length, then JSON with dtype, shape and data offsets per tensor
+        let header_len = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+        let header: serde_json::Value = serde_json::from_slice(&bytes[8..8 + header_len]).unwrap();
+        let data = &bytes[8 + header_len..];
+        let expected = [
+            ("w1", vec![3, 4]),
+            ("b1", vec![4]),
+            ("w2", vec![4, 2]),
+            ("b2", vec![2]),
+        ];
+        assert_eq!(
+            header
+                .as_object()
+                .unwrap()
+                .keys()
+                .filter(|k| *k != "__metadata__")
+                .count(),
+            expected.len()
+        );
+        let mut offset = 0;
+        for (name, shape) in expected {
+            let tensor = &header[name];
+            assert_eq!(tensor["dtype"], "F32", "{}", name);
+            assert_eq!(tensor["shape"], serde_json::json!(shape), "{}", name);
+            let [start, end] = [0, 1].map(|i| tensor["data_offsets"][i].as_u64().unwrap() as usize);
+            let values: Vec<u32> = data[start..end]
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            let size: usize = shape.iter().product();
+            let original: Vec<u32> = params[offset..offset + size]
+                .iter()
+                .map(|v| v.to_bits())
+                .collect();
+            assert_eq!(values, original, "{}", name);
+            offset += size;
+        }
+        assert_eq!(offset, params.len());
+
+        // Loading into a differently initialized model gives back the same bits
+        let mut loaded = build_model(&spec, 8);
+        load_safetensors(loaded.as_mut(), &bytes).unwrap();
+        let bits = |p: Vec<f32>| p.into_iter().map(f32::to_bits).collect::<Vec<_>>();
+        assert_eq!(bits(loaded.get_params()), bits(params));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::loss::Loss;
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Slope of leaky ReLU for negative inputs
+pub const LEAKY_RELU_ALPHA: f32 = 0.01;
+
+// Constants of the tanh approximation of GELU, GELU_K being sqrt(2 / pi)
+pub const GELU_K: f32 = 0.797_884_6;
+pub const GELU_C: f32 = 0.044_715;
+
+// Activation applied after a dense layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    Linear,
+    Relu,
+    LeakyRelu,
+    Tanh,
+    Sigmoid,
+    Gelu,
+}
+
+impl Activation {
+    // Apply the activation element-wise
+    pub fn apply(&self, z: &Array2<f32>) -> Array2<f32> {
+        match self {
+            Activation::Linear => z.clone(),
+            Activation::Relu => z.mapv(|v| if v > 0.0 { v } else { 0.0 }),
+            Activation::LeakyRelu => z.mapv(|v| if v > 0.0 { v } else { LEAKY_RELU_ALPHA * v }),
+            Activation::Tanh => z.mapv(f32::tanh),
+            Activation::Sigmoid => z.mapv(sigmoid),
+            Activation::Gelu => z.mapv(|v| 0.5 * v * (1.0 + gelu_inner(v).tanh())),
+        }
+    }
+
+    // Derivative of the activation with respect to its input, evaluated at z
+    pub fn derivative(&self, z: &Array2<f32>) -> Array2<f32> {
+        match self {
+            Activation::Linear => Array2::ones(z.raw_dim()),
+            Activation::Relu => z.mapv(|v| if v > 0.0 { 1.0 } else { 0.0 }),
+            Activation::LeakyRelu => z.mapv(|v| if v > 0.0 { 1.0 } else { LEAKY_RELU_ALPHA }),
+            Activation::Tanh => z.mapv(|v| 1.0 - v.tanh().powi(2)),
+            Activation::Sigmoid => z.mapv(|v| {
+                let s = sigmoid(v);
+                s * (1.0 - s)
+            }),
+            Activation::Gelu => z.mapv(|v| {
+                let t = gelu_inner(v).tanh();
+                0.5 * (1.0 + t) + 0.5 * v * (1.0 - t * t) * GELU_K * (1.0 + 3.0 * GELU_C * v * v)
+            }),
+        }
+    }
+
+    // Bound of the uniform initializer: He for ReLU-like activations, Xavier otherwise
+    pub fn init_bound(&self, fan_in: usize, fan_out: usize) -> f32 {
+        match self {
+            Activation::Relu | Activation::LeakyRelu | Activation::Gelu => {
+                (6.0 / fan_in as f32).sqrt()
+            }
+            Activation::Linear | Activation::Tanh | Activation::Sigmoid => {
+                (6.0 / (fan_in + fan_out) as f32).sqrt()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Activation::Linear => "linear",
+            Activation::Relu => "relu",
+            Activation::LeakyRelu => "leaky_relu",
+            Activation::Tanh => "tanh",
+            Activation::Sigmoid => "sigmoid",
+            Activation::Gelu => "gelu",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Activation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "linear" | "none" => Ok(Activation::Linear),
+            "relu" => Ok(Activation::Relu),
+            "leaky_relu" | "leakyrelu" => Ok(Activation::LeakyRelu),
+            "tanh" => Ok(Activation::Tanh),
+            "sigmoid" => Ok(Activation::Sigmoid),
+            "gelu" => Ok(Activation::Gelu),
+            other => Err(anyhow!("Unknown activation: {}", other)),
+        }
+    }
+}
+
+fn sigmoid(v: f32) -> f32 {
+    1.0 / (1.0 + (-v).exp())
+}
+
+fn gelu_inner(v: f32) -> f32 {
+    GELU_K * (v + GELU_C * v * v * v)
+}
+
+// A dense layer with its output width and activation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerSpec {
+    pub units: usize,
+    pub activation: Activation,
+}
+
+// Architecture of a sequential model, shared by the server with every node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelSpec {
+    pub input_size: usize,
+    pub layers: Vec<LayerSpec>,
+    #[serde(default)]
+    pub loss: Loss,
+}
+
+impl ModelSpec {
+    // Parse a layer list such as "64:relu,32:tanh,1:linear"
+    pub fn parse(input_size: usize, layers: &str, loss: Loss) -> Result<Self> {
+        let layers = layers
+            .split(',')
+            .filter(|layer| !layer.trim().is_empty())
+            .map(|layer| {
+                let (units, activation) = layer.split_once(':').unwrap_or((layer, "linear"));
+                let units = units
+                    .trim()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid layer width '{}': {}", units, e))?;
+                Ok(LayerSpec {
+                    units,
+                    activation: activation.parse()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let spec = Self {
+            input_size,
+            layers,
+            loss,
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    // Check that the architecture can be built
+    pub fn validate(&self) -> Result<()> {
+        if self.input_size == 0 {
+            return Err(anyhow!("Model input size must be positive"));
+        }
+        if self.layers.is_empty() {
+            return Err(anyhow!("Model needs at least one layer"));
+        }
+        if let Some(i) = self.layers.iter().position(|layer| layer.units == 0) {
+            return Err(anyhow!("Layer {} has zero units", i + 1));
+        }
+
+        // Cross-entropy losses apply sigmoid/softmax to the logits themselves
+        let last = &self.layers[self.layers.len() - 1];
+        if self.loss.is_classification() && last.activation != Activation::Linear {
+            return Err(anyhow!(
+                "Loss {} needs a linear output layer, got {}",
+                self.loss,
+                last.activation
+            ));
+        }
+        if self.loss == Loss::CategoricalCrossEntropy && last.units < 2 {
+            return Err(anyhow!(
+                "Loss {} needs at least two output classes",
+                self.loss
+            ));
+        }
+        Ok(())
+    }
+
+    // Width of the last layer
+    pub fn output_size(&self) -> usize {
+        self.layers.last().map(|layer| layer.units).unwrap_or(0)
+    }
+}
+
+impl fmt::Display for ModelSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.input_size)?;
+        for layer in &self.layers {
+            write!(f, " -> {} ({})", layer.units, layer.activation)?;
+        }
+        write!(f, ", loss {}", self.loss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn derivatives_match_finite_differences() {
+        // Points away from the kinks of ReLU and leaky ReLU at zero
+        let z = array![[-2.5, -0.7, -0.1, 0.2, 0.9, 3.0]];
+        let h = 1e-3;
+        for activation in [
+            Activation::Linear,
+            Activation::Relu,
+            Activation::LeakyRelu,
+            Activation::Tanh,
+            Activation::Sigmoid,
+            Activation::Gelu,
+        ] {
+            let numeric = (activation.apply(&(&z + h)) - activation.apply(&(&z - h))) / (2.0 * h);
+            for (numeric, analytic) in numeric.iter().zip(activation.derivative(&z).iter()) {
+                assert!(
+                    (numeric - analytic).abs() < 1e-3,
+                    "{}: numeric {} vs analytic {}",
+                    activation,
+                    numeric,
+                    analytic
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gelu_matches_reference_values() {
+        // Tanh approximation of GELU at -1, 0, 1 and 2
+        let gelu = Activation::Gelu.apply(&array![[-1.0, 0.0, 1.0, 2.0]]);
+        for (value, expected) in gelu.iter().zip([-0.158_808, 0.0, 0.841_192, 1.954_597_7]) {
+            assert!((value - expected).abs() < 1e-5, "{} vs {}", value, expected);
+        }
+    }
+
+    #[test]
+    fn parses_layer_lists() {
+        let spec = ModelSpec::parse(4, " 8:gelu, 3:leaky_relu ,2", Loss::Mse).unwrap();
+        assert_eq!(
+            spec.layers,
+            vec![
+                LayerSpec {
+                    units: 8,
+                    activation: Activation::Gelu
+                },
+                LayerSpec {
+                    units: 3,
+                    activation: Activation::LeakyRelu
+                },
+                LayerSpec {
+                    units: 2,
+                    activation: Activation::Linear
+                },
+            ]
+        );
+        assert_eq!(spec.output_size(), 2);
+        assert_eq!(
+            spec.to_string(),
+            "4 -> 8 (gelu) -> 3 (leaky_relu) -> 2 (linear), loss mse"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        let error = |input_size, layers, loss| {
+            ModelSpec::parse(input_size, layers, loss)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error(4, "x:relu", Loss::Mse).starts_with("Invalid layer width 'x'"));
+        assert_eq!(error(4, "8:swish", Loss::Mse), "Unknown activation: swish");
+        assert_eq!(
+            error(4, "8:relu,0:linear", Loss::Mse),
+            "Layer 2 has zero units"
+        );
+        assert_eq!(error(4, " , ", Loss::Mse), "Model needs at least one layer");
+        assert_eq!(
+            error(0, "1:linear", Loss::Mse),
+            "Model input size must be positive"
+        );
+        assert_eq!(
+            error(4, "1:sigmoid", Loss::BinaryCrossEntropy),
+            "Loss binary_cross_entropy needs a linear output layer, got sigmoid"
+        );
+        assert_eq!(
+            error(4, "1:linear", Loss::CategoricalCrossEntropy),
+            "Loss categorical_cross_entropy needs at least two output classes"
+        );
+
+        // Specs received from elsewhere are checked the same way
+        let mut spec = ModelSpec::parse(4, "3:relu,1:linear", Loss::Mse).unwrap();
+        spec.layers[0].units = 0;
+        assert!(spec.validate().is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::messages::NodeMessage;
+use crate::secagg::SecAggMessage;
+use actix_web::error::JsonPayloadError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Buckets kept before idle (full) ones are dropped, so many peers cannot grow the map without bound
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+// Limits on the messages nodes send to the server
+#[derive(Clone, Debug)]
+pub struct LimitsConfig {
+    // Largest accepted JSON body of a message, in bytes
+    pub max_payload_bytes: usize,
+    // Updates a node may send per minute, 0 for no limit
+    pub updates_per_minute: u32,
+    // Registrations a node may send per minute, 0 for no limit
+    pub registrations_per_minute: u32,
+    // Other messages (evaluations, secure aggregation steps) a node may send per minute, 0 for no limit
+    pub messages_per_minute: u32,
+}
+
+impl LimitsConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_payload_bytes == 0 {
+            return Err(anyhow!("The maximum payload size must be positive"));
+        }
+        Ok(())
+    }
+}
+
+// Kind of message a rate limit applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Limited {
+    Update,
+    Registration,
+    Other,
+}
+
+impl Limited {
+    fn of(msg: &NodeMessage) -> Self {
+        match msg {
+            NodeMessage::RegisterNode { .. } => Limited::Registration,
+            NodeMessage::SubmitUpdate { .. } | NodeMessage::UpdateModel { .. } => Limited::Update,
+            NodeMessage::SecAgg(msg) if matches!(**msg, SecAggMessage::MaskedInput { .. }) => {
+                Limited::Update
+            }
+            _ => Limited::Other,
+        }
+    }
+}
+
+// Token bucket refilled at a steady rate up to one minute's allowance
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // Tokens the bucket holds at `now`
+    fn refill(&self, now: Instant, per_minute: f64) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * per_minute / 60.0).min(per_minute)
+    }
+}
+
+// Rate limits per node and the count of messages refused before they reach the server actor
+pub struct RequestLimiter {
+    config: LimitsConfig,
+    buckets: Mutex<HashMap<(String, Limited), Bucket>>,
+    rejections: Mutex<HashMap<String, u64>>,
+}
+
+impl RequestLimiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            rejections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // JSON extractor settings enforcing the payload limit with a 413 response
+    pub fn json_config(&self) -> web::JsonConfig {
+        web::JsonConfig::default()
+            .limit(self.config.max_payload_bytes)
+            .error_handler(payload_error)
+    }
+
+    // Take a token for the message from its sender's bucket, or return how long to wait for one.
+    // The sender names itself, so unless it was `authenticated` the message is counted
+    // against the peer's IP address instead; otherwise a new name would get a new bucket.
+    pub fn check(
+        &self,
+        msg: &NodeMessage,
+        peer: &str,
+        authenticated: bool,
+    ) -> Result<(), Duration> {
+        let key = match msg.sender() {
+            Some(sender) if authenticated => sender,
+            _ => peer,
+        };
+        self.take(Limited::of(msg), key, Instant::now())
+    }
+
+    fn take(&self, kind: Limited, key: &str, now: Instant) -> Result<(), Duration> {
+        let capacity = self.per_minute(kind);
+        if capacity == 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            buckets.retain(|(_, kind), bucket| {
+                bucket.refill(now, self.per_minute(*kind)) < self.per_minute(*kind)
+            });
+        }
+        let bucket = buckets.entry((key.to_string(), kind)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = bucket.refill(now, capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * 60.0 / capacity,
+            ))
+        }
+    }
+
+    fn per_minute(&self, kind: Limited) -> f64 {
+        match kind {
+            Limited::Update => self.config.updates_per_minute as f64,
+            Limited::Registration => self.config.registrations_per_minute as f64,
+            Limited::Other => self.config.messages_per_minute as f64,
+        }
+    }
+
+    pub fn record_rejection(&self, reason: &str) {
+        let mut rejections = self.rejections.lock().unwrap_or_else(|e| e.into_inner());
+        *rejections.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    // Refused messages keyed by reason
+    pub fn rejections(&self) -> HashMap<String, u64> {
+        self.rejections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+// Answer oversized bodies with 413 and count them, other malformed bodies with 400
+fn payload_error(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let response = match &err {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            warn!("Refused oversized message to {}: {}", req.path(), err);
+            if let Some(limiter) = req.app_data::<web::Data<RequestLimiter>>() {
+                limiter.record_rejection("payload_too_large");
+            }
+            HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "status": "error",
+                "message": err.to_string()
+            }))
+        }
+        _ => HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": err.to_string()
+        })),
+    };
+    actix_web::error::InternalError::from_response(err, response).into()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::ModelSpec;
+    use crate::loss::Loss;
+    use crate::network::receive_server_message;
+    use crate::server::{CentralServer, ServerConfig};
+    use actix::Actor;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    fn limiter(per_minute: u32) -> RequestLimiter {
+        RequestLimiter::new(LimitsConfig {
+            max_payload_bytes: 1024,
+            updates_per_minute: per_minute,
+            registrations_per_minute: per_minute,
+            messages_per_minute: per_minute,
+        })
+    }
+
+    fn register(addr: &str) -> NodeMessage {
+        NodeMessage::RegisterNode {
+            addr: addr.to_string(),
+        }
+    }
+
+    #[test]
+    fn buckets_refill_at_the_configured_rate() {
+        let limiter = limiter(60);
+        let start = Instant::now();
+        // A full minute's allowance can be spent at once
+        for _ in 0..60 {
+            assert!(limiter.take(Limited::Update, "node", start).is_ok());
+        }
+        let wait = limiter.take(Limited::Update, "node", start).unwrap_err();
+        assert!((wait.as_secs_f64() - 1.0).abs() < 1e-9);
+
+        // One token per second at 60 per minute
+        let half = start + Duration::from_millis(500);
+        assert!(limiter.take(Limited::Update, "node", half).is_err());
+        let later = start + Duration::from_millis(1500);
+        assert!(limiter.take(Limited::Update, "node", later).is_ok());
+        assert!(limiter.take(Limited::Update, "node", later).is_err());
+
+        // Other kinds and keys have their own buckets
+        assert!(limiter.take(Limited::Other, "node", later).is_ok());
+        assert!(limiter.take(Limited::Update, "other", later).is_ok());
+    }
+
+    #[test]
+    fn unauthenticated_senders_share_the_peer_bucket() {
+        let limiter = limiter(1);
+        assert!(limiter
+            .check(&register("http://a"), "10.0.0.1", false)
+            .is_ok());
+        // A made-up name does not get a fresh bucket
+        assert!(limiter
+            .check(&register("http://b"), "10.0.0.1", false)
+            .is_err());
+        assert!(limiter
+            .check(&register("http://c"), "10.0.0.2", false)
+            .is_ok());
+
+        // Authenticated nodes are counted by name, even behind one address
+        assert!(limiter
+            .check(&register("http://a"), "10.0.0.3", true)
+            .is_ok());
+        assert!(limiter
+            .check(&register("http://b"), "10.0.0.3", true)
+            .is_ok());
+        assert!(limiter
+            .check(&register("http://b"), "10.0.0.3", true)
+            .is_err());
+    }
+
+    #[test]
+    fn full_buckets_are_evicted_at_the_cap() {
+        let limiter = limiter(60);
+        let start = Instant::now();
+        for i in 0..MAX_TRACKED_BUCKETS {
+            limiter
+                .take(Limited::Other, &format!("peer-{}", i), start)
+                .unwrap();
+        }
+        // One peer keeps draining its bucket, the others refill over the minute
+        let later = start + Duration::from_secs(60);
+        for _ in 0..60 {
+            limiter.take(Limited::Other, "peer-0", later).unwrap();
+        }
+        limiter.take(Limited::Other, "newcomer", later).unwrap();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert!(buckets.contains_key(&("peer-0".to_string(), Limited::Other)));
+        assert!(buckets.contains_key(&("newcomer".to_string(), Limited::Other)));
+    }
+
+    #[actix_rt::test]
+    async fn rate_limited_messages_get_429_with_retry_after() {
+        let spec = ModelSpec::parse(2, "1:linear", Loss::Mse).unwrap();
+        let server = CentralServer::new(ServerConfig::default(), &spec).start();
+        let limiter = web::Data::new(limiter(1));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(server))
+                .app_data(limiter.clone())
+                .route("/message", web::post().to(receive_server_message)),
+        )
+        .await;
+        let request = |addr: &str| {
+            TestRequest::post()
+                .uri("/message")
+                .peer_addr("10.0.0.1:4000".parse().unwrap())
+                .set_json(register(addr))
+                .to_request()
+        };
+
+        let response = call_service(&app, request("http://a")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call_service(&app, request("http://b")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "60");
+        assert_eq!(limiter.rejections()["rate_limited"], 1);
+    }
+}
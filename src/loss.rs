@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Result};
+use ndarray::{Array2, Axis};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Probabilities are clamped away from 0 and 1 before taking logarithms
+const EPSILON: f32 = 1e-7;
+
+// Training objective, including the output transform it pairs with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Loss {
+    // Mean squared error on the raw network output (regression)
+    #[default]
+    Mse,
+    // Sigmoid on the logits with binary cross-entropy (binary or multi-label)
+    BinaryCrossEntropy,
+    // Softmax on the logits with categorical cross-entropy (multi-class)
+    CategoricalCrossEntropy,
+}
+
+impl Loss {
+    // Turn the last layer's output into predictions
+    pub fn output(&self, z: &Array2<f32>) -> Array2<f32> {
+        match self {
+            Loss::Mse => z.clone(),
+            Loss::BinaryCrossEntropy => z.mapv(|v| 1.0 / (1.0 + (-v).exp())),
+            Loss::CategoricalCrossEntropy => softmax(z),
+        }
+    }
+
+    // Mean loss of predictions produced by `output` against targets
+    pub fn loss(&self, predictions: &Array2<f32>, targets: &Array2<f32>) -> f32 {
+        let n = predictions.nrows().max(1) as f32;
+        match self {
+            Loss::Mse => (predictions - targets)
+                .mapv(|v| v * v)
+                .mean()
+                .unwrap_or(0.0),
+            Loss::BinaryCrossEntropy => {
+                let total: f32 = predictions
+                    .iter()
+                    .zip(targets.iter())
+                    .map(|(&p, &y)| {
+                        let p = p.clamp(EPSILON, 1.0 - EPSILON);
+                        -(y * p.ln() + (1.0 - y) * (1.0 - p).ln())
+                    })
+                    .sum();
+                total / predictions.len().max(1) as f32
+            }
+            Loss::CategoricalCrossEntropy => {
+                let total: f32 = predictions
+                    .iter()
+                    .zip(targets.iter())
+                    .map(|(&p, &y)| -y * p.clamp(EPSILON, 1.0).ln())
+                    .sum();
+                total / n
+            }
+        }
+    }
+
+    // Gradient of `loss` with respect to the last layer's output (before `output` for
+    // the cross-entropy losses, where sigmoid/softmax fold into a simple difference),
+    // averaged over the same values as the loss
+    pub fn gradient(&self, predictions: &Array2<f32>, targets: &Array2<f32>) -> Array2<f32> {
+        let values = predictions.len().max(1) as f32;
+        let diff = predictions - targets;
+        match self {
+            Loss::Mse => diff * (2.0 / values),
+            Loss::BinaryCrossEntropy => diff / values,
+            Loss::CategoricalCrossEntropy => diff / predictions.nrows().max(1) as f32,
+        }
+    }
+
+    // Share of correct predictions, or None for regression
+    pub fn accuracy(&self, predictions: &Array2<f32>, targets: &Array2<f32>) -> Option<f32> {
+        match self {
+            Loss::Mse => None,
+            // Each output is its own yes/no decision at a 0.5 threshold
+            Loss::BinaryCrossEntropy => {
+                let hits = predictions
+                    .iter()
+                    .zip(targets.iter())
+                    .filter(|(&p, &y)| (p >= 0.5) == (y >= 0.5))
+                    .count();
+                Some(hits as f32 / predictions.len().max(1) as f32)
+            }
+            // The most likely class must match the labelled class
+            Loss::CategoricalCrossEntropy => {
+                let hits = predictions
+                    .axis_iter(Axis(0))
+                    .zip(targets.axis_iter(Axis(0)))
+                    .filter(|(p, y)| argmax(p.iter()) == argmax(y.iter()))
+                    .count();
+                Some(hits as f32 / predictions.nrows().max(1) as f32)
+            }
+        }
+    }
+
+    // Root mean squared error for regression, or None for classification
+    pub fn rmse(&self, predictions: &Array2<f32>, targets: &Array2<f32>) -> Option<f32> {
+        match self {
+            Loss::Mse => Some(self.loss(predictions, targets).sqrt()),
+            Loss::BinaryCrossEntropy | Loss::CategoricalCrossEntropy => None,
+        }
+    }
+
+    // Whether targets are class labels rather than real values
+    pub fn is_classification(&self) -> bool {
+        !matches!(self, Loss::Mse)
+    }
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Loss::Mse => "mse",
+            Loss::BinaryCrossEntropy => "binary_cross_entropy",
+            Loss::CategoricalCrossEntropy => "categorical_cross_entropy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Loss {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "mse" => Ok(Loss::Mse),
+            "bce" | "binary_cross_entropy" => Ok(Loss::BinaryCrossEntropy),
+            "cce" | "categorical_cross_entropy" | "cross_entropy" => {
+                Ok(Loss::CategoricalCrossEntropy)
+            }
+            other => Err(anyhow!("Unknown loss: {}", other)),
+        }
+    }
+}
+
+// Row-wise softmax, shifted by the row maximum for numerical stability
+fn softmax(z: &Array2<f32>) -> Array2<f32> {
+    let mut out = z.clone();
+    for mut row in out.axis_iter_mut(Axis(0)) {
+        let max = row.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        row.mapv_inplace(|v| (v - max).exp());
+        let sum = row.sum();
+        row.mapv_inplace(|v| v / sum);
+    }
+    out
+}
+
+// Index of the largest value
+pub fn argmax<'a>(values: impl Iterator<Item = &'a f32>) -> usize {
+    values
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |(best, max), (i, &v)| {
+            if v > max {
+                (i, v)
+            } else {
+                (best, max)
+            }
+        })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    // Compare `gradient` with central differences of `loss(output(z))` in every entry of z
+    fn check_gradient(loss: Loss, z: Array2<f32>, targets: Array2<f32>) {
+        let analytic = loss.gradient(&loss.output(&z), &targets);
+        let h = 1e-2;
+        for ((i, j), expected) in analytic.indexed_iter() {
+            let (mut plus, mut minus) = (z.clone(), z.clone());
+            plus[[i, j]] += h;
+            minus[[i, j]] -= h;
+            let numeric = (loss.loss(&loss.output(&plus), &targets)
+                - loss.loss(&loss.output(&minus), &targets))
+                / (2.0 * h);
+            assert!(
+                (numeric - expected).abs() < 1e-3,
+                "{} at ({}, {}): numeric {} vs analytic {}",
+                loss,
+                i,
+                j,
+                numeric,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn mse_gradient_matches_finite_differences() {
+        check_gradient(
+            Loss::Mse,
+            array![[0.5, -1.0], [2.0, 0.3], [-0.7, 1.1]],
+            array![[0.0, 1.0], [1.5, -0.2], [0.3, 0.3]],
+        );
+    }
+
+    #[test]
+    fn bce_gradient_matches_finite_differences() {
+        // Several outputs per row, so the per-value mean must match in loss and gradient
+        check_gradient(
+            Loss::BinaryCrossEntropy,
+            array![[0.5, -1.0, 2.0], [0.1, 1.3, -0.4]],
+            array![[1.0, 0.0, 1.0], [0.0, 1.0, 0.5]],
+        );
+    }
+
+    #[test]
+    fn cce_gradient_matches_finite_differences() {
+        check_gradient(
+            Loss::CategoricalCrossEntropy,
+            array![[0.5, -1.0, 2.0], [0.1, 1.3, -0.4], [-2.0, 0.0, 0.7]],
+            array![[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        );
+    }
+}
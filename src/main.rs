@@ -1,24 +1,58 @@
+mod auth;
+mod dataset;
 mod discovery;
+mod evaluation;
+mod export;
+mod layers;
+mod limits;
+mod loss;
 mod messages;
 mod model;
 mod network;
 mod node;
+mod onnx;
+mod optim;
+mod partition;
+mod privacy;
+mod rbac;
+mod screening;
+mod secagg;
+mod seed;
 mod server;
+mod signing;
+mod simulation;
+mod tls;
+mod training;
+mod validation;
 
 use actix::Actor;
 use actix_web::{middleware, web, App, HttpServer};
 use anyhow::Result;
+use auth::{AuthConfig, Enrollment, NodeCredential};
+use dataset::{Column, CsvConfig, LocalDataset, Normalization, Samples};
 use env_logger::Env;
-use log::{error, info};
-use node::NodeActor;
+use evaluation::{EarlyStopping, StopMetric};
+use layers::ModelSpec;
+use limits::{LimitsConfig, RequestLimiter};
+use log::{error, info, warn};
+use node::{NodeActor, ServerLink};
 use once_cell::sync::Lazy;
-use server::CentralServer;
+use partition::PartitionStrategy;
+use privacy::{AdaptiveClipping, DpConfig, DpSgdConfig};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rbac::{AccessControl, Role};
+use screening::{ScreeningAction, ScreeningConfig};
+use secagg::SecAggConfig;
+use server::{CentralServer, ServerConfig};
+use simulation::SimulationConfig;
 use std::env;
+use tls::TlsConfig;
+use training::TrainingConfig;
 
 // Global server address for access throughout the app
-static SERVER_ADDR: Lazy<String> = Lazy::new(|| {
-    env::var("SERVER_ADDR").unwrap_or_else(|_| "http://127.0.0.1:5000".to_string())
-});
+static SERVER_ADDR: Lazy<String> =
+    Lazy::new(|| env::var("SERVER_ADDR").unwrap_or_else(|_| "http://127.0.0.1:5000".to_string()));
 
 // Number of nodes to expect
 static TOTAL_NODES: Lazy<usize> = Lazy::new(|| {
@@ -28,56 +62,706 @@ static TOTAL_NODES: Lazy<usize> = Lazy::new(|| {
         .unwrap_or(2)
 });
 
+// Upper bound on the L2 norm of a node's parameter vector
+static MAX_UPDATE_NORM: Lazy<f32> = Lazy::new(|| {
+    env::var("MAX_UPDATE_NORM")
+        .unwrap_or_else(|_| "1000".to_string())
+        .parse()
+        .unwrap_or(1000.0)
+});
+
+// Model architecture from MODEL_INPUT_SIZE, MODEL_LAYERS (e.g. "64:relu,1:linear") and MODEL_LOSS
+fn model_spec() -> Result<ModelSpec> {
+    let input_size = env::var("MODEL_INPUT_SIZE")
+        .unwrap_or_else(|_| "10".to_string())
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid MODEL_INPUT_SIZE: {}", e))?;
+    let layers = env::var("MODEL_LAYERS").unwrap_or_else(|_| "64:relu,1:linear".to_string());
+    let loss = env::var("MODEL_LOSS")
+        .unwrap_or_else(|_| "mse".to_string())
+        .parse()?;
+    ModelSpec::parse(input_size, &layers, loss)
+}
+
+// Root seed from SEED; a random one is drawn (and logged) when unset
+fn root_seed() -> Result<u64> {
+    let seed = env_or("SEED", rand::thread_rng().gen())?;
+    info!(
+        "Root seed {} (set SEED={} to reproduce this run)",
+        seed, seed
+    );
+    Ok(seed)
+}
+
+// Federation settings; ROUND_CONFIG holds the per-round training settings as JSON
+fn server_config(seed: u64) -> Result<ServerConfig> {
+    let round_training = match env::var("ROUND_CONFIG") {
+        Ok(json) => {
+            let config: TrainingConfig = serde_json::from_str(&json)
+                .map_err(|e| anyhow::anyhow!("Invalid ROUND_CONFIG: {}", e))?;
+            config.validate()?;
+            Some(config)
+        }
+        Err(_) => None,
+    };
+    // EARLY_STOPPING_PATIENCE of 0 (the default) disables early stopping
+    let patience: u64 = env_or("EARLY_STOPPING_PATIENCE", 0)?;
+    let early_stopping = if patience > 0 {
+        Some(EarlyStopping::new(
+            env_or("EARLY_STOPPING_METRIC", StopMetric::Loss)?,
+            patience,
+            env_or("EARLY_STOPPING_MIN_DELTA", 0.0)?,
+        ))
+    } else {
+        None
+    };
+    let dp = dp_config()?;
+    let secure_aggregation = secagg_config()?;
+    if dp.is_some() && secure_aggregation.is_some() {
+        return Err(anyhow::anyhow!(
+            "DP-FedAvg clips each node's update, which secure aggregation hides from the server; set DP_CLIP_NORM or SECURE_AGGREGATION, not both"
+        ));
+    }
+    let screening = screening_config()?;
+    if screening.is_some() && secure_aggregation.is_some() {
+        return Err(anyhow::anyhow!(
+            "Update screening needs each node's update, which secure aggregation hides from the server; set SCREENING or SECURE_AGGREGATION, not both"
+        ));
+    }
+    Ok(ServerConfig {
+        dp,
+        secure_aggregation,
+        screening,
+        seed,
+        total_nodes: *TOTAL_NODES,
+        max_update_norm: *MAX_UPDATE_NORM,
+        // Without ROUNDS the server keeps training until stopped
+        rounds: match env::var("ROUNDS") {
+            Ok(_) => Some(env_or("ROUNDS", 1)?),
+            Err(_) => None,
+        },
+        round_training,
+        eval_every: env_or("EVAL_EVERY", 1)?,
+        early_stopping,
+    })
+}
+
+// DP-FedAvg settings, enabled by DP_CLIP_NORM; DP_TARGET_QUANTILE turns on adaptive clipping
+fn dp_config() -> Result<Option<DpConfig>> {
+    let clip_norm = match env::var("DP_CLIP_NORM") {
+        Ok(_) => env_or("DP_CLIP_NORM", 1.0)?,
+        Err(_) => return Ok(None),
+    };
+    let adaptive = match env::var("DP_TARGET_QUANTILE") {
+        Ok(_) => Some(AdaptiveClipping {
+            target_quantile: env_or("DP_TARGET_QUANTILE", 0.5)?,
+            learning_rate: env_or("DP_CLIP_LEARNING_RATE", 0.2)?,
+            // Suggested default of Andrew et al.: expected clients per round / 20
+            count_noise_std: env_or("DP_COUNT_NOISE_STD", *TOTAL_NODES as f64 / 20.0)?,
+        }),
+        Err(_) => None,
+    };
+    let target_epsilon = match env::var("DP_EPSILON") {
+        Ok(_) => Some(env_or("DP_EPSILON", 0.0)?),
+        Err(_) => None,
+    };
+    let config = DpConfig {
+        clip_norm,
+        noise_multiplier: env_or("DP_NOISE_MULTIPLIER", 1.0)?,
+        delta: env_or("DP_DELTA", 1e-5)?,
+        target_epsilon,
+        adaptive,
+        seeded_noise: env_or("DP_SEEDED_NOISE", false)?,
+    };
+    config.validate()?;
+    if config.seeded_noise {
+        warn!("DP_SEEDED_NOISE is set: the DP-FedAvg noise follows SEED and gives no privacy");
+    }
+    Ok(Some(config))
+}
+
+// Node-side DP-SGD settings, enabled by DP_SGD_CLIP_NORM
+fn dp_sgd_config() -> Result<Option<DpSgdConfig>> {
+    let clip_norm = match env::var("DP_SGD_CLIP_NORM") {
+        Ok(_) => env_or("DP_SGD_CLIP_NORM", 1.0)?,
+        Err(_) => return Ok(None),
+    };
+    let target_epsilon = match env::var("DP_SGD_EPSILON") {
+        Ok(_) => Some(env_or("DP_SGD_EPSILON", 0.0)?),
+        Err(_) => None,
+    };
+    let config = DpSgdConfig {
+        clip_norm,
+        noise_multiplier: env_or("DP_SGD_NOISE_MULTIPLIER", 1.0)?,
+        delta: env_or("DP_SGD_DELTA", 1e-5)?,
+        target_epsilon,
+        seeded_noise: env_or("DP_SGD_SEEDED_NOISE", false)?,
+    };
+    config.validate()?;
+    if config.seeded_noise {
+        warn!("DP_SGD_SEEDED_NOISE is set: the DP-SGD noise follows SEED and gives no privacy");
+    }
+    Ok(Some(config))
+}
+
+// Message limits from MAX_PAYLOAD_SIZE, RATE_LIMIT_UPDATES, RATE_LIMIT_REGISTRATIONS and RATE_LIMIT_MESSAGES
+fn limits_config() -> Result<LimitsConfig> {
+    let config = LimitsConfig {
+        max_payload_bytes: env_or("MAX_PAYLOAD_SIZE", 2 * 1024 * 1024)?,
+        updates_per_minute: env_or("RATE_LIMIT_UPDATES", 60)?,
+        registrations_per_minute: env_or("RATE_LIMIT_REGISTRATIONS", 10)?,
+        messages_per_minute: env_or("RATE_LIMIT_MESSAGES", 120)?,
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+// Update screening settings, enabled by SCREENING=flag|down_weight|quarantine
+fn screening_config() -> Result<Option<ScreeningConfig>> {
+    let action: ScreeningAction = match env::var("SCREENING") {
+        Ok(_) => env_or("SCREENING", ScreeningAction::Flag)?,
+        Err(_) => return Ok(None),
+    };
+    let config = ScreeningConfig {
+        action,
+        norm_threshold: env_or("SCREENING_NORM_THRESHOLD", 3.5)?,
+        min_cosine: env_or("SCREENING_MIN_COSINE", 0.0)?,
+        history_factor: env_or("SCREENING_HISTORY_FACTOR", 3.0)?,
+        max_loss_increase: env_or("SCREENING_MAX_LOSS_INCREASE", 0.5)?,
+        down_weight: env_or("SCREENING_DOWN_WEIGHT", 0.25)?,
+        min_reputation: env_or("SCREENING_MIN_REPUTATION", 0.5)?,
+    };
+    config.validate()?;
+    Ok(Some(config))
+}
+
+// Secure aggregation settings, enabled by SECURE_AGGREGATION on the server and on every node
+fn secagg_config() -> Result<Option<SecAggConfig>> {
+    if !env_or("SECURE_AGGREGATION", false)? {
+        return Ok(None);
+    }
+    // By default tolerate as many dropouts as a surviving majority allows
+    let max_dropouts = env_or("SECAGG_MAX_DROPOUTS", TOTAL_NODES.saturating_sub(1) / 2)?;
+    let phase_timeout = std::time::Duration::from_secs(env_or("SECAGG_PHASE_TIMEOUT", 30)?);
+    SecAggConfig::new(*TOTAL_NODES, max_dropouts, phase_timeout).map(Some)
+}
+
+// Local training settings from TRAINING_CONFIG, LOCAL_EPOCHS, BATCH_SIZE, SHUFFLE_SEED and LEARNING_RATE
+fn training_config() -> Result<TrainingConfig> {
+    // TRAINING_CONFIG (JSON) sets optimizer and schedule; the single variables override it
+    let base: TrainingConfig = match env::var("TRAINING_CONFIG") {
+        Ok(json) => serde_json::from_str(&json)
+            .map_err(|e| anyhow::anyhow!("Invalid TRAINING_CONFIG: {}", e))?,
+        Err(_) => TrainingConfig::default(),
+    };
+    let config = TrainingConfig {
+        epochs: env_or("LOCAL_EPOCHS", base.epochs)?,
+        batch_size: env_or("BATCH_SIZE", base.batch_size)?,
+        shuffle_seed: env_or("SHUFFLE_SEED", base.shuffle_seed)?,
+        learning_rate: env_or("LEARNING_RATE", base.learning_rate)?,
+        ..base
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+// Local CSV dataset of a node, if DATA_PATH is set
+fn csv_config() -> Result<Option<CsvConfig>> {
+    let path = match env::var("DATA_PATH") {
+        Ok(path) => path,
+        Err(_) => return Ok(None),
+    };
+    let delimiter = env::var("DATA_DELIMITER").unwrap_or_else(|_| ",".to_string());
+    let delimiter = match delimiter.as_bytes() {
+        [byte] => *byte,
+        _ if delimiter == "\\t" => b'\t',
+        _ => return Err(anyhow::anyhow!("Invalid DATA_DELIMITER: {}", delimiter)),
+    };
+    let features = match env::var("DATA_FEATURES") {
+        Ok(columns) => Some(
+            columns
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<Column>>>()?,
+        ),
+        Err(_) => None,
+    };
+    let label = match env::var("DATA_LABEL") {
+        Ok(column) => Some(column.parse()?),
+        Err(_) => None,
+    };
+
+    Ok(Some(CsvConfig {
+        path: path.into(),
+        has_header: env_or("DATA_HEADER", true)?,
+        delimiter,
+        features,
+        label,
+        normalization: env_or("DATA_NORMALIZE", Normalization::None)?,
+        validation_fraction: env_or("DATA_VALIDATION_FRACTION", 0.2)?,
+        split_seed: match env::var("DATA_SPLIT_SEED") {
+            Ok(_) => Some(env_or("DATA_SPLIT_SEED", 0)?),
+            Err(_) => None,
+        },
+    }))
+}
+
+// Shared-dataset partitioning for simulated clients, if PARTITION is set
+fn partition_config(root_seed: u64) -> Result<Option<(PartitionStrategy, usize, u64)>> {
+    let strategy = match env::var("PARTITION") {
+        Ok(strategy) => strategy.parse()?,
+        Err(_) => return Ok(None),
+    };
+    Ok(Some((
+        strategy,
+        env_or("PARTITION_CLIENTS", *TOTAL_NODES)?,
+        partition_seed(root_seed)?,
+    )))
+}
+
+// PARTITION_SEED, derived from the root seed when unset
+fn partition_seed(root_seed: u64) -> Result<u64> {
+    env_or(
+        "PARTITION_SEED",
+        seed::derive(root_seed, seed::Stream::Partition, 0),
+    )
+}
+
+// Samples shared by all clients: the CSV file, or `synthetic` samples drawn from `rng`
+fn source_samples(
+    spec: &ModelSpec,
+    csv: Option<&CsvConfig>,
+    synthetic: usize,
+    rng: &mut impl Rng,
+) -> Result<Samples> {
+    let (samples, features) = match csv {
+        Some(config) => dataset::read_csv(config)?,
+        None => (
+            partition::generate_data(synthetic, spec, rng),
+            spec.input_size,
+        ),
+    };
+    if features != spec.input_size {
+        return Err(anyhow::anyhow!(
+            "Dataset has {} feature columns, but the model expects {}",
+            features,
+            spec.input_size
+        ));
+    }
+    Ok(samples)
+}
+
+// Split a client's samples for training and validation, normalizing CSV features.
+// Unless DATA_SPLIT_SEED is set, the split follows the node's seed, so nodes hold out different rows.
+fn client_dataset(
+    samples: Samples,
+    spec: &ModelSpec,
+    csv: Option<&CsvConfig>,
+    training: &TrainingConfig,
+    node_seed: u64,
+) -> Result<LocalDataset> {
+    let derived_seed = seed::derive(node_seed, seed::Stream::Split, 0);
+    match csv {
+        Some(config) => {
+            let mut dataset = LocalDataset::split(
+                samples,
+                spec.input_size,
+                config.validation_fraction,
+                config.split_seed.unwrap_or(derived_seed),
+            )?;
+            dataset.normalize(config.normalization);
+            Ok(dataset)
+        }
+        None => LocalDataset::split(
+            samples,
+            spec.input_size,
+            training.eval_fraction,
+            derived_seed,
+        ),
+    }
+}
+
+// Build the node's local dataset from its CSV file or synthetic data. With PARTITION
+// set, every node reads the same source and keeps only its own client's part.
+fn local_dataset(
+    spec: &ModelSpec,
+    training: &TrainingConfig,
+    node_id: &str,
+    node_seed: u64,
+    root_seed: u64,
+) -> Result<LocalDataset> {
+    let csv = csv_config()?;
+
+    let samples = match partition_config(root_seed)? {
+        Some((strategy, clients, seed)) => {
+            // NODE_ID 1 is client 0
+            let client = node_id.parse::<usize>().unwrap_or(1).saturating_sub(1);
+            if client >= clients {
+                return Err(anyhow::anyhow!(
+                    "NODE_ID {} has no partition among {} clients",
+                    node_id,
+                    clients
+                ));
+            }
+            // All nodes generate the same shared dataset from the partition seed
+            let synthetic = env_or("PARTITION_SAMPLES", 100 * clients)?;
+            let samples = source_samples(
+                spec,
+                csv.as_ref(),
+                synthetic,
+                &mut StdRng::seed_from_u64(seed),
+            )?;
+            let mut partition = partition::partition(&samples, spec, &strategy, clients, seed)?;
+            info!("{}", partition.summary);
+            partition.clients.swap_remove(client)
+        }
+        None => source_samples(
+            spec,
+            csv.as_ref(),
+            100,
+            &mut seed::rng(node_seed, seed::Stream::Data, 0),
+        )?,
+    };
+
+    client_dataset(samples, spec, csv.as_ref(), training, node_seed)
+}
+
+// In-process simulation settings from SIM_THREADS, SIM_METRICS_PATH, SIM_ROUND_TIMEOUT and SIM_MODEL_PATH
+fn simulation_config() -> Result<SimulationConfig> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Ok(SimulationConfig {
+        threads: env_or("SIM_THREADS", threads)?,
+        metrics_path: env_or("SIM_METRICS_PATH", "simulation_metrics.jsonl".into())?,
+        round_timeout: std::time::Duration::from_secs(env_or("SIM_ROUND_TIMEOUT", 600)?),
+        model_path: env::var("SIM_MODEL_PATH").ok().map(Into::into),
+        dp_sgd: dp_sgd_config()?,
+        secure_aggregation: secagg_config()?,
+    })
+}
+
+// TLS settings from TLS_CERT and TLS_KEY; TLS_CA verifies peers, TLS_CLIENT_AUTH requires client certificates
+fn tls_config() -> Result<Option<TlsConfig>> {
+    let (cert_path, key_path) = match (env::var("TLS_CERT"), env::var("TLS_KEY")) {
+        (Ok(cert), Ok(key)) => (cert, key),
+        (Err(_), Err(_)) => return Ok(None),
+        _ => return Err(anyhow::anyhow!("TLS_CERT and TLS_KEY must be set together")),
+    };
+    let ca_path = env::var("TLS_CA").ok().map(Into::into);
+    let client_auth = env_or("TLS_CLIENT_AUTH", false)?;
+    if client_auth && ca_path.is_none() {
+        return Err(anyhow::anyhow!(
+            "TLS_CLIENT_AUTH needs TLS_CA to verify client certificates"
+        ));
+    }
+    Ok(Some(TlsConfig {
+        cert_path: cert_path.into(),
+        key_path: key_path.into(),
+        ca_path,
+        client_auth,
+    }))
+}
+
+// Node enrollment, enabled by ADMIN_TOKEN on the server
+fn auth_config() -> Result<Option<AuthConfig>> {
+    let admin_token = match env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        Ok(_) => return Err(anyhow::anyhow!("ADMIN_TOKEN must not be empty")),
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(AuthConfig {
+        admin_token,
+        join_token_ttl: std::time::Duration::from_secs(env_or("JOIN_TOKEN_TTL", 86400)?),
+        credential_ttl: match env::var("CREDENTIAL_TTL") {
+            Ok(_) => Some(std::time::Duration::from_secs(env_or("CREDENTIAL_TTL", 0)?)),
+            Err(_) => None,
+        },
+        state_path: env::var("ENROLLMENT_STATE_PATH").ok().map(Into::into),
+    }))
+}
+
+// Role-based access control, enabled by API_KEYS on the server; ADMIN_TOKEN also acts as an admin key
+fn access_control() -> Result<Option<AccessControl>> {
+    let spec = match env::var("API_KEYS") {
+        Ok(spec) => spec,
+        Err(_) => return Ok(None),
+    };
+    let mut keys = AccessControl::parse_keys(&spec)?;
+    if keys.is_empty() {
+        return Err(anyhow::anyhow!(
+            "API_KEYS must name at least one role:key pair"
+        ));
+    }
+    if let Ok(token) = env::var("ADMIN_TOKEN") {
+        keys.push((Role::Admin, token));
+    }
+    Ok(Some(AccessControl::new(keys)))
+}
+
+// Credential and keys of a node: read from CREDENTIAL_PATH, or enrolled with
+// JOIN_TOKEN and then saved to CREDENTIAL_PATH for later restarts
+async fn node_credential(node_addr: &str) -> Result<Option<NodeCredential>> {
+    let path = env::var("CREDENTIAL_PATH").ok();
+    if let Some(json) = path
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+    {
+        let path = path.as_deref().unwrap_or_default();
+        info!("Using the credential in {}", path);
+        return serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid credential file {}: {}", path, e));
+    }
+    let token = match env::var("JOIN_TOKEN") {
+        Ok(token) => token,
+        Err(_) => return Ok(None),
+    };
+    let signing_key = signing::generate_key();
+    let enrolled = network::enroll(
+        &SERVER_ADDR,
+        &token,
+        node_addr,
+        &signing_key.verifying_key(),
+    )
+    .await?;
+    let credential = NodeCredential {
+        credential: enrolled.credential,
+        signing_key: signing::encode_signing_key(&signing_key),
+        server_key: enrolled.server_key,
+    };
+    if let Some(path) = &path {
+        auth::write_private(
+            std::path::Path::new(path),
+            &serde_json::to_vec_pretty(&credential)?,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path, e))?;
+    }
+    Ok(Some(credential))
+}
+
+// Parse an environment variable, falling back to a default when unset
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {}: {}", name, e)),
+        Err(_) => Ok(default),
+    }
+}
+
 #[actix_web::main]
 async fn main() -> Result<()> {
     // Initialize logger
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-    // Determine if we're running as a server or node
-    let is_server = env::var("RUN_AS").unwrap_or_else(|_| "server".to_string()) == "server";
+    // Outgoing requests present the TLS certificate, if any
+    let tls = tls_config()?;
+    tls::init_client(tls.as_ref())?;
 
-    if is_server {
-        run_server().await?;
-    } else {
-        let node_id = env::var("NODE_ID").unwrap_or_else(|_| "node1".to_string());
-        let node_addr = format!("http://127.0.0.1:{}", 8001 + node_id.parse::<u16>().unwrap_or(1));
-        run_node(&node_id, &node_addr).await?;
+    // Determine if we're running as a server, a node or a whole simulated federation
+    match env::var("RUN_AS")
+        .unwrap_or_else(|_| "server".to_string())
+        .as_str()
+    {
+        "server" => run_server(tls).await?,
+        "simulation" => run_simulation().await?,
+        _ => {
+            let node_id = env::var("NODE_ID").unwrap_or_else(|_| "node1".to_string());
+            // With mutual TLS the host must match the node certificate's common name
+            let scheme = if tls.is_some() { "https" } else { "http" };
+            let host = env::var("NODE_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+            let node_addr = format!(
+                "{}://{}:{}",
+                scheme,
+                host,
+                8001 + node_id.parse::<u16>().unwrap_or(1)
+            );
+            run_node(&node_id, &node_addr, tls).await?;
+        }
     }
 
     Ok(())
 }
 
-async fn run_server() -> Result<()> {
+async fn run_server(tls: Option<TlsConfig>) -> Result<()> {
     info!("Starting central server");
 
+    let spec = model_spec()?;
+    info!("Global model architecture: {}", spec);
+
     // Start central server actor
-    let server_actor = CentralServer::new(*TOTAL_NODES).start();
+    let mut central_server = central_server(server_config(root_seed()?)?, &spec)?;
+    let enrollment = match auth_config()? {
+        Some(config) => {
+            info!(
+                "Node enrollment enabled; messages need a node credential and updates a signature"
+            );
+            let enrollment = Enrollment::new(config)?;
+            central_server.set_signing_key(enrollment.signing_key().clone());
+            Some(web::Data::new(enrollment))
+        }
+        None => None,
+    };
+    let server_actor = central_server.start();
+    let access_control = access_control()?.map(|access_control| {
+        info!("Role-based access control enabled for the HTTP API");
+        web::Data::new(access_control)
+    });
+    let limiter = web::Data::new(RequestLimiter::new(limits_config()?));
 
     // Start HTTP server for API endpoints
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(rbac::authorize))
             .wrap(middleware::Logger::default())
             .app_data(web::Data::new(server_actor.clone()))
-            .route("/message", web::post().to(network::receive_server_message))
+            .app_data(limiter.clone())
+            .configure(|cfg| {
+                if let Some(enrollment) = &enrollment {
+                    cfg.app_data(enrollment.clone());
+                }
+                if let Some(access_control) = &access_control {
+                    cfg.app_data(access_control.clone());
+                }
+            })
+            .service(
+                web::resource("/message")
+                    .app_data(limiter.json_config())
+                    .route(web::post().to(network::receive_server_message)),
+            )
             .route("/status", web::get().to(network::get_server_status))
-            .route("/", web::get().to(|| async {
-                actix_web::HttpResponse::Ok()
-                    .content_type("text/html")
-                    .body(include_str!("../templates/dashboard.html"))
-            }))
+            .route(
+                "/",
+                web::get().to(|| async {
+                    actix_web::HttpResponse::Ok()
+                        .content_type("text/html")
+                        .body(include_str!("../templates/dashboard.html"))
+                }),
+            )
             .route("/api/nodes", web::get().to(network::get_all_nodes))
-            .route("/api/model/params", web::get().to(network::get_model_params))
+            .route(
+                "/api/model/params",
+                web::get().to(network::get_model_params),
+            )
+            .route("/api/model/spec", web::get().to(network::get_model_spec))
+            .route(
+                "/api/model/safetensors",
+                web::get().to(network::get_model_safetensors),
+            )
+            .route("/api/model/onnx", web::get().to(network::get_model_onnx))
+            .route(
+                "/api/training/config",
+                web::get().to(network::get_round_config),
+            )
+            .route(
+                "/api/training/config",
+                web::put().to(network::set_round_config),
+            )
+            .route(
+                "/api/training/round",
+                web::post().to(network::control_round),
+            )
+            .route("/api/metrics", web::get().to(network::get_metrics))
+            .route("/api/history", web::get().to(network::get_history))
+            .route("/api/privacy", web::get().to(network::get_privacy))
+            .route("/predict", web::post().to(network::predict_on_server))
+            .route("/api/enroll", web::post().to(network::enroll_node))
+            .route(
+                "/api/admin/join-tokens",
+                web::post().to(network::create_join_token),
+            )
+            .route(
+                "/api/admin/credentials",
+                web::get().to(network::list_credentials),
+            )
+            .route(
+                "/api/admin/credentials/{id}",
+                web::delete().to(network::revoke_credential),
+            )
     })
-    .bind(("0.0.0.0", 5000))?
+    .on_connect(tls::peer_identity);
+    let server = match &tls {
+        Some(tls) => server.bind_rustls_0_23(("0.0.0.0", 5000), tls::server_config(tls)?)?,
+        None => server.bind(("0.0.0.0", 5000))?,
+    }
     .run();
 
-    info!("Central server listening on 0.0.0.0:5000");
+    info!(
+        "Central server listening on {}://0.0.0.0:5000",
+        if tls.is_some() { "https" } else { "http" }
+    );
 
     server.await?;
     Ok(())
 }
 
-async fn run_node(node_id: &str, node_addr: &str) -> Result<()> {
+// Central server, optionally seeded from a safetensors file and with a holdout set
+fn central_server(config: ServerConfig, spec: &ModelSpec) -> Result<CentralServer> {
+    if let Some(early_stopping) = &config.early_stopping {
+        early_stopping.validate(spec.loss)?;
+    }
+    let mut central_server = CentralServer::new(config, spec);
+    if let Ok(path) = env::var("INITIAL_MODEL") {
+        central_server.seed_from_safetensors(std::path::Path::new(&path))?;
+    }
+    if let Ok(path) = env::var("HOLDOUT_PATH") {
+        central_server.load_holdout(std::path::Path::new(&path))?;
+    }
+    Ok(central_server)
+}
+
+// Run the server and TOTAL_NODES nodes in this process on a partition of one dataset
+async fn run_simulation() -> Result<()> {
+    let spec = model_spec()?;
+    let training = training_config()?;
+    let root_seed = root_seed()?;
+    let clients = *TOTAL_NODES;
+    info!(
+        "Simulating a federation of {} nodes, model {}",
+        clients, spec
+    );
+
+    // Without PARTITION the shared dataset is split IID
+    let csv = csv_config()?;
+    let strategy = env_or("PARTITION", PartitionStrategy::Iid)?;
+    let seed = partition_seed(root_seed)?;
+    let synthetic = env_or("PARTITION_SAMPLES", 100 * clients)?;
+    let samples = source_samples(
+        &spec,
+        csv.as_ref(),
+        synthetic,
+        &mut StdRng::seed_from_u64(seed),
+    )?;
+    let partition = partition::partition(&samples, &spec, &strategy, clients, seed)?;
+    info!("{}", partition.summary);
+    let datasets = partition
+        .clients
+        .into_iter()
+        .enumerate()
+        .map(|(i, samples)| {
+            let node_seed = seed::node_seed(root_seed, &simulation::node_addr(i));
+            client_dataset(samples, &spec, csv.as_ref(), &training, node_seed)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // A simulation ends after its last round, one unless ROUNDS says otherwise
+    let mut config = server_config(root_seed)?;
+    config.rounds = config.rounds.or(Some(1));
+    let server = central_server(config, &spec)?;
+    simulation::run(
+        &simulation_config()?,
+        server,
+        &spec,
+        &training,
+        datasets,
+        root_seed,
+    )
+    .await
+}
+
+async fn run_node(node_id: &str, node_addr: &str, tls: Option<TlsConfig>) -> Result<()> {
     info!("Starting node {} at {}", node_id, node_addr);
 
     // Parse port from node_addr
@@ -89,7 +773,40 @@ async fn run_node(node_id: &str, node_addr: &str) -> Result<()> {
     };
 
     // Start node actor
-    let node_actor = NodeActor::new(SERVER_ADDR.clone(), node_addr.to_string()).start();
+    let mut spec = model_spec()?;
+    let training = training_config()?;
+    let root_seed = root_seed()?;
+    let mut node_actor = NodeActor::new(
+        ServerLink::Http(SERVER_ADDR.clone()),
+        node_addr.to_string(),
+        &spec,
+        training.clone(),
+        root_seed,
+    );
+    if let Some(config) = dp_sgd_config()? {
+        info!("Training with local DP-SGD: {:?}", config);
+        node_actor = node_actor.with_dp_sgd(config);
+    }
+    if let Some(config) = secagg_config()? {
+        info!(
+            "Submitting updates through secure aggregation: {:?}",
+            config
+        );
+        node_actor = node_actor.with_secure_aggregation(config);
+    }
+    // Bearer token for the server: the enrolled credential, or else an API key with the node role
+    let mut bearer = env::var("API_KEY").ok();
+    if let Some(credential) = node_credential(node_addr).await? {
+        node_actor = node_actor.with_signing_keys(
+            signing::parse_signing_key(&credential.signing_key)?,
+            signing::parse_verifying_key(&credential.server_key)?,
+        );
+        bearer = Some(credential.credential);
+    }
+    if let Some(token) = &bearer {
+        node_actor = node_actor.with_credential(token.clone());
+    }
+    let node_actor = node_actor.start();
 
     // Optional: Register with etcd if ETCD_ENDPOINTS is set
     if let Ok(etcd_endpoints) = env::var("ETCD_ENDPOINTS") {
@@ -102,12 +819,42 @@ async fn run_node(node_id: &str, node_addr: &str) -> Result<()> {
         }
     }
 
-    // Generate some train data and train the model
-    let (data, labels) = model::generate_data(100);
-    let _ = node_actor.send(messages::NodeMessage::Train {
-        data: data.clone(),
-        labels: labels.clone()
-    }).await;
+    // Build the same architecture as the server
+    match network::fetch_model_spec(&SERVER_ADDR, bearer.as_deref()).await {
+        Ok(server_spec) => match node_actor
+            .send(messages::SetArchitecture(server_spec.clone()))
+            .await
+        {
+            Ok(Ok(())) => spec = server_spec,
+            Ok(Err(e)) => error!("{}, using local config", e),
+            Err(e) => error!("Failed to rebuild model, using local config: {}", e),
+        },
+        Err(e) => error!(
+            "Failed to fetch model architecture, using local config: {}",
+            e
+        ),
+    }
+
+    // Start from the current global model so a seeded federation reaches every node
+    match network::fetch_global_params(&SERVER_ADDR, bearer.as_deref()).await {
+        Ok(model) => {
+            let _ = node_actor.send(model).await;
+        }
+        Err(e) => error!(
+            "Failed to fetch global model, using local initialization: {}",
+            e
+        ),
+    }
+
+    // Load the local dataset and train the model
+    let dataset = local_dataset(
+        &spec,
+        &training,
+        node_id,
+        seed::node_seed(root_seed, node_addr),
+        root_seed,
+    )?;
+    let _ = node_actor.send(messages::LoadDataset(dataset)).await;
 
     // Start HTTP server for API endpoints
     let server = HttpServer::new(move || {
@@ -116,20 +863,19 @@ async fn run_node(node_id: &str, node_addr: &str) -> Result<()> {
             .app_data(web::Data::new(node_actor.clone()))
             .route("/message", web::post().to(network::receive_node_message))
             .route("/status", web::get().to(network::get_node_status))
-            .route("/train", web::post().to(|data: web::Json<(Vec<f32>, Vec<f32>)>, actor: web::Data<actix::Addr<NodeActor>>| {
-                async move {
-                    let (data, labels) = data.into_inner();
-                    match actor.send(messages::NodeMessage::Train { data, labels }).await {
-                        Ok(Ok(())) => "Training started",
-                        _ => "Failed to start training",
-                    }
-                }
-            }))
-    })
-    .bind(("0.0.0.0", port))?
+            .route("/predict", web::post().to(network::predict_on_node))
+    });
+    let server = match &tls {
+        Some(tls) => server.bind_rustls_0_23(("0.0.0.0", port), tls::server_config(tls)?)?,
+        None => server.bind(("0.0.0.0", port))?,
+    }
     .run();
 
-    info!("Node listening on 0.0.0.0:{}", port);
+    info!(
+        "Node listening on {}://0.0.0.0:{}",
+        if tls.is_some() { "https" } else { "http" },
+        port
+    );
 
     server.await?;
     Ok(())
@@ -1,3 +1,7 @@
+use crate::evaluation::RoundEvaluation;
+use crate::layers::ModelSpec;
+use crate::model::EvalMetrics;
+use crate::training::TrainingConfig;
 use actix::prelude::*;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -6,12 +10,85 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Message, Clone, Debug)]
 #[rtype(result = "Result<(), String>")]
 pub enum NodeMessage {
-    Train { data: Vec<f32>, labels: Vec<f32> }, // Request to train on data
-    Predict { data: Vec<f32> },                 // Request for prediction
-    UpdateModel { params: Vec<f32> },           // Update model parameters
-    RegisterNode { addr: String },              // Register node with server
+    UpdateModel {
+        params: Vec<f32>,
+        // Rounds behind the model, covered by the signature
+        #[serde(default)]
+        round: u64,
+        // Server's signature of the model, when nodes are enrolled
+        #[serde(default)]
+        signature: Option<String>,
+    }, // Update model parameters
+    RegisterNode {
+        addr: String,
+    }, // Register node with server
+    SubmitUpdate {
+        node_addr: String,
+        #[serde(default)]
+        round: u64,
+        params: Vec<f32>,
+        num_samples: usize,
+        num_steps: usize,
+        // Epsilon the node has spent with local DP-SGD, if enabled
+        #[serde(default)]
+        epsilon: Option<f64>,
+        // Node's signature over the round, its address and the parameters, when enrolled
+        #[serde(default)]
+        signature: Option<String>,
+    }, // Locally trained parameters sent to the server
+    StartRound {
+        round: u64,
+        params: Vec<f32>,
+        training: Option<Box<TrainingConfig>>,
+        // Server's signature of the model, when nodes are enrolled
+        #[serde(default)]
+        signature: Option<String>,
+    }, // Global model for a new round, with the round's training settings
+    Evaluate {
+        round: u64,
+        params: Vec<f32>,
+        // Server's signature of the model, when nodes are enrolled
+        #[serde(default)]
+        signature: Option<String>,
+    }, // Evaluate a global model on the node's held-out split
+    SubmitEvaluation {
+        node_addr: String,
+        round: u64,
+        metrics: EvalMetrics,
+    }, // Held-out metrics sent to the server
+    SecAgg(Box<crate::secagg::SecAggMessage>), // Secure aggregation step, relayed by the server
 }
 
+impl NodeMessage {
+    // Address of the node a message to the server comes from
+    pub fn sender(&self) -> Option<&str> {
+        match self {
+            NodeMessage::RegisterNode { addr } => Some(addr),
+            NodeMessage::SubmitUpdate { node_addr, .. }
+            | NodeMessage::SubmitEvaluation { node_addr, .. } => Some(node_addr),
+            NodeMessage::SecAgg(msg) => msg.sender(),
+            _ => None,
+        }
+    }
+}
+
+// Message to give a node a dataset read from local storage and train on it.
+// Only sent in-process, so the data never crosses the network.
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct LoadDataset(pub crate::dataset::LocalDataset);
+
+// Message to rebuild a node's model for the federation's architecture. Only sent
+// in-process with the spec fetched from the server, so peers cannot swap the model.
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct SetArchitecture(pub ModelSpec);
+
+// Message to request a node's local training progress
+#[derive(Message)]
+#[rtype(result = "crate::node::NodeTrainingStatus")]
+pub struct GetNodeStatus;
+
 // Message to request information about connected nodes
 #[derive(Message)]
 #[rtype(result = "Vec<crate::network::NodeStatus>")]
@@ -22,10 +99,119 @@ pub struct GetNodesRequest;
 #[rtype(result = "Result<Vec<f32>, String>")]
 pub struct GetModelParams;
 
+// Message to request the global model as sent to nodes, signed by the server
+#[derive(Message)]
+#[rtype(result = "Result<NodeMessage, String>")]
+pub struct GetGlobalModel;
+
+// Message to request the architecture of the global model
+#[derive(Message)]
+#[rtype(result = "Option<ModelSpec>")]
+pub struct GetModelSpec;
+
+// Message to request the global model in safetensors format
+#[derive(Message)]
+#[rtype(result = "Result<Vec<u8>, String>")]
+pub struct GetModelSafetensors;
+
+// Message to request the global model as an ONNX graph
+#[derive(Message)]
+#[rtype(result = "Result<Vec<u8>, String>")]
+pub struct GetModelOnnx;
+
+// Message to read the training settings the server sends with each round
+#[derive(Message)]
+#[rtype(result = "Option<TrainingConfig>")]
+pub struct GetRoundConfig;
+
+// Message to change the training settings for the following rounds
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetRoundConfig(pub Option<TrainingConfig>);
+
+// Message to run inference on a batch of feature rows
+#[derive(Message)]
+#[rtype(result = "Result<Vec<Vec<f32>>, crate::model::PredictError>")]
+pub struct PredictBatch(pub Vec<Vec<f32>>);
+
+// Message to request the per-round evaluation history
+#[derive(Message)]
+#[rtype(result = "Vec<RoundEvaluation>")]
+pub struct GetHistory;
+
+// Message to connect a node running in the same process, so the server
+// broadcasts to it in memory instead of over HTTP
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ConnectNode {
+    pub addr: String,
+    pub recipient: Recipient<NodeMessage>,
+}
+
+// Message to request the number of completed rounds
+#[derive(Message)]
+#[rtype(result = "crate::server::RoundProgress")]
+pub struct GetProgress;
+
+// Message to start more rounds, restart the current one or stop training
+#[derive(Message)]
+#[rtype(result = "Result<crate::server::RoundProgress, String>")]
+pub struct ControlRound(pub crate::server::RoundControl);
+
+// Message to request the privacy budget spent by DP-FedAvg
+#[derive(Message)]
+#[rtype(result = "Option<crate::privacy::PrivacyReport>")]
+pub struct GetPrivacy;
+
+// Message to request server metrics
+#[derive(Message)]
+#[rtype(result = "crate::server::ServerMetrics")]
+pub struct GetMetrics;
+
 // Message for central server
 #[derive(Message, Clone)]
-#[rtype(result = "Result<(), String>")]
+#[rtype(result = "Result<(), crate::validation::UpdateRejection>")]
 pub struct ServerMessage {
     pub node_addr: String,
+    // Round the update was trained for
+    pub round: u64,
     pub params: Vec<f32>,
+    // Local samples and gradient steps behind the update, 0 when unknown
+    pub num_samples: usize,
+    pub num_steps: usize,
+    // Epsilon the node has spent with local DP-SGD
+    pub epsilon: Option<f64>,
+}
+
+impl ServerMessage {
+    // Extract a model update from a node message, handing back any other message
+    pub fn from_node_message(msg: NodeMessage) -> Result<Self, NodeMessage> {
+        match msg {
+            NodeMessage::UpdateModel { params, .. } => Ok(Self {
+                node_addr: "direct".to_string(),
+                round: 0,
+                params,
+                num_samples: 0,
+                num_steps: 0,
+                epsilon: None,
+            }),
+            NodeMessage::SubmitUpdate {
+                node_addr,
+                round,
+                params,
+                num_samples,
+                num_steps,
+                epsilon,
+                ..
+            } => Ok(Self {
+                node_addr,
+                round,
+                params,
+                num_samples,
+                num_steps,
+                epsilon,
+            }),
+            other => Err(other),
+        }
+    }
 }
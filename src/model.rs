@@ -1,98 +1,253 @@
+use crate::layers::ModelSpec;
+use crate::loss::Loss;
+use crate::optim::Optimizer;
+use crate::privacy::{DpSgd, DpSgdConfig};
+use crate::seed::{self, Stream};
+use crate::training::{add_l2_gradient, clip_grad_norm, epoch_batches, Dropout, TrainingConfig};
 use anyhow::Result;
-use ndarray::{Array1, Array2, Axis};
+use ndarray::{s, Array1, Array2, Axis};
+use rand::rngs::StdRng;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::sync::{Arc, Mutex};
 
-// Simple neural network model with two layers
-#[derive(Clone, Serialize, Deserialize)]
-pub struct SimpleModel {
-    // First layer weights: [input_size, hidden_size]
-    pub w1: Array2<f32>,
-    // First layer bias: [hidden_size]
-    pub b1: Array1<f32>,
-    // Second layer weights: [hidden_size, output_size]
-    pub w2: Array2<f32>,
-    // Second layer bias: [output_size]
-    pub b2: Array1<f32>,
-    // Learning rate
-    pub learning_rate: f32,
-}
+// Interface the federation needs from a model architecture
+pub trait FederatedModel: Send {
+    // Forward pass: [batch, input_size] -> [batch, output_size]
+    fn forward(&self, x: &Array2<f32>) -> Array2<f32>;
 
-impl SimpleModel {
-    // Create a new model with random initialization
-    pub fn new(input_size: usize, hidden_size: usize, output_size: usize) -> Self {
-        let mut rng = rand::thread_rng();
-
-        // Xavier initialization for weights
-        let w1_bound = (6.0 / (input_size + hidden_size) as f32).sqrt();
-        let w2_bound = (6.0 / (hidden_size + output_size) as f32).sqrt();
-
-        // Initialize weights and biases
-        let mut w1 = Array2::zeros((input_size, hidden_size));
-        let mut w2 = Array2::zeros((hidden_size, output_size));
-        let b1 = Array1::zeros(hidden_size);
-        let b2 = Array1::zeros(output_size);
-
-        // Random initialization of weights
-        for i in 0..input_size {
-            for j in 0..hidden_size {
-                w1[[i, j]] = rng.gen_range(-w1_bound..w1_bound);
+    // Loss on a batch and its gradient, flattened in `param_schema` order.
+    // Hidden units are dropped when `dropout` is given.
+    fn gradients(
+        &self,
+        x: &Array2<f32>,
+        y: &Array2<f32>,
+        dropout: Option<&mut Dropout>,
+    ) -> (f32, Vec<f32>);
+
+    // Flat parameter vector in `param_schema` order
+    fn get_params(&self) -> Vec<f32>;
+
+    // Replace all parameters from a flat vector in `param_schema` order
+    fn set_params(&mut self, params: &[f32]) -> Result<()>;
+
+    // Name and shape of every tensor in the parameter vector
+    fn param_schema(&self) -> Vec<TensorSpec>;
+
+    // Number of input features expected by `forward`
+    fn input_size(&self) -> usize;
+
+    // Number of outputs produced by `forward` (classes for categorical models)
+    fn output_size(&self) -> usize;
+
+    // Training objective, which also decides how labels are encoded
+    fn loss(&self) -> Loss {
+        Loss::Mse
+    }
+
+    // Loss and accuracy on a labelled dataset without updating the model
+    fn evaluate(&self, x: &Array2<f32>, y: &Array2<f32>) -> EvalMetrics;
+
+    // One optimizer step on a batch with the configured regularization,
+    // returning the loss before the update. With `dp_sgd` the gradient is
+    // built from clipped per-example gradients plus noise.
+    #[allow(clippy::too_many_arguments)]
+    fn train_step(
+        &mut self,
+        x: &Array2<f32>,
+        y: &Array2<f32>,
+        config: &TrainingConfig,
+        optimizer: &mut Optimizer,
+        learning_rate: f32,
+        rng: &mut StdRng,
+        dp_sgd: Option<&mut DpSgd>,
+    ) -> Result<f32> {
+        let mut dropout = Dropout {
+            rate: config.dropout,
+            rng,
+        };
+        let mut dropout = (config.dropout > 0.0).then_some(&mut dropout);
+        let (loss, mut grads) = match dp_sgd {
+            Some(dp_sgd) => {
+                let mut loss = 0.0;
+                let per_example: Vec<Vec<f32>> = (0..x.nrows())
+                    .map(|i| {
+                        let rows = i..i + 1;
+                        let (example_loss, grads) = self.gradients(
+                            &x.slice(s![rows.clone(), ..]).to_owned(),
+                            &y.slice(s![rows, ..]).to_owned(),
+                            dropout.as_deref_mut(),
+                        );
+                        loss += example_loss;
+                        grads
+                    })
+                    .collect();
+                (
+                    loss / x.nrows().max(1) as f32,
+                    dp_sgd.privatize(&per_example),
+                )
             }
+            None => self.gradients(x, y, dropout),
+        };
+
+        if let Some(max_norm) = config.max_grad_norm {
+            clip_grad_norm(&mut grads, max_norm);
         }
+        let mut params = self.get_params();
+        if config.l2 > 0.0 {
+            add_l2_gradient(&mut grads, &params, &self.param_schema(), config.l2);
+        }
+        optimizer.step(&mut params, &grads, learning_rate);
+        self.set_params(&params)?;
+        Ok(loss)
+    }
 
-        for i in 0..hidden_size {
-            for j in 0..output_size {
-                w2[[i, j]] = rng.gen_range(-w2_bound..w2_bound);
+    // Mini-batch training over shuffled epochs, returning the number of steps taken.
+    // `seed` drives shuffling and dropout and should differ per node and round.
+    fn train(
+        &mut self,
+        x: &Array2<f32>,
+        y: &Array2<f32>,
+        config: &TrainingConfig,
+        optimizer: &mut Optimizer,
+        seed: u64,
+        dp_sgd: Option<&DpSgdConfig>,
+    ) -> Result<usize> {
+        let mut rng = seed::rng(seed, Stream::Dropout, 0);
+        let mut dp_sgd = dp_sgd.map(|config| DpSgd::new(config, seed));
+        let mut steps = 0;
+        for epoch in 0..config.epochs {
+            // Epoch `e` shuffles with `shuffle_seed + e`, mixed into the run's seed
+            let shuffle_seed = config.shuffle_seed.wrapping_add(epoch as u64);
+            let shuffle_seed = seed::derive(seed, Stream::Shuffle, shuffle_seed);
+            for (batch_x, batch_y) in epoch_batches(x, y, config.batch_size, shuffle_seed) {
+                let learning_rate = config.schedule.learning_rate(
+                    config.learning_rate,
+                    optimizer.steps(),
+                    config.warmup_steps,
+                );
+                self.train_step(
+                    &batch_x,
+                    &batch_y,
+                    config,
+                    optimizer,
+                    learning_rate,
+                    &mut rng,
+                    dp_sgd.as_mut(),
+                )?;
+                steps += 1;
             }
         }
+        Ok(steps)
+    }
 
-        SimpleModel {
-            w1,
-            b1,
-            w2,
-            b2,
-            learning_rate: 0.01,
-        }
+    // Layer spec other participants can use to build the same model
+    fn architecture(&self) -> Option<ModelSpec> {
+        None
     }
 
-    // Forward pass
-    pub fn forward(&self, x: &Array2<f32>) -> Array2<f32> {
-        // First layer
-        let z1 = x.dot(&self.w1) + &self.b1;
-        let a1 = z1.mapv(|v| if v > 0.0 { v } else { 0.0 }); // ReLU activation
+    // Serialize the model as an ONNX graph, if the architecture supports it
+    fn to_onnx(&self) -> Result<Vec<u8>> {
+        Err(anyhow::anyhow!(
+            "ONNX export is not supported for this model"
+        ))
+    }
+}
 
-        // Second layer
-        let z2 = a1.dot(&self.w2) + &self.b2;
+// Result of evaluating a model on a dataset
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EvalMetrics {
+    pub loss: f32,
+    // Only reported for classification losses
+    pub accuracy: Option<f32>,
+    // Root mean squared error, only reported for regression
+    #[serde(default)]
+    pub rmse: Option<f32>,
+    pub num_samples: usize,
+}
 
-        z2
+impl fmt::Display for EvalMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "loss {:.6}", self.loss)?;
+        if let Some(accuracy) = self.accuracy {
+            write!(f, ", accuracy {:.3}", accuracy)?;
+        }
+        if let Some(rmse) = self.rmse {
+            write!(f, ", rmse {:.6}", rmse)?;
+        }
+        write!(f, " on {} samples", self.num_samples)
     }
+}
 
-    // Backward pass and weights update
-    pub fn train(&mut self, x: &Array2<f32>, y: &Array2<f32>, epochs: usize) {
-        for _ in 0..epochs {
-            // Forward pass
-            let z1 = x.dot(&self.w1) + &self.b1;
-            let a1 = z1.mapv(|v| if v > 0.0 { v } else { 0.0 }); // ReLU activation
+// Sequential neural network of dense layers, built from a `ModelSpec`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SimpleModel {
+    // Architecture the tensors were built from
+    pub spec: ModelSpec,
+    // Layer weights: [fan_in, units] for each layer
+    pub weights: Vec<Array2<f32>>,
+    // Layer biases: [units] for each layer
+    pub biases: Vec<Array1<f32>>,
+}
 
-            let z2 = a1.dot(&self.w2) + &self.b2;
+impl SimpleModel {
+    // Create a new model with random initialization
+    pub fn new(spec: &ModelSpec, rng: &mut impl Rng) -> Self {
+        let mut weights = Vec::with_capacity(spec.layers.len());
+        let mut biases = Vec::with_capacity(spec.layers.len());
+        let mut fan_in = spec.input_size;
+
+        for layer in &spec.layers {
+            // Xavier or He initialization depending on the activation
+            let bound = layer.activation.init_bound(fan_in, layer.units);
+            let w = Array2::from_shape_fn((fan_in, layer.units), |_| rng.gen_range(-bound..bound));
+            weights.push(w);
+            biases.push(Array1::zeros(layer.units));
+            fan_in = layer.units;
+        }
 
-            // Backward pass
-            // Compute gradients
-            let dz2 = &z2 - y;
-            let dw2 = a1.t().dot(&dz2) * (1.0 / x.nrows() as f32);
-            let db2 = dz2.sum_axis(Axis(0)) * (1.0 / x.nrows() as f32);
+        SimpleModel {
+            spec: spec.clone(),
+            weights,
+            biases,
+        }
+    }
 
-            let da1 = dz2.dot(&self.w2.t());
-            let dz1 = da1 * z1.mapv(|v| if v > 0.0 { 1.0 } else { 0.0 }); // ReLU derivative
-            let dw1 = x.t().dot(&dz1) * (1.0 / x.nrows() as f32);
-            let db1 = dz1.sum_axis(Axis(0)) * (1.0 / x.nrows() as f32);
+    // Forward pass keeping pre-activations, activations and dropout masks of every
+    // layer for backprop. Only hidden layers are masked.
+    fn forward_cached(&self, x: &Array2<f32>, mut dropout: Option<&mut Dropout>) -> ForwardCache {
+        let mut zs = Vec::with_capacity(self.weights.len());
+        let mut activations = Vec::with_capacity(self.weights.len() + 1);
+        let mut masks = Vec::with_capacity(self.weights.len());
+        activations.push(x.clone());
+
+        let last = self.weights.len() - 1;
+        for (l, ((w, b), layer)) in self
+            .weights
+            .iter()
+            .zip(&self.biases)
+            .zip(&self.spec.layers)
+            .enumerate()
+        {
+            let z = activations[activations.len() - 1].dot(w) + b;
+            let mut a = layer.activation.apply(&z);
+            let mask = match dropout.as_deref_mut() {
+                Some(dropout) if l < last => Some(dropout.mask(a.dim())),
+                _ => None,
+            };
+            if let Some(mask) = &mask {
+                a *= mask;
+            }
+            activations.push(a);
+            masks.push(mask);
+            zs.push(z);
+        }
 
-            // Update parameters
-            self.w2 = &self.w2 - &(&dw2 * self.learning_rate);
-            self.b2 = &self.b2 - &(&db2 * self.learning_rate);
-            self.w1 = &self.w1 - &(&dw1 * self.learning_rate);
-            self.b1 = &self.b1 - &(&db1 * self.learning_rate);
+        ForwardCache {
+            zs,
+            activations,
+            masks,
         }
     }
 
@@ -100,76 +255,199 @@ impl SimpleModel {
     pub fn to_params_vec(&self) -> Vec<f32> {
         let mut params = Vec::new();
 
-        // Add w1
-        params.extend(self.w1.iter());
-
-        // Add b1
-        params.extend(self.b1.iter());
-
-        // Add w2
-        params.extend(self.w2.iter());
-
-        // Add b2
-        params.extend(self.b2.iter());
+        // Weights then bias of each layer
+        for (w, b) in self.weights.iter().zip(&self.biases) {
+            params.extend(w.iter());
+            params.extend(b.iter());
+        }
 
         params
     }
 
     // Update model from parameters vector
-    pub fn from_params_vec(&mut self, params: &[f32]) -> Result<()> {
+    pub fn set_params_vec(&mut self, params: &[f32]) -> Result<()> {
+        let expected: usize = self
+            .weights
+            .iter()
+            .zip(&self.biases)
+            .map(|(w, b)| w.len() + b.len())
+            .sum();
+        if params.len() != expected {
+            return Err(anyhow::anyhow!(
+                "Expected {} parameters, got {}",
+                expected,
+                params.len()
+            ));
+        }
+
         let mut offset = 0;
+        for (w, b) in self.weights.iter_mut().zip(self.biases.iter_mut()) {
+            // Update weights
+            let w_size = w.len();
+            for (dst, src) in w.iter_mut().zip(&params[offset..offset + w_size]) {
+                *dst = *src;
+            }
+            offset += w_size;
 
-        // Update w1
-        let w1_size = self.w1.len();
-        for i in 0..self.w1.shape()[0] {
-            for j in 0..self.w1.shape()[1] {
-                let idx = i * self.w1.shape()[1] + j;
-                if idx < w1_size && offset + idx < params.len() {
-                    self.w1[[i, j]] = params[offset + idx];
-                }
+            // Update bias
+            let b_size = b.len();
+            for (dst, src) in b.iter_mut().zip(&params[offset..offset + b_size]) {
+                *dst = *src;
             }
+            offset += b_size;
         }
-        offset += w1_size;
 
-        // Update b1
-        let b1_size = self.b1.len();
-        for i in 0..b1_size {
-            if offset + i < params.len() {
-                self.b1[i] = params[offset + i];
+        Ok(())
+    }
+}
+
+impl FederatedModel for SimpleModel {
+    fn forward(&self, x: &Array2<f32>) -> Array2<f32> {
+        let cache = self.forward_cached(x, None);
+        self.spec
+            .loss
+            .output(&cache.activations[cache.activations.len() - 1])
+    }
+
+    // Backward pass
+    fn gradients(
+        &self,
+        x: &Array2<f32>,
+        y: &Array2<f32>,
+        dropout: Option<&mut Dropout>,
+    ) -> (f32, Vec<f32>) {
+        // Forward pass
+        let ForwardCache {
+            zs,
+            activations,
+            masks,
+        } = self.forward_cached(x, dropout);
+        let output = self.spec.loss.output(&activations[activations.len() - 1]);
+        let loss = self.spec.loss.loss(&output, y);
+
+        // Walk from the output layer down to the first layer; the loss gradient
+        // already averages over the batch
+        let mut layer_grads = Vec::with_capacity(self.weights.len());
+        let mut grad = self.spec.loss.gradient(&output, y);
+        for l in (0..self.weights.len()).rev() {
+            // Dropped units pass no gradient back
+            if let Some(mask) = &masks[l] {
+                grad *= mask;
             }
+            let dz = grad * self.spec.layers[l].activation.derivative(&zs[l]);
+            let dw = activations[l].t().dot(&dz);
+            let db = dz.sum_axis(Axis(0));
+            grad = dz.dot(&self.weights[l].t());
+            layer_grads.push((dw, db));
         }
-        offset += b1_size;
 
-        // Update w2
-        let w2_size = self.w2.len();
-        for i in 0..self.w2.shape()[0] {
-            for j in 0..self.w2.shape()[1] {
-                let idx = i * self.w2.shape()[1] + j;
-                if idx < w2_size && offset + idx < params.len() {
-                    self.w2[[i, j]] = params[offset + idx];
-                }
-            }
+        // Flatten in parameter order: weights then bias of each layer
+        let mut grads = Vec::with_capacity(self.to_params_vec().len());
+        for (dw, db) in layer_grads.iter().rev() {
+            grads.extend(dw.iter());
+            grads.extend(db.iter());
         }
-        offset += w2_size;
 
-        // Update b2
-        let b2_size = self.b2.len();
-        for i in 0..b2_size {
-            if offset + i < params.len() {
-                self.b2[i] = params[offset + i];
-            }
+        (loss, grads)
+    }
+
+    fn get_params(&self) -> Vec<f32> {
+        self.to_params_vec()
+    }
+
+    fn set_params(&mut self, params: &[f32]) -> Result<()> {
+        self.set_params_vec(params)
+    }
+
+    // Tensors are named w1, b1, w2, b2, ... in layer order
+    fn param_schema(&self) -> Vec<TensorSpec> {
+        self.weights
+            .iter()
+            .zip(&self.biases)
+            .enumerate()
+            .flat_map(|(i, (w, b))| {
+                [
+                    TensorSpec::new(&format!("w{}", i + 1), w.shape()),
+                    TensorSpec::new(&format!("b{}", i + 1), b.shape()),
+                ]
+            })
+            .collect()
+    }
+
+    fn input_size(&self) -> usize {
+        self.spec.input_size
+    }
+
+    fn output_size(&self) -> usize {
+        self.spec.output_size()
+    }
+
+    fn loss(&self) -> Loss {
+        self.spec.loss
+    }
+
+    fn evaluate(&self, x: &Array2<f32>, y: &Array2<f32>) -> EvalMetrics {
+        let predictions = self.forward(x);
+        EvalMetrics {
+            loss: self.spec.loss.loss(&predictions, y),
+            accuracy: self.spec.loss.accuracy(&predictions, y),
+            rmse: self.spec.loss.rmse(&predictions, y),
+            num_samples: x.nrows(),
         }
+    }
 
-        Ok(())
+    fn architecture(&self) -> Option<ModelSpec> {
+        Some(self.spec.clone())
+    }
+
+    fn to_onnx(&self) -> Result<Vec<u8>> {
+        crate::onnx::export_model(self)
+    }
+}
+
+// Intermediate values of a forward pass needed by backprop
+struct ForwardCache {
+    zs: Vec<Array2<f32>>,
+    activations: Vec<Array2<f32>>,
+    // Dropout mask applied to each layer's activation, if any
+    masks: Vec<Option<Array2<f32>>>,
+}
+
+// Name and shape of a single model tensor
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TensorSpec {
+    pub name: String,
+    pub shape: Vec<usize>,
+}
+
+impl TensorSpec {
+    pub fn new(name: &str, shape: &[usize]) -> Self {
+        Self {
+            name: name.to_string(),
+            shape: shape.to_vec(),
+        }
+    }
+
+    // Number of scalar values in the tensor
+    pub fn num_values(&self) -> usize {
+        self.shape.iter().product()
     }
 }
 
 // Type alias for a thread-safe model
-pub type SharedModel = Arc<Mutex<SimpleModel>>;
+pub type SharedModel = Arc<Mutex<Box<dyn FederatedModel>>>;
+
+// Build the built-in sequential model for an architecture, initialized from `seed`
+pub fn build_model(spec: &ModelSpec, seed: u64) -> Box<dyn FederatedModel> {
+    Box::new(SimpleModel::new(
+        spec,
+        &mut seed::rng(seed, Stream::Init, 0),
+    ))
+}
 
-// Create a new shared model
-pub fn build_model() -> SharedModel {
-    Arc::new(Mutex::new(SimpleModel::new(10, 64, 1)))
+// Wrap any model implementation for use by the actors
+pub fn share_model(model: Box<dyn FederatedModel>) -> SharedModel {
+    Arc::new(Mutex::new(model))
 }
 
 // Extract parameters from a model
@@ -177,50 +455,173 @@ pub fn extract_params(model: &SharedModel) -> Result<Vec<f32>> {
     let model_lock = model
         .lock()
         .map_err(|e| anyhow::anyhow!("Failed to lock model: {}", e))?;
-    Ok(model_lock.to_params_vec())
+    Ok(model_lock.get_params())
 }
 
-// Update model with parameters
-pub fn update_model(model: &SharedModel, params: &[f32]) -> Result<()> {
-    let mut model_lock = model
+// Get the parameter schema of a model
+pub fn param_schema(model: &SharedModel) -> Result<Vec<TensorSpec>> {
+    let model_lock = model
         .lock()
         .map_err(|e| anyhow::anyhow!("Failed to lock model: {}", e))?;
-    model_lock.from_params_vec(params)
+    Ok(model_lock.param_schema())
 }
 
-// Generate some synthetic data for training (only for demo purposes)
-pub fn generate_data(samples: usize) -> (Vec<f32>, Vec<f32>) {
-    let mut rng = rand::thread_rng();
+// Rows per forward pass when serving predictions
+pub const PREDICT_BATCH_SIZE: usize = 256;
 
-    let mut data = Vec::with_capacity(samples * 10);
-    let mut labels = Vec::with_capacity(samples);
+// Why a prediction request could not be served
+#[derive(Debug)]
+pub enum PredictError {
+    // The request does not fit the model's input shape
+    InvalidInput(String),
+    Internal(String),
+}
 
-    for _ in 0..samples {
-        // Generate 10 features
-        for _ in 0..10 {
-            data.push(rng.gen_range(-1.0..1.0));
+impl fmt::Display for PredictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PredictError::InvalidInput(msg) | PredictError::Internal(msg) => write!(f, "{}", msg),
         }
+    }
+}
+
+// Run inference on a batch of feature rows, `PREDICT_BATCH_SIZE` rows per forward pass
+pub fn predict(model: &SharedModel, inputs: &[Vec<f32>]) -> Result<Vec<Vec<f32>>, PredictError> {
+    let model = model
+        .lock()
+        .map_err(|e| PredictError::Internal(format!("Failed to lock model: {}", e)))?;
 
-        // Simple function to generate label: sum of first 3 features
-        let x = &data[data.len() - 10..];
-        labels.push(x[0] + x[1] + x[2]);
+    let features = model.input_size();
+    if inputs.is_empty() {
+        return Err(PredictError::InvalidInput("No input rows".to_string()));
+    }
+    for (i, row) in inputs.iter().enumerate() {
+        if row.len() != features {
+            return Err(PredictError::InvalidInput(format!(
+                "Row {} has {} features, expected {}",
+                i,
+                row.len(),
+                features
+            )));
+        }
+        if row.iter().any(|v| !v.is_finite()) {
+            return Err(PredictError::InvalidInput(format!(
+                "Row {} contains a non-finite value",
+                i
+            )));
+        }
     }
 
-    (data, labels)
+    let mut predictions = Vec::with_capacity(inputs.len());
+    for chunk in inputs.chunks(PREDICT_BATCH_SIZE) {
+        let x = Array2::from_shape_vec((chunk.len(), features), chunk.concat())
+            .map_err(|e| PredictError::Internal(format!("Failed to reshape input: {}", e)))?;
+        predictions.extend(model.forward(&x).outer_iter().map(|row| row.to_vec()));
+    }
+    Ok(predictions)
 }
 
-// Convert raw data vectors to ndarray format
-pub fn prepare_data(data: &[f32], labels: &[f32]) -> (Array2<f32>, Array2<f32>) {
-    let batch_size = labels.len();
-    let features = 10;
+// Update model with parameters
+pub fn update_model(model: &SharedModel, params: &[f32]) -> Result<()> {
+    let mut model_lock = model
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Failed to lock model: {}", e))?;
+    model_lock.set_params(params)
+}
+
+// Convert raw data vectors to ndarray format for a model. Categorical models take one
+// class index per sample, other models take `output_size` targets per sample.
+pub fn prepare_data(
+    data: &[f32],
+    labels: &[f32],
+    model: &dyn FederatedModel,
+) -> Result<(Array2<f32>, Array2<f32>)> {
+    let features = model.input_size();
+    let outputs = model.output_size();
+    if !data.len().is_multiple_of(features) {
+        return Err(anyhow::anyhow!(
+            "Data length {} is not a multiple of {} features",
+            data.len(),
+            features
+        ));
+    }
+    let batch_size = data.len() / features;
 
     // Reshape data to [batch_size, features]
-    let x = Array2::from_shape_vec((batch_size, features), data.to_vec())
-        .expect("Failed to reshape data");
+    let x = Array2::from_shape_vec((batch_size, features), data.to_vec())?;
+
+    let y = if model.loss() == Loss::CategoricalCrossEntropy {
+        if labels.len() != batch_size {
+            return Err(anyhow::anyhow!(
+                "Expected {} class labels, got {}",
+                batch_size,
+                labels.len()
+            ));
+        }
 
-    // Reshape labels to [batch_size, 1]
-    let y =
-        Array2::from_shape_vec((batch_size, 1), labels.to_vec()).expect("Failed to reshape labels");
+        // One-hot encode class indices to [batch_size, classes]
+        let mut y = Array2::zeros((batch_size, outputs));
+        for (i, &label) in labels.iter().enumerate() {
+            let class = label as usize;
+            if label < 0.0 || label.fract() != 0.0 || class >= outputs {
+                return Err(anyhow::anyhow!(
+                    "Label {} is not a class index below {}",
+                    label,
+                    outputs
+                ));
+            }
+            y[[i, class]] = 1.0;
+        }
+        y
+    } else {
+        // Reshape labels to [batch_size, outputs]
+        if labels.len() != batch_size * outputs {
+            return Err(anyhow::anyhow!(
+                "Expected {} labels, got {}",
+                batch_size * outputs,
+                labels.len()
+            ));
+        }
+        Array2::from_shape_vec((batch_size, outputs), labels.to_vec())?
+    };
+
+    Ok((x, y))
+}
 
-    (x, y)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::{self, Stream};
+    use rand_distr::{Distribution, Uniform};
+
+    #[test]
+    fn backward_pass_matches_finite_differences() {
+        let spec = ModelSpec::parse(4, "4:gelu,3:tanh,1:sigmoid", Loss::Mse).unwrap();
+        let mut model = build_model(&spec, 3);
+        let mut rng = seed::rng(3, Stream::Data, 0);
+        let uniform = Uniform::new(-1.0f32, 1.0);
+        let x = Array2::from_shape_fn((5, 4), |_| uniform.sample(&mut rng));
+        let y = Array2::from_shape_fn((5, 1), |_| uniform.sample(&mut rng).abs());
+
+        let (_, analytic) = model.gradients(&x, &y, None);
+        let params = model.get_params();
+        assert_eq!(analytic.len(), params.len());
+        let h = 1e-2;
+        for (i, analytic) in analytic.iter().enumerate() {
+            let mut loss_at = |delta: f32| {
+                let mut shifted = params.clone();
+                shifted[i] += delta;
+                model.set_params(&shifted).unwrap();
+                model.evaluate(&x, &y).loss
+            };
+            let numeric = (loss_at(h) - loss_at(-h)) / (2.0 * h);
+            assert!(
+                (numeric - analytic).abs() < 1e-3,
+                "parameter {}: numeric {} vs analytic {}",
+                i,
+                numeric,
+                analytic
+            );
+        }
+    }
 }
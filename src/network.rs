@@ -1,10 +1,28 @@
-use crate::messages::{GetModelParams, GetNodesRequest, NodeMessage};
+use crate::auth::{AuthError, Enrollment};
+use crate::layers::ModelSpec;
+use crate::limits::RequestLimiter;
+use crate::messages::{
+    ControlRound, GetGlobalModel, GetHistory, GetMetrics, GetModelOnnx, GetModelSafetensors,
+    GetModelSpec, GetNodeStatus, GetNodesRequest, GetPrivacy, GetRoundConfig, NodeMessage,
+    PredictBatch, ServerMessage, SetRoundConfig,
+};
+use crate::model::PredictError;
 use crate::node::NodeActor;
-use crate::server::CentralServer;
+use crate::rbac::Role;
+use crate::screening::NodeReputation;
+use crate::secagg::SecAggMessage;
+use crate::server::{CentralServer, RoundControl};
+use crate::signing;
+use crate::tls::{self, PeerIdentity};
+use crate::training::TrainingConfig;
+use crate::validation::UpdateRejection;
 use actix::Addr;
-use actix_web::{web, HttpResponse, Responder};
-use log::{error, info};
+use actix_web::http::header;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use ed25519_dalek::VerifyingKey;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 // Handler for receiving messages at nodes
 pub async fn receive_node_message(
@@ -30,12 +48,103 @@ pub async fn receive_node_message(
 
 // Handler for receiving messages at server
 pub async fn receive_server_message(
+    req: HttpRequest,
     msg: web::Json<NodeMessage>,
     server: web::Data<Addr<CentralServer>>,
+    enrollment: Option<web::Data<Enrollment>>,
+    limiter: web::Data<RequestLimiter>,
 ) -> impl Responder {
     info!("Server received message: {:?}", msg.0);
 
-    match server.send(msg.0).await {
+    // With enrollment every message must carry the credential of the node it names
+    let mut authenticated = false;
+    if let Some(enrollment) = enrollment {
+        let result = enrollment
+            .authenticate(bearer(&req))
+            .and_then(|(node_addr, key)| match msg.0.sender() {
+                Some(sender) if sender == node_addr => verify_signature(&msg.0, &key),
+                _ => Err(AuthError::WrongNode),
+            });
+        if let Err(e) = result {
+            warn!(
+                "Refused message from {}: {}",
+                msg.0.sender().unwrap_or("an unnamed sender"),
+                e
+            );
+            return auth_error(e);
+        }
+        authenticated = true;
+    }
+
+    // With mutual TLS a node may only speak for the host named in its certificate
+    if let Some(PeerIdentity(identity)) = req.conn_data::<PeerIdentity>() {
+        let sender = msg.0.sender().map(tls::host);
+        if sender != Some(identity.as_str()) {
+            error!(
+                "Client certificate {} cannot send as {}",
+                identity,
+                msg.0.sender().unwrap_or("an unnamed sender")
+            );
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Client certificate {} does not match the sender", identity)
+            }));
+        }
+        authenticated = true;
+    }
+
+    // Throttle every message per authenticated node, or else per IP address, before it reaches the server actor
+    let peer = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    if let Err(retry_after) = limiter.check(&msg.0, &peer, authenticated) {
+        warn!(
+            "Rate limited message from {}",
+            msg.0.sender().unwrap_or(&peer)
+        );
+        limiter.record_rejection("rate_limited");
+        let retry_after = retry_after.as_secs_f64().ceil() as u64;
+        return HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .json(serde_json::json!({
+                "status": "error",
+                "message": format!("Too many messages, retry after {} s", retry_after)
+            }));
+    }
+
+    // Model updates go through validation and report rejections in a structured form
+    let msg = match ServerMessage::from_node_message(msg.into_inner()) {
+        Ok(update) => {
+            return match server.send(update).await {
+                Ok(Ok(())) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+                Ok(Err(rejection)) => {
+                    error!("Rejected model update: {}", rejection);
+                    // A second update in the same round is refused like a rate limit, without a retry time
+                    let mut response = match rejection {
+                        UpdateRejection::DuplicateUpdate { .. } => HttpResponse::TooManyRequests(),
+                        UpdateRejection::TrainingFinished | UpdateRejection::StaleRound { .. } => {
+                            HttpResponse::Conflict()
+                        }
+                        _ => HttpResponse::UnprocessableEntity(),
+                    };
+                    response.json(serde_json::json!({
+                        "status": "error",
+                        "message": rejection.to_string(),
+                        "error": rejection
+                    }))
+                }
+                Err(e) => {
+                    error!("Server actor mailbox error: {}", e);
+                    HttpResponse::InternalServerError()
+                        .json(serde_json::json!({"status": "error", "message": e.to_string()}))
+                }
+            };
+        }
+        Err(msg) => msg,
+    };
+
+    match server.send(msg).await {
         Ok(Ok(())) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
         Ok(Err(e)) => {
             error!("Error handling message at server: {}", e);
@@ -55,23 +164,16 @@ pub async fn receive_server_message(
 pub struct NodeStatus {
     pub address: String,
     pub status: String,
+    // Epsilon the node last reported from local DP-SGD
+    pub epsilon: Option<f64>,
+    // Reputation and findings of update screening, if enabled
+    pub screening: Option<NodeReputation>,
 }
 
-// Handler for getting node status
+// Handler for getting node status, including local training steps
 pub async fn get_node_status(actor: web::Data<Addr<NodeActor>>) -> impl Responder {
-    match actor
-        .send(NodeMessage::Predict {
-            data: vec![0.0; 10],
-        })
-        .await
-    {
-        Ok(_) => {
-            let node_status = NodeStatus {
-                address: "active".to_string(),
-                status: "running".to_string(),
-            };
-            HttpResponse::Ok().json(node_status)
-        }
+    match actor.send(GetNodeStatus).await {
+        Ok(node_status) => HttpResponse::Ok().json(node_status),
         Err(e) => {
             error!("Node status error: {}", e);
             HttpResponse::InternalServerError().json(serde_json::json!({
@@ -124,15 +226,25 @@ pub async fn get_all_nodes(server: web::Data<Addr<CentralServer>>) -> impl Respo
 
 // Handler for getting model parameters
 pub async fn get_model_params(server: web::Data<Addr<CentralServer>>) -> impl Responder {
-    match server.send(GetModelParams).await {
-        Ok(Ok(params)) => {
+    match server.send(GetGlobalModel).await {
+        Ok(Ok(NodeMessage::UpdateModel {
+            params,
+            round,
+            signature,
+        })) => {
             info!("Returning model parameters, size: {}", params.len());
             HttpResponse::Ok().json(serde_json::json!({
                 "status": "success",
+                "size": params.len(),
                 "parameters": params,
-                "size": params.len()
+                "round": round,
+                "signature": signature
             }))
         }
+        Ok(Ok(_)) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": "Unexpected reply from server actor"
+        })),
         Ok(Err(e)) => {
             error!("Failed to get model parameters: {}", e);
             HttpResponse::InternalServerError().json(serde_json::json!({
@@ -149,3 +261,532 @@ pub async fn get_model_params(server: web::Data<Addr<CentralServer>>) -> impl Re
         }
     }
 }
+
+// Handler for getting the architecture nodes must build
+pub async fn get_model_spec(server: web::Data<Addr<CentralServer>>) -> impl Responder {
+    match server.send(GetModelSpec).await {
+        Ok(Some(spec)) => HttpResponse::Ok().json(spec),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": "The global model does not expose a layer spec"
+        })),
+        Err(e) => {
+            error!("Failed to communicate with server actor: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to communicate with server: {}", e)
+            }))
+        }
+    }
+}
+
+// Handler for downloading the global model as a safetensors file
+pub async fn get_model_safetensors(server: web::Data<Addr<CentralServer>>) -> impl Responder {
+    model_download(
+        server.send(GetModelSafetensors).await,
+        "global_model.safetensors",
+    )
+}
+
+// Handler for downloading the global model as an ONNX graph
+pub async fn get_model_onnx(server: web::Data<Addr<CentralServer>>) -> impl Responder {
+    model_download(server.send(GetModelOnnx).await, "global_model.onnx")
+}
+
+// Turn an exported model into a file download response
+fn model_download(
+    result: Result<Result<Vec<u8>, String>, actix::MailboxError>,
+    filename: &str,
+) -> HttpResponse {
+    match result {
+        Ok(Ok(bytes)) => {
+            info!("Returning {}, {} bytes", filename, bytes.len());
+            HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .insert_header((
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{}\"", filename),
+                ))
+                .body(bytes)
+        }
+        Ok(Err(e)) => {
+            error!("Failed to export model: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": e
+            }))
+        }
+        Err(e) => {
+            error!("Failed to communicate with server actor: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to communicate with server: {}", e)
+            }))
+        }
+    }
+}
+
+// Fetch the architecture of the global model from the server
+pub async fn fetch_model_spec(
+    server_addr: &str,
+    bearer: Option<&str>,
+) -> anyhow::Result<ModelSpec> {
+    let url = format!("{}/api/model/spec", server_addr);
+    let mut request = tls::http_client().get(&url);
+    if let Some(token) = bearer {
+        request = request.bearer_auth(token);
+    }
+    let mut response = request
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to reach {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("{} returned {}", url, response.status()));
+    }
+    let spec: ModelSpec = response
+        .json()
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid response from {}: {}", url, e))?;
+    spec.validate()
+        .map_err(|e| anyhow::anyhow!("Invalid model architecture from {}: {}", url, e))?;
+    Ok(spec)
+}
+
+// Fetch the current global model parameters from the server
+pub async fn fetch_global_params(
+    server_addr: &str,
+    bearer: Option<&str>,
+) -> anyhow::Result<NodeMessage> {
+    let url = format!("{}/api/model/params", server_addr);
+    let mut request = tls::http_client().get(&url);
+    if let Some(token) = bearer {
+        request = request.bearer_auth(token);
+    }
+    let mut response = request
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to reach {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("{} returned {}", url, response.status()));
+    }
+    let body: serde_json::Value = response
+        .json()
+        .limit(64 * 1024 * 1024)
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid response from {}: {}", url, e))?;
+    let params = serde_json::from_value(body["parameters"].clone())
+        .map_err(|e| anyhow::anyhow!("Invalid parameters from {}: {}", url, e))?;
+    // The node checks the signature before applying the model
+    Ok(NodeMessage::UpdateModel {
+        params,
+        round: body["round"].as_u64().unwrap_or(0),
+        signature: body["signature"].as_str().map(str::to_string),
+    })
+}
+
+// Handler for getting the training settings sent with each round
+pub async fn get_round_config(server: web::Data<Addr<CentralServer>>) -> impl Responder {
+    match server.send(GetRoundConfig).await {
+        Ok(config) => HttpResponse::Ok().json(serde_json::json!({ "training": config })),
+        Err(e) => {
+            error!("Failed to communicate with server actor: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to communicate with server: {}", e)
+            }))
+        }
+    }
+}
+
+// Handler for changing the training settings of the following rounds (null clears them)
+pub async fn set_round_config(
+    req: HttpRequest,
+    config: web::Json<Option<TrainingConfig>>,
+    server: web::Data<Addr<CentralServer>>,
+    enrollment: Option<web::Data<Enrollment>>,
+) -> impl Responder {
+    if let Some(enrollment) = enrollment {
+        if let Err(e) = authorize_admin(&req, &enrollment) {
+            return auth_error(e);
+        }
+    }
+    let config = config.into_inner();
+    if let Some(Err(e)) = config.as_ref().map(TrainingConfig::validate) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Invalid training config: {}", e)
+        }));
+    }
+
+    match server.send(SetRoundConfig(config)).await {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => {
+            error!("Failed to communicate with server actor: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to communicate with server: {}", e)
+            }))
+        }
+    }
+}
+
+// Handler for starting more rounds, restarting the current round or stopping training
+pub async fn control_round(
+    req: HttpRequest,
+    action: web::Json<RoundControl>,
+    server: web::Data<Addr<CentralServer>>,
+    enrollment: Option<web::Data<Enrollment>>,
+) -> impl Responder {
+    if let Some(enrollment) = enrollment {
+        if let Err(e) = authorize_admin(&req, &enrollment) {
+            return auth_error(e);
+        }
+    }
+    match server.send(ControlRound(action.into_inner())).await {
+        Ok(Ok(progress)) => HttpResponse::Ok().json(progress),
+        Ok(Err(e)) => HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": e
+        })),
+        Err(e) => {
+            error!("Failed to communicate with server actor: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to communicate with server: {}", e)
+            }))
+        }
+    }
+}
+
+// Body of a prediction request: one feature row per sample
+#[derive(Deserialize)]
+pub struct PredictRequest {
+    pub inputs: Vec<Vec<f32>>,
+}
+
+// Handler for predictions with a node's local model
+pub async fn predict_on_node(
+    request: web::Json<PredictRequest>,
+    actor: web::Data<Addr<NodeActor>>,
+) -> impl Responder {
+    prediction_response(actor.send(PredictBatch(request.into_inner().inputs)).await)
+}
+
+// Handler for predictions with the global model
+pub async fn predict_on_server(
+    request: web::Json<PredictRequest>,
+    server: web::Data<Addr<CentralServer>>,
+) -> impl Responder {
+    prediction_response(server.send(PredictBatch(request.into_inner().inputs)).await)
+}
+
+// Turn a prediction result into a JSON response
+fn prediction_response(
+    result: Result<Result<Vec<Vec<f32>>, PredictError>, actix::MailboxError>,
+) -> HttpResponse {
+    match result {
+        Ok(Ok(predictions)) => {
+            HttpResponse::Ok().json(serde_json::json!({ "predictions": predictions }))
+        }
+        Ok(Err(PredictError::InvalidInput(e))) => {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": e
+            }))
+        }
+        Ok(Err(PredictError::Internal(e))) => {
+            error!("Prediction failed: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": e
+            }))
+        }
+        Err(e) => {
+            error!("Actor mailbox error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error", "message": e.to_string()}))
+        }
+    }
+}
+
+// Handler for the per-round federated evaluation history
+pub async fn get_history(server: web::Data<Addr<CentralServer>>) -> impl Responder {
+    match server.send(GetHistory).await {
+        Ok(rounds) => HttpResponse::Ok().json(serde_json::json!({ "rounds": rounds })),
+        Err(e) => {
+            error!("Failed to get evaluation history: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get evaluation history: {}", e)
+            }))
+        }
+    }
+}
+
+// Handler for getting the privacy budget spent by DP-FedAvg
+pub async fn get_privacy(server: web::Data<Addr<CentralServer>>) -> impl Responder {
+    match server.send(GetPrivacy).await {
+        Ok(Some(report)) => HttpResponse::Ok().json(serde_json::json!({
+            "enabled": true,
+            "privacy": report
+        })),
+        Ok(None) => HttpResponse::Ok().json(serde_json::json!({ "enabled": false })),
+        Err(e) => {
+            error!("Failed to get privacy report: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get privacy report: {}", e)
+            }))
+        }
+    }
+}
+
+// Handler for getting server metrics
+pub async fn get_metrics(
+    server: web::Data<Addr<CentralServer>>,
+    limiter: web::Data<RequestLimiter>,
+) -> impl Responder {
+    match server.send(GetMetrics).await {
+        Ok(mut metrics) => {
+            metrics.refusals_by_reason = limiter.rejections();
+            metrics.messages_refused = metrics.refusals_by_reason.values().sum();
+            HttpResponse::Ok().json(metrics)
+        }
+        Err(e) => {
+            error!("Failed to get server metrics: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get server metrics: {}", e)
+            }))
+        }
+    }
+}
+
+// Check the signature of an update against the key the node registered on enrollment
+fn verify_signature(msg: &NodeMessage, key: &VerifyingKey) -> Result<(), AuthError> {
+    let valid = match msg {
+        NodeMessage::SubmitUpdate {
+            node_addr,
+            round,
+            params,
+            signature,
+            ..
+        } => signing::verify_update(
+            key,
+            *round,
+            node_addr,
+            signing::params_hash(params),
+            signature.as_deref(),
+        ),
+        NodeMessage::SecAgg(msg) => match msg.as_ref() {
+            SecAggMessage::MaskedInput {
+                node_addr,
+                round,
+                masked,
+                signature,
+            } => signing::verify_update(
+                key,
+                *round,
+                node_addr,
+                signing::masked_hash(masked),
+                signature.as_deref(),
+            ),
+            _ => true,
+        },
+        _ => true,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(AuthError::BadSignature)
+    }
+}
+
+// Bearer token of a request's Authorization header
+pub fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+// Accept callers the access control middleware admitted as admins, or the ADMIN_TOKEN
+fn authorize_admin(req: &HttpRequest, enrollment: &Enrollment) -> Result<(), AuthError> {
+    if req.extensions().get::<Role>() == Some(&Role::Admin) {
+        return Ok(());
+    }
+    enrollment.authorize_admin(bearer(req))
+}
+
+// Turn an authentication failure into a 401, or a 403 for a token of another node
+fn auth_error(e: AuthError) -> HttpResponse {
+    let mut response = match e {
+        AuthError::WrongNode => HttpResponse::Forbidden(),
+        _ => HttpResponse::Unauthorized(),
+    };
+    response.json(serde_json::json!({"status": "error", "message": e.to_string()}))
+}
+
+fn enrollment_disabled() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "status": "error",
+        "message": "Node enrollment is not enabled on this server"
+    }))
+}
+
+// Body of a join token request
+#[derive(Deserialize)]
+pub struct JoinTokenRequest {
+    // Restrict the token to one node address
+    #[serde(default)]
+    pub node_addr: Option<String>,
+    // Validity in seconds, instead of the configured default
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+// Handler for issuing a join token (admin)
+pub async fn create_join_token(
+    req: HttpRequest,
+    body: web::Json<JoinTokenRequest>,
+    enrollment: Option<web::Data<Enrollment>>,
+) -> impl Responder {
+    let Some(enrollment) = enrollment else {
+        return enrollment_disabled();
+    };
+    if let Err(e) = authorize_admin(&req, &enrollment) {
+        return auth_error(e);
+    }
+    let body = body.into_inner();
+    let (token, join_token) =
+        enrollment.issue_join_token(body.node_addr, body.ttl_secs.map(Duration::from_secs));
+    info!(
+        "Issued a join token for {}, valid until {}",
+        join_token.node_addr.as_deref().unwrap_or("any node"),
+        join_token.expires_at
+    );
+    HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "node_addr": join_token.node_addr,
+        "expires_at": join_token.expires_at
+    }))
+}
+
+// Handler for listing issued credentials (admin)
+pub async fn list_credentials(
+    req: HttpRequest,
+    enrollment: Option<web::Data<Enrollment>>,
+) -> impl Responder {
+    let Some(enrollment) = enrollment else {
+        return enrollment_disabled();
+    };
+    match authorize_admin(&req, &enrollment) {
+        Ok(()) => HttpResponse::Ok().json(enrollment.credentials()),
+        Err(e) => auth_error(e),
+    }
+}
+
+// Handler for revoking a credential (admin)
+pub async fn revoke_credential(
+    req: HttpRequest,
+    id: web::Path<String>,
+    enrollment: Option<web::Data<Enrollment>>,
+) -> impl Responder {
+    let Some(enrollment) = enrollment else {
+        return enrollment_disabled();
+    };
+    if let Err(e) = authorize_admin(&req, &enrollment) {
+        return auth_error(e);
+    }
+    match enrollment.revoke(&id) {
+        Some(info) => {
+            info!("Revoked credential {} of node {}", info.id, info.node_addr);
+            HttpResponse::Ok().json(info)
+        }
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("No credential {}", id)
+        })),
+    }
+}
+
+// Body of an enrollment request
+#[derive(Serialize, Deserialize)]
+pub struct EnrollRequest {
+    pub token: String,
+    pub node_addr: String,
+    // Hex-encoded ed25519 key the node will sign its updates with
+    pub public_key: String,
+}
+
+// Credential returned on enrollment, sent as a bearer token with every message
+#[derive(Serialize, Deserialize)]
+pub struct EnrollResponse {
+    pub credential: String,
+    pub credential_id: String,
+    // Hex-encoded ed25519 key the server signs global models with
+    pub server_key: String,
+}
+
+// Handler for exchanging a join token for a credential
+pub async fn enroll_node(
+    body: web::Json<EnrollRequest>,
+    enrollment: Option<web::Data<Enrollment>>,
+) -> impl Responder {
+    let Some(enrollment) = enrollment else {
+        return enrollment_disabled();
+    };
+    match enrollment.enroll(&body.token, &body.node_addr, &body.public_key) {
+        Ok((credential, info)) => {
+            info!(
+                "Enrolled node {} with credential {}",
+                info.node_addr, info.id
+            );
+            HttpResponse::Ok().json(EnrollResponse {
+                credential,
+                credential_id: info.id,
+                server_key: signing::encode_verifying_key(
+                    &enrollment.signing_key().verifying_key(),
+                ),
+            })
+        }
+        Err(e) => {
+            warn!("Refused enrollment of {}: {}", body.node_addr, e);
+            auth_error(e)
+        }
+    }
+}
+
+// Exchange a join token for a credential at the server, registering the node's update key
+pub async fn enroll(
+    server_addr: &str,
+    token: &str,
+    node_addr: &str,
+    public_key: &VerifyingKey,
+) -> anyhow::Result<EnrollResponse> {
+    let url = format!("{}/api/enroll", server_addr);
+    let mut response = tls::http_client()
+        .post(&url)
+        .send_json(&EnrollRequest {
+            token: token.to_string(),
+            node_addr: node_addr.to_string(),
+            public_key: signing::encode_verifying_key(public_key),
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to reach {}: {}", url, e))?;
+    if !response.status().is_success() {
+        let body = response.body().await.unwrap_or_default();
+        return Err(anyhow::anyhow!(
+            "Enrollment refused ({}): {}",
+            response.status(),
+            String::from_utf8_lossy(&body)
+        ));
+    }
+    let enrolled: EnrollResponse = response
+        .json()
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid response from {}: {}", url, e))?;
+    info!("Enrolled with credential {}", enrolled.credential_id);
+    Ok(enrolled)
+}
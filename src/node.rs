@@ -1,14 +1,76 @@
-use crate::messages::{NodeMessage, ServerMessage};
-use crate::model::{build_model, extract_params, prepare_data, update_model, SharedModel};
+use crate::dataset::LocalDataset;
+use crate::layers::ModelSpec;
+use crate::messages::{GetNodeStatus, LoadDataset, NodeMessage, PredictBatch, SetArchitecture};
+use crate::model::{
+    build_model, extract_params, predict, prepare_data, share_model, update_model, FederatedModel,
+    PredictError, SharedModel,
+};
+use crate::optim::Optimizer;
+use crate::privacy::{DpSgdConfig, LocalPrivacy};
+use crate::secagg::{SecAggClient, SecAggConfig, SecAggMessage};
+use crate::seed::{self, Stream};
+use crate::signing::{self, ModelKind};
+use crate::tls;
+use crate::training::{OptimizerState, TrainingConfig};
 use actix::prelude::*;
+use actix_web::http::{header, StatusCode};
 use anyhow::Result;
-use log::{error, info};
-use ndarray::Array2;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::time::Duration;
+
+// Times a rate-limited message is sent before giving up
+const MAX_SEND_ATTEMPTS: u32 = 3;
 
 pub struct NodeActor {
     model: SharedModel,
-    server_addr: String,
+    server: ServerLink,
     node_addr: String,
+    // Credential sent as a bearer token with every message to the server
+    credential: Option<String>,
+    // Key the node signs its updates with, registered with the server on enrollment
+    signing_key: Option<SigningKey>,
+    // Key global models must be signed with before the node applies them
+    server_key: Option<VerifyingKey>,
+    training: TrainingConfig,
+    optimizer: Optimizer,
+    // Node-specific seed all of the node's randomness is derived from
+    seed: u64,
+    // Local dataset reused in every round
+    local_data: Option<LocalDataset>,
+    // Local DP-SGD settings and the privacy spent, if enabled
+    privacy: Option<LocalPrivacy>,
+    // Secure aggregation settings, if the server aggregates masked updates
+    secure_aggregation: Option<SecAggConfig>,
+    // Secure aggregation state of the current round
+    secagg_round: Option<SecAggClient>,
+    // Round the node is currently training for
+    round: u64,
+    // Gradient steps taken in the last local training run
+    local_steps: usize,
+    // Gradient steps taken since the node started
+    total_steps: usize,
+}
+
+// How a node reaches the central server
+#[derive(Clone)]
+pub enum ServerLink {
+    // POST messages to the server's HTTP address
+    Http(String),
+    // Deliver messages to a server actor in the same process
+    InMemory(Recipient<NodeMessage>),
+}
+
+// Local training progress reported by a node
+#[derive(Serialize, Clone)]
+pub struct NodeTrainingStatus {
+    pub address: String,
+    pub status: String,
+    pub local_steps: usize,
+    pub total_steps: usize,
+    // Epsilon spent on local DP-SGD, if enabled
+    pub epsilon: Option<f64>,
 }
 
 impl Actor for NodeActor {
@@ -22,9 +84,8 @@ impl Actor for NodeActor {
             addr: self.node_addr.clone(),
         };
 
-        let server_addr = self.server_addr.clone();
         ctx.run_later(std::time::Duration::from_secs(1), move |act, _| {
-            act.send_to_server(&server_addr, msg.clone());
+            act.send_to_server(msg);
         });
     }
 }
@@ -34,91 +95,504 @@ impl Handler<NodeMessage> for NodeActor {
 
     fn handle(&mut self, msg: NodeMessage, _ctx: &mut Self::Context) -> Self::Result {
         match msg {
-            NodeMessage::Train { data, labels } => {
-                info!("Training on node {}", self.node_addr);
-
-                // Convert data to ndarray format
-                let (x, y) = prepare_data(&data, &labels);
-
-                // Train model
-                match self.model.lock() {
-                    Ok(mut model) => {
-                        // Train for 10 epochs
-                        model.train(&x, &y, 10);
-                        info!("Node {} - Training completed", self.node_addr);
-                    }
-                    Err(e) => return Err(format!("Failed to lock model for training: {}", e)),
+            NodeMessage::StartRound {
+                round,
+                params,
+                training,
+                signature,
+            } => {
+                info!("Node {} starting round {}", self.node_addr, round);
+                self.verify_global_model(
+                    ModelKind::StartRound,
+                    round,
+                    &params,
+                    signature.as_deref(),
+                )?;
+                update_model(&self.model, &params)
+                    .map_err(|e| format!("Failed to update model: {}", e))?;
+                self.round = round;
+                if let Some(training) = training {
+                    self.training = *training;
                 }
-
-                // Send updated parameters to server
-                match extract_params(&self.model) {
-                    Ok(params) => {
-                        let server_msg = ServerMessage {
-                            node_addr: self.node_addr.clone(),
-                            params,
-                        };
-                        self.send_to_server(
-                            &self.server_addr,
-                            NodeMessage::UpdateModel {
-                                params: server_msg.params.clone(),
-                            },
-                        );
-                        Ok(())
-                    }
-                    Err(e) => Err(format!("Failed to extract model parameters: {}", e)),
+                if self.local_data.is_none() {
+                    return Err("No local dataset to train on".to_string());
                 }
+                self.train_and_submit()
             }
-            NodeMessage::Predict { data } => {
-                info!("Prediction on node {}", self.node_addr);
-
-                // Reshape data to [batch_size, features]
-                let batch_size = data.len() / 10;
-                let x = Array2::from_shape_vec((batch_size, 10), data.clone())
-                    .map_err(|e| format!("Failed to reshape data: {}", e))?;
-
-                match self.model.lock() {
-                    Ok(model) => {
-                        let predictions = model.forward(&x);
-                        info!("Prediction on node {}: {:?}", self.node_addr, predictions);
+            NodeMessage::Evaluate {
+                round,
+                params,
+                signature,
+            } => {
+                self.verify_global_model(
+                    ModelKind::Evaluate,
+                    round,
+                    &params,
+                    signature.as_deref(),
+                )?;
+                self.evaluate_and_submit(round, &params)
+            }
+            NodeMessage::SecAgg(msg) => self.continue_secure_aggregation(*msg),
+            NodeMessage::UpdateModel {
+                params,
+                round,
+                signature,
+            } => {
+                self.verify_global_model(ModelKind::Update, round, &params, signature.as_deref())?;
+                match update_model(&self.model, &params) {
+                    Ok(_) => {
+                        info!("Model updated on node {}", self.node_addr);
+                        // A node joining mid-training trains for the round of the model it fetched
+                        self.round = round;
                         Ok(())
                     }
-                    Err(e) => Err(format!("Failed to lock model for prediction: {}", e)),
+                    Err(e) => Err(format!("Failed to update model: {}", e)),
                 }
             }
-            NodeMessage::UpdateModel { params } => match update_model(&self.model, &params) {
-                Ok(_) => {
-                    info!("Model updated on node {}", self.node_addr);
-                    Ok(())
-                }
-                Err(e) => Err(format!("Failed to update model: {}", e)),
-            },
-            NodeMessage::RegisterNode { .. } => Ok(()), // Ignore, this is for server
+            NodeMessage::RegisterNode { .. }
+            | NodeMessage::SubmitUpdate { .. }
+            | NodeMessage::SubmitEvaluation { .. } => Ok(()), // Ignore, this is for server
+        }
+    }
+}
+
+impl Handler<PredictBatch> for NodeActor {
+    type Result = Result<Vec<Vec<f32>>, PredictError>;
+
+    fn handle(&mut self, msg: PredictBatch, _: &mut Self::Context) -> Self::Result {
+        predict(&self.model, &msg.0)
+    }
+}
+
+impl Handler<LoadDataset> for NodeActor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: LoadDataset, _: &mut Self::Context) -> Self::Result {
+        let dataset = msg.0;
+        info!(
+            "Node {} loaded {} training and {} validation samples",
+            self.node_addr,
+            dataset.train.data.len() / dataset.features,
+            dataset.validation.data.len() / dataset.features
+        );
+        self.local_data = Some(dataset);
+        self.train_and_submit()
+    }
+}
+
+impl Handler<SetArchitecture> for NodeActor {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: SetArchitecture, _: &mut Self::Context) -> Self::Result {
+        let spec = msg.0;
+        spec.validate()
+            .map_err(|e| format!("Invalid model architecture: {}", e))?;
+        let mut model = self
+            .model
+            .lock()
+            .map_err(|e| format!("Failed to lock model: {}", e))?;
+        if model.architecture().as_ref() != Some(&spec) {
+            info!("Node {} rebuilding model as {}", self.node_addr, spec);
+            *model = build_model(&spec, self.seed);
         }
+        Ok(())
+    }
+}
+
+impl Handler<GetNodeStatus> for NodeActor {
+    type Result = MessageResult<GetNodeStatus>;
+
+    fn handle(&mut self, _: GetNodeStatus, _: &mut Self::Context) -> Self::Result {
+        MessageResult(NodeTrainingStatus {
+            address: self.node_addr.clone(),
+            status: "running".to_string(),
+            local_steps: self.local_steps,
+            total_steps: self.total_steps,
+            epsilon: self.privacy.as_ref().map(LocalPrivacy::epsilon),
+        })
     }
 }
 
 impl NodeActor {
-    pub fn new(server_addr: String, node_addr: String) -> Self {
-        let model = build_model();
+    pub fn new(
+        server: ServerLink,
+        node_addr: String,
+        spec: &ModelSpec,
+        training: TrainingConfig,
+        root_seed: u64,
+    ) -> Self {
+        let seed = seed::node_seed(root_seed, &node_addr);
+        Self::with_model(server, node_addr, build_model(spec, seed), training, seed)
+    }
 
+    // Create a node that trains a custom model architecture
+    pub fn with_model(
+        server: ServerLink,
+        node_addr: String,
+        model: Box<dyn FederatedModel>,
+        training: TrainingConfig,
+        seed: u64,
+    ) -> Self {
         Self {
-            model,
-            server_addr,
+            model: share_model(model),
+            server,
+            credential: None,
+            signing_key: None,
+            server_key: None,
+            optimizer: Optimizer::new(training.optimizer.clone()),
+            seed,
+            training,
             node_addr,
+            local_data: None,
+            privacy: None,
+            secure_aggregation: None,
+            secagg_round: None,
+            round: 0,
+            local_steps: 0,
+            total_steps: 0,
+        }
+    }
+
+    // Train with DP-SGD under the given local settings
+    pub fn with_dp_sgd(mut self, config: DpSgdConfig) -> Self {
+        self.privacy = Some(LocalPrivacy::new(config));
+        self
+    }
+
+    // Authenticate messages to the server with an enrolled credential or API key
+    pub fn with_credential(mut self, credential: String) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    // Sign updates with the node's key and only apply global models signed by the server
+    pub fn with_signing_keys(mut self, signing_key: SigningKey, server_key: VerifyingKey) -> Self {
+        self.signing_key = Some(signing_key);
+        self.server_key = Some(server_key);
+        self
+    }
+
+    // Submit updates through secure aggregation
+    pub fn with_secure_aggregation(mut self, config: SecAggConfig) -> Self {
+        self.secure_aggregation = Some(config);
+        self
+    }
+
+    // Train on the local dataset and send the resulting parameters to the server
+    fn train_and_submit(&mut self) -> Result<(), String> {
+        let train = &self
+            .local_data
+            .as_ref()
+            .ok_or_else(|| "No local dataset to train on".to_string())?
+            .train;
+        info!("Training on node {}", self.node_addr);
+
+        // The optimizer follows the current config, restarting when asked to
+        if self.optimizer.config() != &self.training.optimizer {
+            self.optimizer = Optimizer::new(self.training.optimizer.clone());
+        } else if self.training.optimizer_state == OptimizerState::Reset {
+            self.optimizer.reset();
+        }
+
+        // Train model
+        let num_samples;
+        match self.model.lock() {
+            Ok(mut model) => {
+                // Convert data to ndarray format
+                let (x, y) = prepare_data(&train.data, &train.labels, model.as_ref())
+                    .map_err(|e| format!("Invalid training data: {}", e))?;
+                num_samples = x.nrows();
+
+                // DP-SGD accounts for every batch as a sample of rate batch / samples
+                let batch_size = match self.training.batch_size {
+                    0 => num_samples,
+                    batch_size => batch_size.min(num_samples),
+                };
+                let sampling_rate = batch_size as f64 / num_samples.max(1) as f64;
+                if let Some(privacy) = &self.privacy {
+                    let steps = self.training.epochs * num_samples.div_ceil(batch_size.max(1));
+                    let epsilon = privacy.epsilon_after(sampling_rate, steps as u64);
+                    if let Some(target) = privacy.config.target_epsilon {
+                        if epsilon > target {
+                            let reason = format!(
+                                "Refusing to train: epsilon would reach {:.4}, over the local budget of {}",
+                                epsilon, target
+                            );
+                            warn!("Node {} - {}", self.node_addr, reason);
+                            return Err(reason);
+                        }
+                    }
+                }
+
+                // A fresh seed per round keeps a round's result independent of earlier ones
+                let round_seed = seed::derive(self.seed, Stream::Round, self.round);
+                self.local_steps = model
+                    .train(
+                        &x,
+                        &y,
+                        &self.training,
+                        &mut self.optimizer,
+                        round_seed,
+                        self.privacy.as_ref().map(|privacy| &privacy.config),
+                    )
+                    .map_err(|e| format!("Training failed: {}", e))?;
+                self.total_steps += self.local_steps;
+                if let Some(privacy) = self.privacy.as_mut() {
+                    privacy.spend(sampling_rate, self.local_steps as u64);
+                }
+                let metrics = model.evaluate(&x, &y);
+                info!(
+                    "Node {} - Training completed in {} steps, {}",
+                    self.node_addr, self.local_steps, metrics
+                );
+            }
+            Err(e) => return Err(format!("Failed to lock model for training: {}", e)),
+        }
+
+        // Send updated parameters to server
+        match extract_params(&self.model) {
+            Ok(params) => {
+                let epsilon = self.privacy.as_ref().map(LocalPrivacy::epsilon);
+                let msg = match &self.secure_aggregation {
+                    // Only masked parameters leave the node, after a key exchange
+                    Some(config) => {
+                        let client =
+                            SecAggClient::new(config, &self.node_addr, self.round, &params);
+                        let msg = client.advertise(num_samples, self.local_steps, epsilon);
+                        self.secagg_round = Some(client);
+                        NodeMessage::SecAgg(Box::new(msg))
+                    }
+                    None => NodeMessage::SubmitUpdate {
+                        node_addr: self.node_addr.clone(),
+                        round: self.round,
+                        signature: self.sign_update(self.round, signing::params_hash(&params)),
+                        params,
+                        num_samples,
+                        num_steps: self.local_steps,
+                        epsilon,
+                    },
+                };
+                self.send_to_server(msg);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to extract model parameters: {}", e)),
+        }
+    }
+
+    // Evaluate a global model on the validation split and report the metrics to the server.
+    // The node's own parameters are restored afterwards.
+    fn evaluate_and_submit(&mut self, round: u64, params: &[f32]) -> Result<(), String> {
+        let validation = &self
+            .local_data
+            .as_ref()
+            .ok_or_else(|| "No local dataset to evaluate on".to_string())?
+            .validation;
+
+        let metrics = {
+            let mut model = self
+                .model
+                .lock()
+                .map_err(|e| format!("Failed to lock model for evaluation: {}", e))?;
+            let (x_eval, y_eval) =
+                prepare_data(&validation.data, &validation.labels, model.as_ref())
+                    .map_err(|e| format!("Invalid evaluation data: {}", e))?;
+            if x_eval.nrows() == 0 {
+                return Err("No held-out samples to evaluate on".to_string());
+            }
+
+            let local_params = model.get_params();
+            model
+                .set_params(params)
+                .map_err(|e| format!("Failed to load model for evaluation: {}", e))?;
+            let metrics = model.evaluate(&x_eval, &y_eval);
+            model
+                .set_params(&local_params)
+                .map_err(|e| format!("Failed to restore local model: {}", e))?;
+            metrics
+        };
+        info!(
+            "Node {} - Round {} evaluation on held-out data: {}",
+            self.node_addr, round, metrics
+        );
+
+        self.send_to_server(NodeMessage::SubmitEvaluation {
+            node_addr: self.node_addr.clone(),
+            round,
+            metrics,
+        });
+        Ok(())
+    }
+
+    // Answer the server's next secure aggregation step for the current round
+    fn continue_secure_aggregation(&mut self, msg: SecAggMessage) -> Result<(), String> {
+        let client = self
+            .secagg_round
+            .as_mut()
+            .ok_or_else(|| "No secure aggregation in progress".to_string())?;
+        match client.handle(msg) {
+            Ok(Some(mut reply)) => {
+                if let SecAggMessage::MaskedInput {
+                    round,
+                    masked,
+                    signature,
+                    ..
+                } = &mut reply
+                {
+                    *signature = self.sign_update(*round, signing::masked_hash(masked));
+                }
+                self.send_to_server(NodeMessage::SecAgg(Box::new(reply)));
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(e) => {
+                warn!("Node {} - Secure aggregation failed: {}", self.node_addr, e);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    // Node's signature of an update, if it has a key
+    fn sign_update(&self, round: u64, payload: [u8; 32]) -> Option<String> {
+        self.signing_key
+            .as_ref()
+            .map(|key| signing::sign_update(key, round, &self.node_addr, payload))
+    }
+
+    // Refuse a global model that is not signed by the server, or older than the current round
+    fn verify_global_model(
+        &self,
+        kind: ModelKind,
+        round: u64,
+        params: &[f32],
+        signature: Option<&str>,
+    ) -> Result<(), String> {
+        let Some(server_key) = &self.server_key else {
+            return Ok(());
+        };
+        if !signing::verify_model(server_key, kind, round, params, signature) {
+            warn!(
+                "Node {} - Refused a global model with a missing or invalid signature",
+                self.node_addr
+            );
+            return Err("Missing or invalid global model signature".to_string());
+        }
+        if round < self.round {
+            warn!(
+                "Node {} - Refused a global model of round {} in round {}",
+                self.node_addr, round, self.round
+            );
+            return Err(format!("Stale global model of round {}", round));
         }
+        Ok(())
     }
 
-    fn send_to_server(&self, server_addr: &str, msg: NodeMessage) {
-        let server_addr = format!("{}/message", server_addr);
+    fn send_to_server(&self, msg: NodeMessage) {
+        let server_addr = match &self.server {
+            ServerLink::Http(server_addr) => format!("{}/message", server_addr),
+            ServerLink::InMemory(server) => {
+                server.do_send(msg);
+                return;
+            }
+        };
         let msg_clone = msg.clone();
+        let credential = self.credential.clone();
 
         // Use actix_web::rt::spawn instead of tokio::spawn
         actix_web::rt::spawn(async move {
-            let client = awc::Client::default();
-            match client.post(&server_addr).send_json(&msg_clone).await {
-                Ok(_) => info!("Message sent to server successfully"),
-                Err(e) => error!("Failed to send message to server: {}", e),
+            for attempt in 1..=MAX_SEND_ATTEMPTS {
+                let mut request = tls::http_client().post(&server_addr);
+                if let Some(credential) = &credential {
+                    request = request.bearer_auth(credential);
+                }
+                match request.send_json(&msg_clone).await {
+                    Ok(response) if response.status().is_success() => {
+                        info!("Message sent to server successfully")
+                    }
+                    Ok(mut response) => {
+                        // A rate-limited message is sent again once the server's Retry-After has passed
+                        let retry_after = response
+                            .headers()
+                            .get(header::RETRY_AFTER)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| value.parse().ok());
+                        if let (StatusCode::TOO_MANY_REQUESTS, Some(secs), true) =
+                            (response.status(), retry_after, attempt < MAX_SEND_ATTEMPTS)
+                        {
+                            warn!("Server rate limited message, retrying in {} s", secs);
+                            actix_web::rt::time::sleep(Duration::from_secs(secs)).await;
+                            continue;
+                        }
+                        let body = response.body().await.unwrap_or_default();
+                        error!(
+                            "Server rejected message ({}): {}",
+                            response.status(),
+                            String::from_utf8_lossy(&body)
+                        );
+                    }
+                    Err(e) => error!("Failed to send message to server: {}", e),
+                }
+                break;
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Samples;
+    use crate::loss::Loss;
+    use crate::messages::{GetGlobalModel, GetMetrics, GetModelParams, GetProgress};
+    use crate::server::{CentralServer, ServerConfig};
+
+    #[actix_rt::test]
+    async fn late_joining_node_trains_for_the_current_round() {
+        let spec = ModelSpec::parse(2, "1:linear", Loss::Mse).unwrap();
+        let config = ServerConfig {
+            total_nodes: 1,
+            rounds: Some(3),
+            ..ServerConfig::default()
+        };
+        let server = CentralServer::new(config, &spec).start();
+
+        // Another node completes round 0 before this one starts
+        let params = server.send(GetModelParams).await.unwrap().unwrap();
+        server
+            .send(NodeMessage::SubmitUpdate {
+                node_addr: "sim://early".to_string(),
+                round: 0,
+                params,
+                num_samples: 1,
+                num_steps: 1,
+                epsilon: None,
+                signature: None,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(server.send(GetProgress).await.unwrap().round, 1);
+
+        let node = NodeActor::new(
+            ServerLink::InMemory(server.clone().recipient()),
+            "sim://late".to_string(),
+            &spec,
+            TrainingConfig::default(),
+            0,
+        )
+        .start();
+        let global = server.send(GetGlobalModel).await.unwrap().unwrap();
+        node.send(global).await.unwrap().unwrap();
+        let samples = Samples {
+            data: (0..40).map(|i| i as f32 / 40.0).collect(),
+            labels: (0..20).map(|i| i as f32 / 20.0).collect(),
+        };
+        let dataset = LocalDataset::split(samples, 2, 0.2, 0).unwrap();
+        node.send(LoadDataset(dataset)).await.unwrap().unwrap();
+
+        // The update was queued at the server before the node replied
+        let metrics = server.send(GetMetrics).await.unwrap();
+        assert_eq!(metrics.updates_rejected, 0);
+        assert_eq!(metrics.updates_accepted, 2);
+        assert_eq!(server.send(GetProgress).await.unwrap().round, 2);
+    }
+}
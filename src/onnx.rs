@@ -0,0 +1,449 @@
+use crate::layers::{Activation, GELU_C, GELU_K, LEAKY_RELU_ALPHA};
+use crate::loss::Loss;
+use crate::model::SimpleModel;
+use anyhow::{anyhow, Result};
+use ndarray::{Array1, Array2};
+
+// ONNX IR and operator set versions targeted by the exporter
+const IR_VERSION: u64 = 8;
+const OPSET_VERSION: u64 = 17;
+
+// TensorProto.DataType.FLOAT
+const DATA_TYPE_FLOAT: u64 = 1;
+
+// AttributeProto.AttributeType.FLOAT
+const ATTRIBUTE_TYPE_FLOAT: u64 = 1;
+
+// Layers the exporter knows how to translate into ONNX nodes
+pub enum OnnxLayer {
+    // Fully connected layer computing x·W + b
+    Dense {
+        weight: Array2<f32>,
+        bias: Array1<f32>,
+    },
+    // Element-wise activation
+    Activation(Activation),
+    // Softmax over the last axis
+    Softmax,
+}
+
+// Export a model into a serialized ONNX ModelProto, mirroring `SimpleModel::forward`
+pub fn export_model(model: &SimpleModel) -> Result<Vec<u8>> {
+    let mut layers = Vec::new();
+    for ((weight, bias), spec) in model
+        .weights
+        .iter()
+        .zip(&model.biases)
+        .zip(&model.spec.layers)
+    {
+        layers.push(OnnxLayer::Dense {
+            weight: weight.clone(),
+            bias: bias.clone(),
+        });
+        layers.push(OnnxLayer::Activation(spec.activation));
+    }
+
+    // Match the output transform of the loss
+    match model.spec.loss {
+        Loss::Mse => {}
+        Loss::BinaryCrossEntropy => layers.push(OnnxLayer::Activation(Activation::Sigmoid)),
+        Loss::CategoricalCrossEntropy => layers.push(OnnxLayer::Softmax),
+    }
+    export_layers(&layers)
+}
+
+// Build an ONNX graph for a sequential stack of layers
+pub fn export_layers(layers: &[OnnxLayer]) -> Result<Vec<u8>> {
+    let input_size = match layers.first() {
+        Some(OnnxLayer::Dense { weight, .. }) => weight.nrows(),
+        _ => return Err(anyhow!("The first layer must be a dense layer")),
+    };
+
+    let mut graph = Graph::default();
+    let mut current = "input".to_string();
+    let mut width = input_size;
+
+    for (i, layer) in layers.iter().enumerate() {
+        match layer {
+            OnnxLayer::Dense { weight, bias } => {
+                if weight.nrows() != width || bias.len() != weight.ncols() {
+                    return Err(anyhow!(
+                        "Dense layer {} has weight {:?} and bias {:?}, incompatible with width {}",
+                        i,
+                        weight.shape(),
+                        bias.shape(),
+                        width
+                    ));
+                }
+
+                let weight_name = format!("dense_{}.weight", i);
+                let bias_name = format!("dense_{}.bias", i);
+                graph.initializer(
+                    &weight_name,
+                    &[weight.nrows(), weight.ncols()],
+                    weight.iter().copied(),
+                );
+                graph.initializer(&bias_name, &[bias.len()], bias.iter().copied());
+                current = graph.node(
+                    &format!("dense_{}", i),
+                    "Gemm",
+                    &[&current, &weight_name, &bias_name],
+                    &[],
+                );
+                width = weight.ncols();
+            }
+            OnnxLayer::Activation(activation) => {
+                current = graph.activation(i, *activation, &current);
+            }
+            OnnxLayer::Softmax => {
+                current = graph.node(&format!("softmax_{}", i), "Softmax", &[&current], &[]);
+            }
+        }
+    }
+
+    // Give the graph output a stable name
+    if current != "output" {
+        let mut node = Vec::new();
+        write_string(&mut node, 1, &current);
+        write_string(&mut node, 2, "output");
+        write_string(&mut node, 3, "output");
+        write_string(&mut node, 4, "Identity");
+        write_message(&mut graph.buf, 1, &node);
+    }
+
+    let mut graph_buf = graph.buf;
+    write_string(&mut graph_buf, 2, "federated_model");
+    write_message(&mut graph_buf, 11, &value_info_proto("input", input_size));
+    write_message(&mut graph_buf, 12, &value_info_proto("output", width));
+
+    let mut opset = Vec::new();
+    write_string(&mut opset, 1, "");
+    write_varint_field(&mut opset, 2, OPSET_VERSION);
+
+    let mut model = Vec::new();
+    write_varint_field(&mut model, 1, IR_VERSION);
+    write_string(&mut model, 2, "federated_learning");
+    write_string(&mut model, 3, env!("CARGO_PKG_VERSION"));
+    write_message(&mut model, 7, &graph_buf);
+    write_message(&mut model, 8, &opset);
+
+    Ok(model)
+}
+
+// GraphProto under construction
+#[derive(Default)]
+struct Graph {
+    buf: Vec<u8>,
+    constants: Vec<String>,
+}
+
+impl Graph {
+    // Add a float initializer tensor
+    fn initializer(&mut self, name: &str, dims: &[usize], values: impl Iterator<Item = f32>) {
+        write_message(&mut self.buf, 5, &tensor_proto(name, dims, values));
+    }
+
+    // Add a scalar constant once and return its name
+    fn constant(&mut self, name: &str, value: f32) -> String {
+        if !self.constants.iter().any(|c| c == name) {
+            self.initializer(name, &[], std::iter::once(value));
+            self.constants.push(name.to_string());
+        }
+        name.to_string()
+    }
+
+    // Add a node and return the name of its output
+    fn node(
+        &mut self,
+        name: &str,
+        op_type: &str,
+        inputs: &[&str],
+        attributes: &[Vec<u8>],
+    ) -> String {
+        let output = format!("{}.out", name);
+        let mut node = Vec::new();
+        for input in inputs {
+            write_string(&mut node, 1, input);
+        }
+        write_string(&mut node, 2, &output);
+        write_string(&mut node, 3, name);
+        write_string(&mut node, 4, op_type);
+        for attribute in attributes {
+            write_message(&mut node, 5, attribute);
+        }
+        write_message(&mut self.buf, 1, &node);
+        output
+    }
+
+    // Add the nodes computing an activation and return the name of its output
+    fn activation(&mut self, i: usize, activation: Activation, input: &str) -> String {
+        let name = format!("{}_{}", activation, i);
+        match activation {
+            Activation::Linear => input.to_string(),
+            Activation::Relu => self.node(&name, "Relu", &[input], &[]),
+            Activation::Tanh => self.node(&name, "Tanh", &[input], &[]),
+            Activation::Sigmoid => self.node(&name, "Sigmoid", &[input], &[]),
+            Activation::LeakyRelu => self.node(
+                &name,
+                "LeakyRelu",
+                &[input],
+                &[float_attribute("alpha", LEAKY_RELU_ALPHA)],
+            ),
+            // Opset 17 has no Gelu operator, so spell out the tanh approximation
+            Activation::Gelu => {
+                let half = self.constant("gelu.half", 0.5);
+                let one = self.constant("gelu.one", 1.0);
+                let k = self.constant("gelu.k", GELU_K);
+                let c = self.constant("gelu.c", GELU_C);
+                let x2 = self.node(&format!("{}.x2", name), "Mul", &[input, input], &[]);
+                let x3 = self.node(&format!("{}.x3", name), "Mul", &[&x2, input], &[]);
+                let cx3 = self.node(&format!("{}.cx3", name), "Mul", &[&x3, &c], &[]);
+                let sum = self.node(&format!("{}.sum", name), "Add", &[input, &cx3], &[]);
+                let inner = self.node(&format!("{}.inner", name), "Mul", &[&sum, &k], &[]);
+                let tanh = self.node(&format!("{}.tanh", name), "Tanh", &[&inner], &[]);
+                let gate = self.node(&format!("{}.gate", name), "Add", &[&tanh, &one], &[]);
+                let gated = self.node(&format!("{}.gated", name), "Mul", &[input, &gate], &[]);
+                self.node(&name, "Mul", &[&gated, &half], &[])
+            }
+        }
+    }
+}
+
+// AttributeProto holding a single float
+fn float_attribute(name: &str, value: f32) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_string(&mut buf, 1, name);
+    write_varint(&mut buf, (2 << 3) | 5);
+    buf.extend_from_slice(&value.to_le_bytes());
+    write_varint_field(&mut buf, 20, ATTRIBUTE_TYPE_FLOAT);
+    buf
+}
+
+// TensorProto holding float data as little-endian raw bytes
+fn tensor_proto(name: &str, dims: &[usize], values: impl Iterator<Item = f32>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for dim in dims {
+        write_varint_field(&mut buf, 1, *dim as u64);
+    }
+    write_varint_field(&mut buf, 2, DATA_TYPE_FLOAT);
+    write_string(&mut buf, 8, name);
+    let raw: Vec<u8> = values.flat_map(|v| v.to_le_bytes()).collect();
+    write_bytes(&mut buf, 9, &raw);
+    buf
+}
+
+// ValueInfoProto for a float tensor of shape [batch, width]
+fn value_info_proto(name: &str, width: usize) -> Vec<u8> {
+    let mut batch_dim = Vec::new();
+    write_string(&mut batch_dim, 2, "batch");
+    let mut width_dim = Vec::new();
+    write_varint_field(&mut width_dim, 1, width as u64);
+
+    let mut shape = Vec::new();
+    write_message(&mut shape, 1, &batch_dim);
+    write_message(&mut shape, 1, &width_dim);
+
+    let mut tensor_type = Vec::new();
+    write_varint_field(&mut tensor_type, 1, DATA_TYPE_FLOAT);
+    write_message(&mut tensor_type, 2, &shape);
+
+    let mut type_proto = Vec::new();
+    write_message(&mut type_proto, 1, &tensor_type);
+
+    let mut buf = Vec::new();
+    write_string(&mut buf, 1, name);
+    write_message(&mut buf, 2, &type_proto);
+    buf
+}
+
+// Protobuf wire encoding helpers
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, value);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buf, (field << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_string(buf: &mut Vec<u8>, field: u64, value: &str) {
+    write_bytes(buf, field, value.as_bytes());
+}
+
+fn write_message(buf: &mut Vec<u8>, field: u64, message: &[u8]) {
+    write_bytes(buf, field, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::ModelSpec;
+    use crate::model::build_model;
+
+    // Wire value of a protobuf field
+    #[derive(Debug)]
+    enum Value<'a> {
+        Varint(u64),
+        Fixed32,
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(buf: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = buf[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+        }
+        panic!("varint longer than 64 bits");
+    }
+
+    // Decode a message into its fields in wire order
+    fn decode(buf: &[u8]) -> Vec<(u64, Value<'_>)> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos);
+            let value = match key & 7 {
+                0 => Value::Varint(read_varint(buf, &mut pos)),
+                2 => {
+                    let len = read_varint(buf, &mut pos) as usize;
+                    pos += len;
+                    Value::Bytes(&buf[pos - len..pos])
+                }
+                5 => {
+                    pos += 4;
+                    Value::Fixed32
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    fn varints(fields: &[(u64, Value)], field: u64) -> Vec<u64> {
+        fields
+            .iter()
+            .filter_map(|(f, v)| match v {
+                Value::Varint(v) if *f == field => Some(*v),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn messages<'a>(fields: &[(u64, Value<'a>)], field: u64) -> Vec<&'a [u8]> {
+        fields
+            .iter()
+            .filter_map(|(f, v)| match v {
+                Value::Bytes(b) if *f == field => Some(*b),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn strings(fields: &[(u64, Value)], field: u64) -> Vec<String> {
+        messages(fields, field)
+            .into_iter()
+            .map(|b| String::from_utf8(b.to_vec()).unwrap())
+            .collect()
+    }
+
+    // Name and [batch, width] shape of a ValueInfoProto
+    fn value_info(bytes: &[u8]) -> (String, String, u64) {
+        let info = decode(bytes);
+        let type_proto = decode(messages(&info, 2)[0]);
+        let tensor_type = decode(messages(&type_proto, 1)[0]);
+        assert_eq!(varints(&tensor_type, 1), [DATA_TYPE_FLOAT]);
+        let shape = decode(messages(&tensor_type, 2)[0]);
+        let dims: Vec<_> = messages(&shape, 1).into_iter().map(decode).collect();
+        assert_eq!(dims.len(), 2);
+        (
+            strings(&info, 1)[0].clone(),
+            strings(&dims[0], 2)[0].clone(),
+            varints(&dims[1], 1)[0],
+        )
+    }
+
+    #[test]
+    fn two_layer_model_exports_a_valid_model_proto() {
+        let spec = ModelSpec::parse(3, "4:relu,2:linear", Loss::Mse).unwrap();
+        let model = build_model(&spec, 0);
+        let bytes = model.to_onnx().unwrap();
+
+        let model_proto = decode(&bytes);
+        assert_eq!(varints(&model_proto, 1), [IR_VERSION]);
+        let opsets = messages(&model_proto, 8);
+        assert_eq!(opsets.len(), 1);
+        let opset = decode(opsets[0]);
+        assert_eq!(strings(&opset, 1), [""]);
+        assert_eq!(varints(&opset, 2), [OPSET_VERSION]);
+
+        let graph = decode(messages(&model_proto, 7)[0]);
+        let nodes: Vec<_> = messages(&graph, 1).into_iter().map(decode).collect();
+        let ops: Vec<String> = nodes.iter().map(|n| strings(n, 4)[0].clone()).collect();
+        assert_eq!(ops, ["Gemm", "Relu", "Gemm", "Identity"]);
+        assert_eq!(
+            strings(&nodes[0], 1),
+            ["input", "dense_0.weight", "dense_0.bias"]
+        );
+        // Each node reads the output of the one before
+        for pair in nodes.windows(2) {
+            assert_eq!(strings(&pair[1], 1)[0], strings(&pair[0], 2)[0]);
+        }
+        assert_eq!(strings(&nodes[3], 2), ["output"]);
+
+        let initializers: Vec<(String, Vec<u64>, usize)> = messages(&graph, 5)
+            .into_iter()
+            .map(|bytes| {
+                let tensor = decode(bytes);
+                assert_eq!(varints(&tensor, 2), [DATA_TYPE_FLOAT]);
+                (
+                    strings(&tensor, 8)[0].clone(),
+                    varints(&tensor, 1),
+                    messages(&tensor, 9)[0].len(),
+                )
+            })
+            .collect();
+        let expected = [
+            ("dense_0.weight", vec![3, 4]),
+            ("dense_0.bias", vec![4]),
+            ("dense_2.weight", vec![4, 2]),
+            ("dense_2.bias", vec![2]),
+        ];
+        assert_eq!(initializers.len(), expected.len());
+        for ((name, dims, raw_len), (expected_name, expected_dims)) in
+            initializers.iter().zip(expected)
+        {
+            assert_eq!(name, expected_name);
+            assert_eq!(dims, &expected_dims);
+            assert_eq!(*raw_len as u64, 4 * expected_dims.iter().product::<u64>());
+        }
+
+        let inputs = messages(&graph, 11);
+        let outputs = messages(&graph, 12);
+        assert_eq!((inputs.len(), outputs.len()), (1, 1));
+        assert_eq!(
+            value_info(inputs[0]),
+            ("input".to_string(), "batch".to_string(), 3)
+        );
+        assert_eq!(
+            value_info(outputs[0]),
+            ("output".to_string(), "batch".to_string(), 2)
+        );
+    }
+}
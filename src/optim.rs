@@ -0,0 +1,353 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// Client-side optimizer and its hyperparameters
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OptimizerConfig {
+    // Plain SGD when momentum is 0
+    Sgd {
+        #[serde(default)]
+        momentum: f32,
+        #[serde(default)]
+        nesterov: bool,
+    },
+    Adam {
+        #[serde(default = "default_beta1")]
+        beta1: f32,
+        #[serde(default = "default_beta2")]
+        beta2: f32,
+        #[serde(default = "default_epsilon")]
+        epsilon: f32,
+    },
+    // Adam with decoupled weight decay
+    #[serde(rename = "adamw")]
+    AdamW {
+        #[serde(default = "default_beta1")]
+        beta1: f32,
+        #[serde(default = "default_beta2")]
+        beta2: f32,
+        #[serde(default = "default_epsilon")]
+        epsilon: f32,
+        #[serde(default = "default_weight_decay")]
+        weight_decay: f32,
+    },
+}
+
+fn default_beta1() -> f32 {
+    0.9
+}
+
+fn default_beta2() -> f32 {
+    0.999
+}
+
+fn default_epsilon() -> f32 {
+    1e-8
+}
+
+fn default_weight_decay() -> f32 {
+    0.01
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        OptimizerConfig::Sgd {
+            momentum: 0.0,
+            nesterov: false,
+        }
+    }
+}
+
+// Learning-rate decay applied after warmup
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LrSchedule {
+    #[default]
+    Constant,
+    // Multiply the rate by `gamma` every `step_size` steps
+    Step {
+        step_size: usize,
+        gamma: f32,
+    },
+    // Cosine decay from the base rate to `min_lr` over `total_steps`
+    Cosine {
+        total_steps: usize,
+        #[serde(default)]
+        min_lr: f32,
+    },
+}
+
+impl LrSchedule {
+    // Learning rate at a given optimizer step, with linear warmup over `warmup_steps`
+    pub fn learning_rate(&self, base_lr: f32, step: usize, warmup_steps: usize) -> f32 {
+        if step < warmup_steps {
+            return base_lr * (step + 1) as f32 / warmup_steps as f32;
+        }
+        let step = step - warmup_steps;
+
+        match self {
+            LrSchedule::Constant => base_lr,
+            LrSchedule::Step { step_size, gamma } => {
+                base_lr * gamma.powi((step / (*step_size).max(1)) as i32)
+            }
+            LrSchedule::Cosine {
+                total_steps,
+                min_lr,
+            } => {
+                let progress = step.min(*total_steps) as f32 / (*total_steps).max(1) as f32;
+                min_lr + (base_lr - min_lr) * 0.5 * (1.0 + (PI * progress).cos())
+            }
+        }
+    }
+}
+
+// Optimizer with its per-parameter state
+pub struct Optimizer {
+    config: OptimizerConfig,
+    // Momentum buffer for SGD, first moment for Adam
+    first: Vec<f32>,
+    // Second moment for Adam
+    second: Vec<f32>,
+    // Number of updates applied since the last reset
+    steps: usize,
+}
+
+impl Optimizer {
+    pub fn new(config: OptimizerConfig) -> Self {
+        Self {
+            config,
+            first: Vec::new(),
+            second: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    pub fn config(&self) -> &OptimizerConfig {
+        &self.config
+    }
+
+    // Updates applied since the last reset, used to drive the learning-rate schedule
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    // Drop accumulated moments and the step counter
+    pub fn reset(&mut self) {
+        self.first.clear();
+        self.second.clear();
+        self.steps = 0;
+    }
+
+    // Apply one update to a flat parameter vector
+    pub fn step(&mut self, params: &mut [f32], grads: &[f32], lr: f32) {
+        // State is (re)allocated lazily so it always matches the parameter count
+        if self.first.len() != params.len() {
+            self.first = vec![0.0; params.len()];
+            self.second = vec![0.0; params.len()];
+        }
+        self.steps += 1;
+
+        match self.config {
+            OptimizerConfig::Sgd { momentum, nesterov } => {
+                for ((p, &g), v) in params.iter_mut().zip(grads).zip(self.first.iter_mut()) {
+                    if momentum == 0.0 {
+                        *p -= lr * g;
+                        continue;
+                    }
+                    *v = momentum * *v + g;
+                    let update = if nesterov { g + momentum * *v } else { *v };
+                    *p -= lr * update;
+                }
+            }
+            OptimizerConfig::Adam {
+                beta1,
+                beta2,
+                epsilon,
+            } => self.adam_step(params, grads, lr, beta1, beta2, epsilon, 0.0),
+            OptimizerConfig::AdamW {
+                beta1,
+                beta2,
+                epsilon,
+                weight_decay,
+            } => self.adam_step(params, grads, lr, beta1, beta2, epsilon, weight_decay),
+        }
+    }
+
+    // Bias-corrected Adam update, with decoupled weight decay for AdamW
+    #[allow(clippy::too_many_arguments)]
+    fn adam_step(
+        &mut self,
+        params: &mut [f32],
+        grads: &[f32],
+        lr: f32,
+        beta1: f32,
+        beta2: f32,
+        epsilon: f32,
+        weight_decay: f32,
+    ) {
+        let t = self.steps as i32;
+        let correction1 = 1.0 - beta1.powi(t);
+        let correction2 = 1.0 - beta2.powi(t);
+
+        for (((p, &g), m), v) in params
+            .iter_mut()
+            .zip(grads)
+            .zip(self.first.iter_mut())
+            .zip(self.second.iter_mut())
+        {
+            *m = beta1 * *m + (1.0 - beta1) * g;
+            *v = beta2 * *v + (1.0 - beta2) * g * g;
+            let m_hat = *m / correction1;
+            let v_hat = *v / correction2;
+            *p -= lr * (m_hat / (v_hat.sqrt() + epsilon) + weight_decay * *p);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parameters after applying `grads` one step at a time from `start` at rate 0.1
+    fn run(config: OptimizerConfig, start: f32, grads: &[f32]) -> Vec<f32> {
+        let mut optimizer = Optimizer::new(config);
+        let mut params = [start];
+        grads
+            .iter()
+            .map(|&g| {
+                optimizer.step(&mut params, &[g], 0.1);
+                params[0]
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} vs {:?}", actual, expected);
+        }
+    }
+
+    fn sgd(momentum: f32, nesterov: bool) -> OptimizerConfig {
+        OptimizerConfig::Sgd { momentum, nesterov }
+    }
+
+    #[test]
+    fn sgd_steps_along_the_gradient() {
+        assert_close(&run(sgd(0.0, false), 1.0, &[0.5, 0.5]), &[0.95, 0.9]);
+    }
+
+    #[test]
+    fn sgd_momentum_accumulates_velocity() {
+        // v1 = 0.5, v2 = 0.9 * 0.5 + 0.5 = 0.95
+        assert_close(&run(sgd(0.9, false), 1.0, &[0.5, 0.5]), &[0.95, 0.855]);
+    }
+
+    #[test]
+    fn nesterov_looks_ahead() {
+        // Updates g + 0.9 v: 0.5 + 0.45 = 0.95, then 0.5 + 0.855 = 1.355
+        assert_close(&run(sgd(0.9, true), 1.0, &[0.5, 0.5]), &[0.905, 0.7695]);
+    }
+
+    #[test]
+    fn adam_corrects_bias() {
+        let adam = OptimizerConfig::Adam {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+        };
+        // The first bias-corrected step moves by the learning rate whatever the gradient's size;
+        // the second has m = 0.07, v = 3.1225e-4 before correction by 0.19 and 1.999e-3
+        assert_close(&run(adam, 1.0, &[0.5, 0.25]), &[0.9, 0.806_782]);
+        let adam = OptimizerConfig::Adam {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+        };
+        assert_close(&run(adam, 1.0, &[-1000.0]), &[1.1]);
+    }
+
+    #[test]
+    fn adamw_decays_weights_separately() {
+        let adamw = OptimizerConfig::AdamW {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            weight_decay: 0.1,
+        };
+        // 1 - 0.1 * (1 + 0.1 * 1), and decay alone with a zero gradient
+        assert_close(&run(adamw.clone(), 1.0, &[0.5]), &[0.89]);
+        assert_close(&run(adamw, 2.0, &[0.0]), &[1.98]);
+    }
+
+    #[test]
+    fn reset_drops_state() {
+        let mut optimizer = Optimizer::new(sgd(0.9, false));
+        let mut params = [1.0];
+        optimizer.step(&mut params, &[0.5], 0.1);
+        assert_eq!(optimizer.steps(), 1);
+        optimizer.reset();
+        assert_eq!(optimizer.steps(), 0);
+        // Without the old velocity the step is plain SGD again
+        optimizer.step(&mut params, &[0.5], 0.1);
+        assert!((params[0] - 0.9).abs() < 1e-6);
+    }
+
+    fn rates(schedule: LrSchedule, warmup_steps: usize, steps: &[usize]) -> Vec<f32> {
+        steps
+            .iter()
+            .map(|&step| schedule.learning_rate(0.1, step, warmup_steps))
+            .collect()
+    }
+
+    #[test]
+    fn warmup_ramps_up_linearly() {
+        assert_close(
+            &rates(LrSchedule::Constant, 4, &[0, 1, 3, 4, 100]),
+            &[0.025, 0.05, 0.1, 0.1, 0.1],
+        );
+    }
+
+    #[test]
+    fn step_schedule_decays_after_warmup() {
+        let schedule = LrSchedule::Step {
+            step_size: 2,
+            gamma: 0.5,
+        };
+        assert_close(
+            &rates(schedule.clone(), 0, &[0, 1, 2, 3, 4]),
+            &[0.1, 0.1, 0.05, 0.05, 0.025],
+        );
+        // The decay counts steps from the end of warmup
+        assert_close(&rates(schedule, 2, &[0, 2, 4]), &[0.05, 0.1, 0.05]);
+    }
+
+    #[test]
+    fn cosine_schedule_ends_at_min_lr() {
+        let schedule = LrSchedule::Cosine {
+            total_steps: 10,
+            min_lr: 0.01,
+        };
+        assert_close(
+            &rates(schedule.clone(), 0, &[0, 5, 10, 20]),
+            &[0.1, 0.055, 0.01, 0.01],
+        );
+        assert_close(&rates(schedule, 2, &[1, 2, 7]), &[0.1, 0.1, 0.055]);
+    }
+
+    #[test]
+    fn optimizer_config_defaults() {
+        // Named as in the round config documentation
+        let config: OptimizerConfig = serde_json::from_str(r#"{"type": "adamw"}"#).unwrap();
+        assert_eq!(
+            config,
+            OptimizerConfig::AdamW {
+                beta1: 0.9,
+                beta2: 0.999,
+                epsilon: 1e-8,
+                weight_decay: 0.01
+            }
+        );
+    }
+}
@@ -0,0 +1,614 @@
+use crate::dataset::Samples;
+use crate::layers::ModelSpec;
+use crate::loss::Loss;
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Dirichlet, Distribution, Normal};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Regression targets are grouped into this many quantile buckets to act as classes
+const REGRESSION_BUCKETS: usize = 10;
+
+// Dirichlet draws are repeated until every client gets at least one sample
+const MAX_DRAWS: usize = 100;
+
+// Generate some synthetic data for training (only for demo purposes)
+pub fn generate_data(samples: usize, spec: &ModelSpec, rng: &mut impl Rng) -> Samples {
+    let features = spec.input_size;
+    let outputs = spec.output_size();
+
+    let mut data = Vec::with_capacity(samples * features);
+    let mut labels = Vec::with_capacity(samples * outputs);
+
+    for _ in 0..samples {
+        // Generate features
+        for _ in 0..features {
+            data.push(rng.gen_range(-1.0..1.0));
+        }
+
+        // Simple function to generate label: sum of first 3 features
+        let x = &data[data.len() - features..];
+        let sum: f32 = x.iter().take(3).sum();
+        match spec.loss {
+            Loss::Mse => labels.extend(std::iter::repeat_n(sum, outputs)),
+            // Positive sums are class 1
+            Loss::BinaryCrossEntropy => labels.extend(std::iter::repeat_n(
+                if sum > 0.0 { 1.0 } else { 0.0 },
+                outputs,
+            )),
+            // Split the range of the sum into equal-width class buckets
+            Loss::CategoricalCrossEntropy => {
+                let scaled = (sum / 3.0 + 1.0) / 2.0 * outputs as f32;
+                labels.push((scaled as usize).min(outputs - 1) as f32);
+            }
+        }
+    }
+
+    Samples { data, labels }
+}
+
+// How a dataset is divided among simulated clients
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PartitionStrategy {
+    // Uniformly shuffled, equally sized parts
+    Iid,
+    // Label skew: each class is spread over clients with Dirichlet(alpha) proportions
+    Dirichlet { alpha: f64 },
+    // Quantity skew: client dataset sizes follow Dirichlet(alpha) proportions
+    Quantity { alpha: f64 },
+    // Feature shift: IID parts with Gaussian noise of std `sigma * i / N` on client i
+    FeatureShift { sigma: f32 },
+    // Pathological split where each client only sees `classes_per_client` classes
+    Classes { classes_per_client: usize },
+}
+
+impl fmt::Display for PartitionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionStrategy::Iid => write!(f, "iid"),
+            PartitionStrategy::Dirichlet { alpha } => write!(f, "dirichlet:{}", alpha),
+            PartitionStrategy::Quantity { alpha } => write!(f, "quantity:{}", alpha),
+            PartitionStrategy::FeatureShift { sigma } => write!(f, "feature_shift:{}", sigma),
+            PartitionStrategy::Classes { classes_per_client } => {
+                write!(f, "classes:{}", classes_per_client)
+            }
+        }
+    }
+}
+
+impl FromStr for PartitionStrategy {
+    type Err = anyhow::Error;
+
+    // Parse a strategy such as "iid", "dirichlet:0.5" or "classes:2"
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, param) = match s.trim().split_once(':') {
+            Some((name, param)) => (name, Some(param.trim())),
+            None => (s.trim(), None),
+        };
+        let param = |what: &str| {
+            param.ok_or_else(|| anyhow!("Partition '{}' needs {}, e.g. {}:0.5", name, what, name))
+        };
+        let positive = |value: f64, what: &str| {
+            if value.is_finite() && value > 0.0 {
+                Ok(value)
+            } else {
+                Err(anyhow!("Partition {} must be positive", what))
+            }
+        };
+
+        let strategy = match name.to_lowercase().as_str() {
+            "iid" => PartitionStrategy::Iid,
+            "dirichlet" => PartitionStrategy::Dirichlet {
+                alpha: positive(param("alpha")?.parse()?, "alpha")?,
+            },
+            "quantity" => PartitionStrategy::Quantity {
+                alpha: positive(param("alpha")?.parse()?, "alpha")?,
+            },
+            "feature_shift" => PartitionStrategy::FeatureShift {
+                sigma: positive(param("sigma")?.parse()?, "sigma")? as f32,
+            },
+            "classes" | "shards" => {
+                let classes_per_client = param("a class count")?.parse()?;
+                if classes_per_client == 0 {
+                    return Err(anyhow!("Partition class count must be positive"));
+                }
+                PartitionStrategy::Classes { classes_per_client }
+            }
+            other => return Err(anyhow!("Unknown partition strategy: {}", other)),
+        };
+        Ok(strategy)
+    }
+}
+
+// Sample counts of one client's part
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientSummary {
+    pub client: usize,
+    pub samples: usize,
+    // Samples per class (quantile bucket for regression targets)
+    pub class_counts: Vec<usize>,
+}
+
+// Overview of how a dataset was divided
+#[derive(Clone, Debug, Serialize)]
+pub struct PartitionSummary {
+    pub strategy: PartitionStrategy,
+    pub num_classes: usize,
+    pub clients: Vec<ClientSummary>,
+}
+
+impl fmt::Display for PartitionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sizes: Vec<usize> = self.clients.iter().map(|c| c.samples).collect();
+        let total: usize = sizes.iter().sum();
+        writeln!(
+            f,
+            "Partition {} over {} clients: {} samples, {} to {} per client",
+            self.strategy,
+            self.clients.len(),
+            total,
+            sizes.iter().min().unwrap_or(&0),
+            sizes.iter().max().unwrap_or(&0)
+        )?;
+        for client in &self.clients {
+            let classes = client.class_counts.iter().filter(|&&n| n > 0).count();
+            writeln!(
+                f,
+                "  client {:>4}: {:>6} samples, {:>3}/{} classes {:?}",
+                client.client, client.samples, classes, self.num_classes, client.class_counts
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A dataset divided among clients
+pub struct Partition {
+    pub clients: Vec<Samples>,
+    pub summary: PartitionSummary,
+}
+
+// Divide `samples` among `num_clients` clients. The result only depends on the inputs and `seed`.
+pub fn partition(
+    samples: &Samples,
+    spec: &ModelSpec,
+    strategy: &PartitionStrategy,
+    num_clients: usize,
+    seed: u64,
+) -> Result<Partition> {
+    let features = spec.input_size;
+    if num_clients == 0 {
+        return Err(anyhow!("Need at least one client to partition for"));
+    }
+    if features == 0 || !samples.data.len().is_multiple_of(features) {
+        return Err(anyhow!(
+            "Data length {} is not a multiple of {} features",
+            samples.data.len(),
+            features
+        ));
+    }
+    let rows = samples.data.len() / features;
+    if rows < num_clients {
+        return Err(anyhow!(
+            "Cannot split {} samples over {} clients",
+            rows,
+            num_clients
+        ));
+    }
+    let label_width = samples.labels.len() / rows;
+    if label_width == 0 || label_width * rows != samples.labels.len() {
+        return Err(anyhow!(
+            "{} labels do not divide evenly over {} samples",
+            samples.labels.len(),
+            rows
+        ));
+    }
+
+    let (classes, num_classes) = classes_of(samples, spec.loss, rows, label_width);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let assignment = match strategy {
+        PartitionStrategy::Iid | PartitionStrategy::FeatureShift { .. } => {
+            iid(rows, num_clients, &mut rng)
+        }
+        PartitionStrategy::Dirichlet { alpha } => {
+            label_skew(&classes, num_classes, num_clients, *alpha, &mut rng)?
+        }
+        PartitionStrategy::Quantity { alpha } => {
+            quantity_skew(rows, num_clients, *alpha, &mut rng)?
+        }
+        PartitionStrategy::Classes { classes_per_client } => class_shards(
+            &classes,
+            num_classes,
+            num_clients,
+            *classes_per_client,
+            &mut rng,
+        )?,
+    };
+
+    let mut clients = Vec::with_capacity(num_clients);
+    let mut summaries = Vec::with_capacity(num_clients);
+    for (client, rows) in assignment.iter().enumerate() {
+        let mut part = Samples::default();
+        let mut class_counts = vec![0; num_classes];
+        for &row in rows {
+            part.data
+                .extend_from_slice(&samples.data[row * features..(row + 1) * features]);
+            part.labels
+                .extend_from_slice(&samples.labels[row * label_width..(row + 1) * label_width]);
+            class_counts[classes[row]] += 1;
+        }
+
+        // Each client sees its own shifted version of the feature space
+        if let PartitionStrategy::FeatureShift { sigma } = strategy {
+            let std = sigma * client as f32 / num_clients as f32;
+            if std > 0.0 {
+                let noise = Normal::new(0.0, std)
+                    .map_err(|e| anyhow!("Invalid feature shift noise: {}", e))?;
+                part.data
+                    .iter_mut()
+                    .for_each(|v| *v += noise.sample(&mut rng));
+            }
+        }
+
+        summaries.push(ClientSummary {
+            client,
+            samples: rows.len(),
+            class_counts,
+        });
+        clients.push(part);
+    }
+
+    Ok(Partition {
+        clients,
+        summary: PartitionSummary {
+            strategy: strategy.clone(),
+            num_classes,
+            clients: summaries,
+        },
+    })
+}
+
+// Class of every sample and the number of classes. Binary labels use the first
+// output, regression targets are bucketed into quantiles of the first output.
+fn classes_of(
+    samples: &Samples,
+    loss: Loss,
+    rows: usize,
+    label_width: usize,
+) -> (Vec<usize>, usize) {
+    let first = |row: usize| samples.labels[row * label_width];
+    match loss {
+        Loss::CategoricalCrossEntropy => {
+            let classes: Vec<usize> = (0..rows).map(|row| first(row).max(0.0) as usize).collect();
+            let num_classes = classes.iter().max().map_or(1, |c| c + 1);
+            (classes, num_classes)
+        }
+        Loss::BinaryCrossEntropy => (
+            (0..rows).map(|row| (first(row) >= 0.5) as usize).collect(),
+            2,
+        ),
+        Loss::Mse => {
+            let mut order: Vec<usize> = (0..rows).collect();
+            order.sort_by(|&a, &b| first(a).total_cmp(&first(b)));
+            let buckets = REGRESSION_BUCKETS.min(rows);
+            let mut classes = vec![0; rows];
+            for (rank, &row) in order.iter().enumerate() {
+                classes[row] = rank * buckets / rows;
+            }
+            (classes, buckets)
+        }
+    }
+}
+
+fn iid(rows: usize, num_clients: usize, rng: &mut StdRng) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..rows).collect();
+    order.shuffle(rng);
+    split_by_proportions(&order, &vec![1.0; num_clients])
+}
+
+fn quantity_skew(
+    rows: usize,
+    num_clients: usize,
+    alpha: f64,
+    rng: &mut StdRng,
+) -> Result<Vec<Vec<usize>>> {
+    let mut order: Vec<usize> = (0..rows).collect();
+    order.shuffle(rng);
+    redraw_until_nonempty(num_clients, alpha, rng, |proportions| {
+        split_by_proportions(&order, proportions)
+    })
+}
+
+fn label_skew(
+    classes: &[usize],
+    num_classes: usize,
+    num_clients: usize,
+    alpha: f64,
+    rng: &mut StdRng,
+) -> Result<Vec<Vec<usize>>> {
+    let by_class = rows_by_class(classes, num_classes, rng);
+    let mut attempt = 0;
+    loop {
+        // Fresh proportions for every class
+        let mut assignment = vec![Vec::new(); num_clients];
+        for rows in &by_class {
+            let proportions = dirichlet(num_clients, alpha, rng)?;
+            for (client, part) in split_by_proportions(rows, &proportions)
+                .into_iter()
+                .enumerate()
+            {
+                assignment[client].extend(part);
+            }
+        }
+        attempt += 1;
+        if assignment.iter().all(|rows| !rows.is_empty()) {
+            return Ok(assignment);
+        }
+        if attempt >= MAX_DRAWS {
+            return Err(empty_client_error(alpha));
+        }
+    }
+}
+
+fn class_shards(
+    classes: &[usize],
+    num_classes: usize,
+    num_clients: usize,
+    classes_per_client: usize,
+    rng: &mut StdRng,
+) -> Result<Vec<Vec<usize>>> {
+    if classes_per_client > num_classes {
+        return Err(anyhow!(
+            "Cannot give each client {} classes out of {}",
+            classes_per_client,
+            num_classes
+        ));
+    }
+
+    // Client i always owns class i mod C, plus randomly chosen other classes
+    let mut owners = vec![Vec::new(); num_classes];
+    for client in 0..num_clients {
+        let first = client % num_classes;
+        let mut others: Vec<usize> = (0..num_classes).filter(|&c| c != first).collect();
+        others.shuffle(rng);
+        for class in std::iter::once(first).chain(others.into_iter().take(classes_per_client - 1)) {
+            owners[class].push(client);
+        }
+    }
+
+    // Split every class evenly among its owners; classes nobody owns are dropped
+    let mut assignment = vec![Vec::new(); num_clients];
+    for (rows, owners) in rows_by_class(classes, num_classes, rng).iter().zip(&owners) {
+        if owners.is_empty() {
+            continue;
+        }
+        for (part, &client) in split_by_proportions(rows, &vec![1.0; owners.len()])
+            .into_iter()
+            .zip(owners)
+        {
+            assignment[client].extend(part);
+        }
+    }
+    if assignment.iter().any(|rows| rows.is_empty()) {
+        return Err(anyhow!(
+            "Some clients received no samples; use fewer clients or more data"
+        ));
+    }
+    Ok(assignment)
+}
+
+// Shuffled row indices of every class
+fn rows_by_class(classes: &[usize], num_classes: usize, rng: &mut StdRng) -> Vec<Vec<usize>> {
+    let mut by_class = vec![Vec::new(); num_classes];
+    for (row, &class) in classes.iter().enumerate() {
+        by_class[class].push(row);
+    }
+    by_class.iter_mut().for_each(|rows| rows.shuffle(rng));
+    by_class
+}
+
+// Draw Dirichlet proportions until no client ends up empty
+fn redraw_until_nonempty(
+    num_clients: usize,
+    alpha: f64,
+    rng: &mut StdRng,
+    split: impl Fn(&[f64]) -> Vec<Vec<usize>>,
+) -> Result<Vec<Vec<usize>>> {
+    for _ in 0..MAX_DRAWS {
+        let assignment = split(&dirichlet(num_clients, alpha, rng)?);
+        if assignment.iter().all(|rows| !rows.is_empty()) {
+            return Ok(assignment);
+        }
+    }
+    Err(empty_client_error(alpha))
+}
+
+fn dirichlet(num_clients: usize, alpha: f64, rng: &mut StdRng) -> Result<Vec<f64>> {
+    // A single client takes everything
+    if num_clients == 1 {
+        return Ok(vec![1.0]);
+    }
+    let distribution = Dirichlet::new_with_size(alpha, num_clients)
+        .map_err(|e| anyhow!("Invalid Dirichlet alpha {}: {}", alpha, e))?;
+    Ok(distribution.sample(rng))
+}
+
+fn empty_client_error(alpha: f64) -> anyhow::Error {
+    anyhow!(
+        "Could not give every client a sample with alpha {}; use a larger alpha or fewer clients",
+        alpha
+    )
+}
+
+// Cut `rows` into consecutive parts sized by `weights`
+fn split_by_proportions(rows: &[usize], weights: &[f64]) -> Vec<Vec<usize>> {
+    let total: f64 = weights.iter().sum();
+    let mut cumulative = 0.0;
+    let mut start = 0;
+    weights
+        .iter()
+        .map(|w| {
+            cumulative += w;
+            let end = ((cumulative / total) * rows.len() as f64).round() as usize;
+            let end = end.clamp(start, rows.len());
+            let part = rows[start..end].to_vec();
+            start = end;
+            part
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLASSES: usize = 4;
+
+    // Rows whose features hold the row index and whose label cycles over the classes
+    fn indexed(rows: usize) -> (Samples, ModelSpec) {
+        let samples = Samples {
+            data: (0..rows).flat_map(|row| [row as f32, 0.0]).collect(),
+            labels: (0..rows).map(|row| (row % CLASSES) as f32).collect(),
+        };
+        let spec = ModelSpec::parse(2, "4:linear", Loss::CategoricalCrossEntropy).unwrap();
+        (samples, spec)
+    }
+
+    fn rows_of(partition: &Partition) -> Vec<Vec<usize>> {
+        partition
+            .clients
+            .iter()
+            .map(|part| part.data.iter().step_by(2).map(|&v| v as usize).collect())
+            .collect()
+    }
+
+    fn strategies() -> Vec<PartitionStrategy> {
+        vec![
+            PartitionStrategy::Iid,
+            PartitionStrategy::Dirichlet { alpha: 0.5 },
+            PartitionStrategy::Quantity { alpha: 0.5 },
+            PartitionStrategy::Classes {
+                classes_per_client: 2,
+            },
+        ]
+    }
+
+    #[test]
+    fn every_sample_is_assigned_exactly_once() {
+        let (samples, spec) = indexed(200);
+        for strategy in strategies() {
+            let partition = partition(&samples, &spec, &strategy, 5, 11).unwrap();
+            let rows = rows_of(&partition);
+            assert!(rows.iter().all(|r| !r.is_empty()), "{}", strategy);
+
+            let mut all: Vec<usize> = rows.concat();
+            all.sort();
+            assert_eq!(all, (0..200).collect::<Vec<_>>(), "{}", strategy);
+
+            // Summaries match the parts
+            for (summary, rows) in partition.summary.clients.iter().zip(&rows) {
+                assert_eq!(summary.samples, rows.len());
+                assert_eq!(summary.class_counts.iter().sum::<usize>(), rows.len());
+            }
+        }
+    }
+
+    #[test]
+    fn partitions_only_depend_on_the_seed() {
+        let (samples, spec) = indexed(200);
+        for strategy in strategies() {
+            let first = rows_of(&partition(&samples, &spec, &strategy, 5, 11).unwrap());
+            let again = rows_of(&partition(&samples, &spec, &strategy, 5, 11).unwrap());
+            let other = rows_of(&partition(&samples, &spec, &strategy, 5, 12).unwrap());
+            assert_eq!(first, again, "{}", strategy);
+            assert_ne!(first, other, "{}", strategy);
+        }
+    }
+
+    #[test]
+    fn class_shards_hold_the_configured_classes() {
+        let (samples, spec) = indexed(200);
+        for classes_per_client in 1..=CLASSES {
+            let strategy = PartitionStrategy::Classes { classes_per_client };
+            let partition = partition(&samples, &spec, &strategy, 6, 3).unwrap();
+            for (client, summary) in partition.summary.clients.iter().enumerate() {
+                let held: Vec<usize> = (0..CLASSES)
+                    .filter(|&c| summary.class_counts[c] > 0)
+                    .collect();
+                assert_eq!(held.len(), classes_per_client);
+                // Client i always owns class i mod C
+                assert!(held.contains(&(client % CLASSES)));
+            }
+        }
+
+        let strategy = PartitionStrategy::Classes {
+            classes_per_client: CLASSES + 1,
+        };
+        assert!(partition(&samples, &spec, &strategy, 6, 3).is_err());
+    }
+
+    #[test]
+    fn feature_shift_grows_with_the_client_index() {
+        // Regression labels hold the row index, since the features get noise
+        let samples = Samples {
+            data: vec![0.0; 300],
+            labels: (0..100).map(|row| row as f32).collect(),
+        };
+        let spec = ModelSpec::parse(3, "1:linear", Loss::Mse).unwrap();
+        let strategy = PartitionStrategy::FeatureShift { sigma: 1.0 };
+        let shifted = partition(&samples, &spec, &strategy, 4, 5).unwrap();
+
+        let mut all: Vec<usize> = shifted
+            .clients
+            .iter()
+            .flat_map(|part| part.labels.iter().map(|&v| v as usize))
+            .collect();
+        all.sort();
+        assert_eq!(all, (0..100).collect::<Vec<_>>());
+
+        let spread: Vec<f32> = shifted
+            .clients
+            .iter()
+            .map(|part| {
+                let n = part.data.len() as f32;
+                (part.data.iter().map(|v| v * v).sum::<f32>() / n).sqrt()
+            })
+            .collect();
+        assert_eq!(spread[0], 0.0);
+        for (client, std) in spread.iter().enumerate().skip(1) {
+            let expected = client as f32 / 4.0;
+            assert!((std - expected).abs() < expected * 0.3, "{:?}", spread);
+        }
+
+        let again = partition(&samples, &spec, &strategy, 4, 5).unwrap();
+        assert_eq!(shifted.clients[3].data, again.clients[3].data);
+    }
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(
+            "dirichlet:0.5".parse::<PartitionStrategy>().unwrap(),
+            PartitionStrategy::Dirichlet { alpha: 0.5 }
+        );
+        assert_eq!(
+            "shards:2".parse::<PartitionStrategy>().unwrap(),
+            PartitionStrategy::Classes {
+                classes_per_client: 2
+            }
+        );
+        for invalid in [
+            "dirichlet",
+            "quantity:0",
+            "feature_shift:-1",
+            "classes:0",
+            "zipf",
+        ] {
+            assert!(invalid.parse::<PartitionStrategy>().is_err(), "{}", invalid);
+        }
+    }
+}
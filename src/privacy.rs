@@ -0,0 +1,557 @@
+use crate::seed::{self, Stream};
+use anyhow::{anyhow, Result};
+use rand::rngs::{OsRng, StdRng};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::Serialize;
+
+// Rényi DP orders the accountant tracks
+const RDP_ORDERS: [f64; 22] = [
+    1.25, 1.5, 1.75, 2.0, 2.5, 3.0, 3.5, 4.0, 5.0, 6.0, 8.0, 10.0, 12.0, 16.0, 20.0, 24.0, 32.0,
+    48.0, 64.0, 128.0, 256.0, 512.0,
+];
+
+// Client-level differential privacy settings of DP-FedAvg
+#[derive(Clone, Debug)]
+pub struct DpConfig {
+    // L2 bound on each client's update delta (the starting value with adaptive clipping)
+    pub clip_norm: f32,
+    // Std of the Gaussian noise on the summed deltas, in units of the clip norm
+    pub noise_multiplier: f64,
+    pub delta: f64,
+    // Stop training before the spent epsilon would exceed this
+    pub target_epsilon: Option<f64>,
+    pub adaptive: Option<AdaptiveClipping>,
+    // Derive the noise from the root seed for reproducible runs. Anyone who knows
+    // the seed can subtract the noise again, so this gives no privacy at all.
+    pub seeded_noise: bool,
+}
+
+// Adaptive clipping (Andrew et al., 2021): the clip norm follows a quantile of
+// the update norms, estimated privately from a noised count of unclipped updates
+#[derive(Clone, Debug)]
+pub struct AdaptiveClipping {
+    // Share of updates that should stay unclipped
+    pub target_quantile: f64,
+    // Geometric step size of the clip norm update
+    pub learning_rate: f64,
+    // Std of the noise on the count of unclipped updates
+    pub count_noise_std: f64,
+}
+
+impl DpConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.clip_norm.is_finite() && self.clip_norm > 0.0) {
+            return Err(anyhow!("DP clip norm must be positive"));
+        }
+        if !(self.noise_multiplier.is_finite() && self.noise_multiplier > 0.0) {
+            return Err(anyhow!("DP noise multiplier must be positive"));
+        }
+        if !(self.delta > 0.0 && self.delta < 1.0) {
+            return Err(anyhow!("DP delta must be in (0, 1)"));
+        }
+        if let Some(epsilon) = self.target_epsilon {
+            if !(epsilon.is_finite() && epsilon > 0.0) {
+                return Err(anyhow!("DP target epsilon must be positive"));
+            }
+            // Rounds only start within the budget, so a budget below one round allows no training
+            let mut accountant = RdpAccountant::default();
+            accountant.compose_gaussian(self.noise_multiplier);
+            let first_round = accountant.epsilon(self.delta);
+            if first_round > epsilon {
+                return Err(anyhow!(
+                    "A single DP-FedAvg round spends epsilon {:.3} at noise multiplier {}, above the target epsilon {}",
+                    first_round,
+                    self.noise_multiplier,
+                    epsilon
+                ));
+            }
+        }
+        if let Some(adaptive) = &self.adaptive {
+            if !(0.0..=1.0).contains(&adaptive.target_quantile) {
+                return Err(anyhow!("DP target quantile must be in [0, 1]"));
+            }
+            if !(adaptive.learning_rate.is_finite() && adaptive.learning_rate > 0.0) {
+                return Err(anyhow!("DP clip learning rate must be positive"));
+            }
+            // The count takes part of the noise budget, which needs 2 * count_noise_std > noise_multiplier
+            if 2.0 * adaptive.count_noise_std <= self.noise_multiplier
+                || !adaptive.count_noise_std.is_finite()
+            {
+                return Err(anyhow!(
+                    "DP count noise std must exceed half the noise multiplier ({})",
+                    self.noise_multiplier / 2.0
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Privacy loss tracked in Rényi DP and converted to (epsilon, delta)
+#[derive(Clone, Debug)]
+pub struct RdpAccountant {
+    // Accumulated RDP at each of `RDP_ORDERS`
+    rdp: Vec<f64>,
+    steps: u64,
+}
+
+impl Default for RdpAccountant {
+    fn default() -> Self {
+        Self {
+            rdp: vec![0.0; RDP_ORDERS.len()],
+            steps: 0,
+        }
+    }
+}
+
+impl RdpAccountant {
+    // Compose one release of the Gaussian mechanism with noise std `noise_multiplier`
+    // times the sensitivity. Every client takes part, so there is no sampling amplification.
+    pub fn compose_gaussian(&mut self, noise_multiplier: f64) {
+        self.compose_sampled_gaussian(1.0, noise_multiplier, 1);
+    }
+
+    // Compose `steps` releases of the Gaussian mechanism on a sample of rate `q`
+    pub fn compose_sampled_gaussian(&mut self, q: f64, noise_multiplier: f64, steps: u64) {
+        for (rdp, order) in self.rdp.iter_mut().zip(RDP_ORDERS) {
+            *rdp += steps as f64 * sampled_gaussian_rdp(q, noise_multiplier, order);
+        }
+        self.steps += steps;
+    }
+
+    // Smallest epsilon over the tracked orders (conversion of Balle et al., 2020)
+    pub fn epsilon(&self, delta: f64) -> f64 {
+        if self.steps == 0 {
+            return 0.0;
+        }
+        self.rdp
+            .iter()
+            .zip(RDP_ORDERS)
+            .map(|(rdp, order)| {
+                rdp + ((order - 1.0) / order).ln() - (delta.ln() + order.ln()) / (order - 1.0)
+            })
+            .fold(f64::INFINITY, f64::min)
+            .max(0.0)
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+// RDP of one step of the sampled Gaussian mechanism (Mironov et al., 2019). With
+// subsampling only integer orders are computed; the others are left unbounded.
+fn sampled_gaussian_rdp(q: f64, noise_multiplier: f64, order: f64) -> f64 {
+    let variance = noise_multiplier * noise_multiplier;
+    if q >= 1.0 {
+        return order / (2.0 * variance);
+    }
+    if order.fract() != 0.0 {
+        return f64::INFINITY;
+    }
+
+    // log of sum_k C(order, k) (1 - q)^(order - k) q^k exp((k^2 - k) / (2 variance))
+    let alpha = order as u64;
+    let mut log_binomial = 0.0;
+    let log_terms: Vec<f64> = (0..=alpha)
+        .map(|k| {
+            if k > 0 {
+                log_binomial += ((alpha - k + 1) as f64).ln() - (k as f64).ln();
+            }
+            log_binomial
+                + (alpha - k) as f64 * (1.0 - q).ln()
+                + k as f64 * q.ln()
+                + (k * k - k) as f64 / (2.0 * variance)
+        })
+        .collect();
+    let max = log_terms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let log_sum = max + log_terms.iter().map(|t| (t - max).exp()).sum::<f64>().ln();
+    log_sum / (order - 1.0)
+}
+
+// Privacy state exposed through the API
+#[derive(Serialize, Clone, Debug)]
+pub struct PrivacyReport {
+    pub noise_multiplier: f64,
+    // Clip norm for the next round
+    pub clip_norm: f32,
+    pub delta: f64,
+    // Epsilon spent so far
+    pub epsilon: f64,
+    pub target_epsilon: Option<f64>,
+    pub rounds: u64,
+    pub budget_exhausted: bool,
+}
+
+// DP-FedAvg aggregation: clipped client deltas averaged with Gaussian noise
+pub struct DpFedAvg {
+    config: DpConfig,
+    clip_norm: f32,
+    // Noise multiplier of the deltas; with adaptive clipping part of the
+    // budget goes to the unclipped count, so it is above `noise_multiplier`
+    delta_noise_multiplier: f64,
+    accountant: RdpAccountant,
+}
+
+impl DpFedAvg {
+    pub fn new(config: DpConfig) -> Self {
+        let z = config.noise_multiplier;
+        let delta_noise_multiplier = match &config.adaptive {
+            Some(adaptive) => (z.powi(-2) - (2.0 * adaptive.count_noise_std).powi(-2)).powf(-0.5),
+            None => z,
+        };
+        Self {
+            clip_norm: config.clip_norm,
+            config,
+            delta_noise_multiplier,
+            accountant: RdpAccountant::default(),
+        }
+    }
+
+    // New global parameters from the clients' parameters: each delta to `global`
+    // is clipped, and noise is added to the sum before dividing by `num_clients`
+    pub fn aggregate(
+        &mut self,
+        global: &[f32],
+        updates: &[Vec<f32>],
+        num_clients: usize,
+        rng: &mut impl Rng,
+    ) -> Vec<f32> {
+        let clip_norm = self.clip_norm;
+        let mut sum = vec![0.0f32; global.len()];
+        let mut unclipped = 0;
+        for params in updates {
+            let delta: Vec<f32> = params.iter().zip(global).map(|(p, g)| p - g).collect();
+            let norm = delta.iter().map(|d| d * d).sum::<f32>().sqrt();
+            if norm <= clip_norm {
+                unclipped += 1;
+            }
+            let scale = if norm > clip_norm {
+                clip_norm / norm
+            } else {
+                1.0
+            };
+            for (s, d) in sum.iter_mut().zip(&delta) {
+                *s += d * scale;
+            }
+        }
+
+        let n = num_clients.max(1) as f32;
+        let noise = Normal::new(0.0, self.delta_noise_multiplier * clip_norm as f64)
+            .expect("noise std is finite and non-negative");
+        let aggregated = global
+            .iter()
+            .zip(&sum)
+            .map(|(g, s)| g + (s + noise.sample(rng) as f32) / n)
+            .collect();
+
+        if let Some(adaptive) = &self.config.adaptive {
+            let count_noise = Normal::new(0.0, adaptive.count_noise_std)
+                .expect("count noise std is finite and non-negative");
+            let unclipped_fraction = (unclipped as f64 + count_noise.sample(rng)) / n as f64;
+            let step = -adaptive.learning_rate * (unclipped_fraction - adaptive.target_quantile);
+            self.clip_norm *= step.exp() as f32;
+        }
+
+        self.accountant
+            .compose_gaussian(self.config.noise_multiplier);
+        aggregated
+    }
+
+    pub fn config(&self) -> &DpConfig {
+        &self.config
+    }
+
+    // Whether one more round stays within the target epsilon
+    pub fn can_continue(&self) -> bool {
+        let Some(target) = self.config.target_epsilon else {
+            return true;
+        };
+        let mut next = self.accountant.clone();
+        next.compose_gaussian(self.config.noise_multiplier);
+        next.epsilon(self.config.delta) <= target
+    }
+
+    pub fn report(&self) -> PrivacyReport {
+        PrivacyReport {
+            noise_multiplier: self.config.noise_multiplier,
+            clip_norm: self.clip_norm,
+            delta: self.config.delta,
+            epsilon: self.accountant.epsilon(self.config.delta),
+            target_epsilon: self.config.target_epsilon,
+            rounds: self.accountant.steps(),
+            budget_exhausted: !self.can_continue(),
+        }
+    }
+}
+
+// Node-side DP-SGD settings. They stay on the node, so the server cannot relax them.
+#[derive(Clone, Debug)]
+pub struct DpSgdConfig {
+    // L2 bound on each example's gradient
+    pub clip_norm: f32,
+    // Std of the Gaussian noise on the summed gradients, in units of the clip norm
+    pub noise_multiplier: f64,
+    pub delta: f64,
+    // Budget the node refuses to train past
+    pub target_epsilon: Option<f64>,
+    // Derive the noise from the node's training seed for reproducible runs. The
+    // server may know that seed and subtract the noise, so this gives no privacy.
+    pub seeded_noise: bool,
+}
+
+impl DpSgdConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.clip_norm.is_finite() && self.clip_norm > 0.0) {
+            return Err(anyhow!("DP-SGD clip norm must be positive"));
+        }
+        if !(self.noise_multiplier.is_finite() && self.noise_multiplier > 0.0) {
+            return Err(anyhow!("DP-SGD noise multiplier must be positive"));
+        }
+        if !(self.delta > 0.0 && self.delta < 1.0) {
+            return Err(anyhow!("DP-SGD delta must be in (0, 1)"));
+        }
+        if let Some(epsilon) = self.target_epsilon {
+            if !(epsilon.is_finite() && epsilon > 0.0) {
+                return Err(anyhow!("DP-SGD target epsilon must be positive"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Per-example clipping and noise for the batches of one training run. The noise
+// comes from the OS unless the config asks for seeded noise.
+pub struct DpSgd<'a> {
+    pub config: &'a DpSgdConfig,
+    // Noise source with seeded noise, None to draw from the OS
+    noise_rng: Option<StdRng>,
+}
+
+impl<'a> DpSgd<'a> {
+    // `seed` is the training run's seed, only used with seeded noise
+    pub fn new(config: &'a DpSgdConfig, seed: u64) -> Self {
+        Self {
+            config,
+            noise_rng: config
+                .seeded_noise
+                .then(|| seed::rng(seed, Stream::DpSgdNoise, 0)),
+        }
+    }
+
+    // Clip each example's gradient, add noise to the sum and average over the batch
+    pub fn privatize(&mut self, per_example: &[Vec<f32>]) -> Vec<f32> {
+        match self.noise_rng.as_mut() {
+            Some(rng) => privatize(self.config, per_example, rng),
+            None => privatize(self.config, per_example, &mut OsRng),
+        }
+    }
+}
+
+// Clip each example's gradient to the clip norm, add noise from `rng` to the sum
+// and average over the batch
+fn privatize(config: &DpSgdConfig, per_example: &[Vec<f32>], rng: &mut impl Rng) -> Vec<f32> {
+    let clip_norm = config.clip_norm;
+    let len = per_example.first().map_or(0, Vec::len);
+    let mut sum = vec![0.0f32; len];
+    for grads in per_example {
+        let norm = grads.iter().map(|g| g * g).sum::<f32>().sqrt();
+        let scale = if norm > clip_norm {
+            clip_norm / norm
+        } else {
+            1.0
+        };
+        for (s, g) in sum.iter_mut().zip(grads) {
+            *s += g * scale;
+        }
+    }
+
+    let n = per_example.len().max(1) as f32;
+    let noise = Normal::new(0.0, config.noise_multiplier * clip_norm as f64)
+        .expect("noise std is finite and non-negative");
+    sum.iter()
+        .map(|s| (s + noise.sample(rng) as f32) / n)
+        .collect()
+}
+
+// A node's DP-SGD settings and the privacy it has spent
+#[derive(Clone, Debug)]
+pub struct LocalPrivacy {
+    pub config: DpSgdConfig,
+    accountant: RdpAccountant,
+}
+
+impl LocalPrivacy {
+    pub fn new(config: DpSgdConfig) -> Self {
+        Self {
+            config,
+            accountant: RdpAccountant::default(),
+        }
+    }
+
+    // Epsilon spent so far
+    pub fn epsilon(&self) -> f64 {
+        self.accountant.epsilon(self.config.delta)
+    }
+
+    // Epsilon after `steps` more steps on batches sampled at rate `q`; batches are
+    // treated as Poisson samples, as common DP-SGD implementations do
+    pub fn epsilon_after(&self, q: f64, steps: u64) -> f64 {
+        let mut next = self.accountant.clone();
+        next.compose_sampled_gaussian(q, self.config.noise_multiplier, steps);
+        next.epsilon(self.config.delta)
+    }
+
+    // Record a finished training run
+    pub fn spend(&mut self, q: f64, steps: u64) {
+        self.accountant
+            .compose_sampled_gaussian(q, self.config.noise_multiplier, steps);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dp_config(target_epsilon: Option<f64>, adaptive: Option<AdaptiveClipping>) -> DpConfig {
+        DpConfig {
+            clip_norm: 1.0,
+            noise_multiplier: 1.0,
+            delta: 1e-5,
+            target_epsilon,
+            adaptive,
+            seeded_noise: false,
+        }
+    }
+
+    #[test]
+    fn gaussian_epsilon_matches_reference() {
+        let mut accountant = RdpAccountant::default();
+        accountant.compose_gaussian(1.0);
+        // RDP of the Gaussian mechanism is order / (2 z^2)
+        for (rdp, order) in accountant.rdp.iter().zip(RDP_ORDERS) {
+            assert!((rdp - order / 2.0).abs() < 1e-12);
+        }
+        // Minimizing the conversion over all real orders gives 4.7284; the order grid stays close above it
+        let epsilon = accountant.epsilon(1e-5);
+        assert!((4.7284..4.76).contains(&epsilon), "epsilon {}", epsilon);
+    }
+
+    #[test]
+    fn sampled_gaussian_rdp_matches_reference() {
+        // Reference values of the TensorFlow Privacy RDP accountant tests
+        assert!((10.0 * sampled_gaussian_rdp(0.1, 2.0, 5.0) - 0.07737).abs() < 1e-5);
+        for (order, expected) in [(5.0, 0.00218075), (50.0, 0.023846), (100.0, 167.416307)] {
+            let rdp = 50.0 * sampled_gaussian_rdp(0.01, 2.5, order);
+            assert!((rdp - expected).abs() < 1e-5, "order {}: {}", order, rdp);
+        }
+        // Order 2 has the closed form log(1 + q^2 (e^(1/z^2) - 1))
+        let expected = (1.0 + 0.01f64.powi(2) * (1.0f64.exp() - 1.0)).ln();
+        assert!((sampled_gaussian_rdp(0.01, 1.0, 2.0) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn epsilon_grows_with_steps() {
+        let mut full = RdpAccountant::default();
+        let mut sampled = RdpAccountant::default();
+        let (mut last_full, mut last_sampled) = (0.0, 0.0);
+        for _ in 0..50 {
+            full.compose_gaussian(2.0);
+            sampled.compose_sampled_gaussian(0.01, 1.0, 100);
+            let (epsilon_full, epsilon_sampled) = (full.epsilon(1e-5), sampled.epsilon(1e-5));
+            assert!(epsilon_full > last_full && epsilon_sampled > last_sampled);
+            last_full = epsilon_full;
+            last_sampled = epsilon_sampled;
+        }
+        assert_eq!(full.steps(), 50);
+        assert_eq!(sampled.steps(), 5000);
+    }
+
+    #[test]
+    fn split_noise_recombines() {
+        let adaptive = AdaptiveClipping {
+            target_quantile: 0.5,
+            learning_rate: 0.2,
+            count_noise_std: 5.0,
+        };
+        let dp = DpFedAvg::new(dp_config(None, Some(adaptive)));
+        assert!(dp.delta_noise_multiplier > 1.0);
+        // Delta and count noise together release as much as noise multiplier z on its own
+        let recombined = (dp.delta_noise_multiplier.powi(-2) + 10.0f64.powi(-2)).powf(-0.5);
+        assert!((recombined - 1.0).abs() < 1e-12);
+
+        assert_eq!(
+            DpFedAvg::new(dp_config(None, None)).delta_noise_multiplier,
+            1.0
+        );
+    }
+
+    #[test]
+    fn budget_covers_whole_rounds() {
+        // One round at z = 1 spends about 4.75
+        assert!(dp_config(Some(2.0), None).validate().is_err());
+        let dp = DpFedAvg::new(dp_config(Some(6.0), None));
+        assert!(dp.can_continue());
+        let mut accountant = dp.accountant.clone();
+        accountant.compose_gaussian(1.0);
+        let spent = DpFedAvg { accountant, ..dp };
+        assert!(!spent.can_continue());
+    }
+
+    fn dp_sgd_config(noise_multiplier: f64, seeded_noise: bool) -> DpSgdConfig {
+        DpSgdConfig {
+            clip_norm: 1.0,
+            noise_multiplier,
+            delta: 1e-5,
+            target_epsilon: None,
+            seeded_noise,
+        }
+    }
+
+    #[test]
+    fn dp_sgd_clips_each_example() {
+        // Without noise a single example's output is its clipped gradient
+        let config = dp_sgd_config(0.0, false);
+        let mut dp_sgd = DpSgd::new(&config, 0);
+        for grads in [vec![3.0, 4.0], vec![-100.0, 0.5], vec![1e6, -1e6]] {
+            let clipped = dp_sgd.privatize(&[grads]);
+            let norm = clipped.iter().map(|g| g * g).sum::<f32>().sqrt();
+            assert!(norm <= config.clip_norm * (1.0 + 1e-6), "norm {}", norm);
+        }
+        // Gradients within the bound are left alone
+        assert_eq!(dp_sgd.privatize(&[vec![0.3, -0.4]]), vec![0.3, -0.4]);
+        // The batch average is bounded by the clip norm even if every example is large
+        let batch = dp_sgd.privatize(&[vec![10.0, 0.0], vec![0.0, 10.0], vec![7.0, 7.0]]);
+        assert!(batch.iter().map(|g| g * g).sum::<f32>().sqrt() <= config.clip_norm);
+    }
+
+    #[test]
+    fn dp_sgd_noise_is_seeded_only_on_request() {
+        let batch = [vec![0.1, 0.2, 0.3]];
+        let seeded = dp_sgd_config(1.0, true);
+        let first = DpSgd::new(&seeded, 7).privatize(&batch);
+        assert_eq!(first, DpSgd::new(&seeded, 7).privatize(&batch));
+        assert_ne!(first, DpSgd::new(&seeded, 8).privatize(&batch));
+
+        let unseeded = dp_sgd_config(1.0, false);
+        assert_ne!(
+            DpSgd::new(&unseeded, 7).privatize(&batch),
+            DpSgd::new(&unseeded, 7).privatize(&batch)
+        );
+    }
+
+    #[test]
+    fn local_epsilon_grows_with_steps() {
+        let mut privacy = LocalPrivacy::new(dp_sgd_config(1.0, false));
+        assert_eq!(privacy.epsilon(), 0.0);
+        let mut last = 0.0;
+        for _ in 0..10 {
+            let next = privacy.epsilon_after(0.1, 5);
+            privacy.spend(0.1, 5);
+            assert_eq!(privacy.epsilon(), next);
+            assert!(next > last, "epsilon {} after {}", next, last);
+            last = next;
+        }
+    }
+}
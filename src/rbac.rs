@@ -0,0 +1,303 @@
+use crate::auth::Enrollment;
+use crate::network::bearer;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Role a caller of the server's HTTP API acts in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Changes the federation's settings and manages node credentials
+    Admin,
+    // Reads the dashboard, metrics and the global model
+    Observer,
+    // Takes part in training: submits updates and fetches the global model
+    Node,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "admin" => Ok(Role::Admin),
+            "observer" => Ok(Role::Observer),
+            "node" => Ok(Role::Node),
+            other => Err(format!(
+                "Unknown role '{}', expected admin, observer or node",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Admin => write!(f, "admin"),
+            Role::Observer => write!(f, "observer"),
+            Role::Node => write!(f, "node"),
+        }
+    }
+}
+
+// Who may call a route
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    // Anyone: health checks, the dashboard page and enrollment, which has its own token
+    Public,
+    // Nodes only
+    Node,
+    // Observers and admins
+    Observer,
+    // The global model: observers, admins and nodes
+    Model,
+    // Admins only
+    Admin,
+}
+
+impl Access {
+    fn allows(self, role: Role) -> bool {
+        match self {
+            Access::Public | Access::Model => true,
+            Access::Node => role == Role::Node,
+            Access::Observer => matches!(role, Role::Observer | Role::Admin),
+            Access::Admin => role == Role::Admin,
+        }
+    }
+}
+
+// API keys of the server and the role each one grants
+pub struct AccessControl {
+    // Roles keyed by the SHA-256 hash of the key
+    keys: HashMap<String, Role>,
+}
+
+impl AccessControl {
+    pub fn new(keys: Vec<(Role, String)>) -> Self {
+        let keys = keys
+            .into_iter()
+            .map(|(role, key)| (sha256_hex(&key), role))
+            .collect();
+        Self { keys }
+    }
+
+    // Parse API keys given as comma-separated role:key pairs, e.g. "observer:abc,admin:def"
+    pub fn parse_keys(spec: &str) -> Result<Vec<(Role, String)>> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (role, key) = entry
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("API keys must be given as role:key pairs"))?;
+                if key.is_empty() {
+                    return Err(anyhow!("Empty API key for role {}", role));
+                }
+                Ok((
+                    role.parse().map_err(|e: String| anyhow!(e))?,
+                    key.to_string(),
+                ))
+            })
+            .collect()
+    }
+
+    // Role of a bearer token: an API key, or the credential of an enrolled node
+    fn role(&self, bearer: &str, enrollment: Option<&Enrollment>) -> Option<Role> {
+        if let Some(role) = self.keys.get(&sha256_hex(bearer)) {
+            return Some(*role);
+        }
+        enrollment
+            .filter(|enrollment| enrollment.authenticate(Some(bearer)).is_ok())
+            .map(|_| Role::Node)
+    }
+}
+
+// Middleware checking the caller's role against the route. The granted role is
+// stored in the request extensions for handlers that check it again.
+pub async fn authorize(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(access_control) = req.app_data::<web::Data<AccessControl>>().cloned() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    let access = access(req.method(), req.match_pattern().as_deref());
+    if access == Access::Public {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let enrollment = req.app_data::<web::Data<Enrollment>>().cloned();
+    let role = bearer(req.request()).and_then(|token| {
+        access_control.role(
+            token,
+            enrollment.as_ref().map(|enrollment| enrollment.get_ref()),
+        )
+    });
+    let response = match role {
+        Some(role) if access.allows(role) => {
+            req.extensions_mut().insert(role);
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body);
+        }
+        Some(role) => HttpResponse::Forbidden().json(serde_json::json!({
+            "status": "error",
+            "message": format!("The {} role may not {} {}", role, req.method(), req.path())
+        })),
+        None => HttpResponse::Unauthorized().json(serde_json::json!({
+            "status": "error",
+            "message": "Missing or unknown API key"
+        })),
+    };
+    warn!(
+        "Refused {} {}: {}",
+        req.method(),
+        req.path(),
+        response.status()
+    );
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+// Access rule of a route pattern; unknown routes are admin-only
+fn access(method: &Method, pattern: Option<&str>) -> Access {
+    match (method.as_str(), pattern.unwrap_or_default()) {
+        ("GET", "/") | ("GET", "/status") | ("POST", "/api/enroll") => Access::Public,
+        ("POST", "/message") => Access::Node,
+        ("GET", "/api/model/params") | ("GET", "/api/model/spec") => Access::Model,
+        ("GET", "/api/nodes")
+        | ("GET", "/api/model/safetensors")
+        | ("GET", "/api/model/onnx")
+        | ("GET", "/api/training/config")
+        | ("GET", "/api/metrics")
+        | ("GET", "/api/history")
+        | ("GET", "/api/privacy")
+        | ("POST", "/predict") => Access::Observer,
+        _ => Access::Admin,
+    }
+}
+
+fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::middleware;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    #[test]
+    fn routes_map_to_their_roles() {
+        let get = |pattern| access(&Method::GET, Some(pattern));
+        assert_eq!(get("/status"), Access::Public);
+        assert_eq!(access(&Method::POST, Some("/api/enroll")), Access::Public);
+        assert_eq!(access(&Method::POST, Some("/message")), Access::Node);
+        assert_eq!(get("/api/model/params"), Access::Model);
+        assert_eq!(get("/api/metrics"), Access::Observer);
+        assert_eq!(access(&Method::POST, Some("/predict")), Access::Observer);
+
+        // Unknown routes, other methods and unmatched paths are admin-only
+        assert_eq!(
+            access(&Method::POST, Some("/api/training/config")),
+            Access::Admin
+        );
+        assert_eq!(access(&Method::GET, Some("/message")), Access::Admin);
+        assert_eq!(get("/api/new-endpoint"), Access::Admin);
+        assert_eq!(access(&Method::GET, None), Access::Admin);
+    }
+
+    #[test]
+    fn access_rules_allow_their_roles() {
+        let allowed = |access: Access| {
+            [Role::Admin, Role::Observer, Role::Node]
+                .into_iter()
+                .filter(|&role| access.allows(role))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(allowed(Access::Node), vec![Role::Node]);
+        assert_eq!(allowed(Access::Observer), vec![Role::Admin, Role::Observer]);
+        assert_eq!(
+            allowed(Access::Model),
+            vec![Role::Admin, Role::Observer, Role::Node]
+        );
+        assert_eq!(allowed(Access::Admin), vec![Role::Admin]);
+    }
+
+    #[test]
+    fn parses_role_key_pairs() {
+        let keys = AccessControl::parse_keys(" observer:abc, admin:def ,").unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                (Role::Observer, "abc".to_string()),
+                (Role::Admin, "def".to_string())
+            ]
+        );
+        assert!(AccessControl::parse_keys("observer").is_err());
+        assert!(AccessControl::parse_keys("observer:").is_err());
+        assert!(AccessControl::parse_keys("root:abc").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_answers_by_role() {
+        let keys = vec![
+            (Role::Admin, "admin-key".to_string()),
+            (Role::Observer, "observer-key".to_string()),
+        ];
+        let ok = || async { HttpResponse::Ok().finish() };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(AccessControl::new(keys)))
+                .wrap(middleware::from_fn(authorize))
+                .route("/status", web::get().to(ok))
+                .route("/api/metrics", web::get().to(ok))
+                .route("/api/admin/nodes", web::get().to(ok)),
+        )
+        .await;
+
+        let status = |path: &str, key: Option<&str>| {
+            let mut req = TestRequest::get().uri(path);
+            if let Some(key) = key {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", key)));
+            }
+            req.to_request()
+        };
+        let cases = [
+            ("/status", None, StatusCode::OK),
+            ("/api/metrics", None, StatusCode::UNAUTHORIZED),
+            ("/api/metrics", Some("wrong-key"), StatusCode::UNAUTHORIZED),
+            ("/api/metrics", Some("observer-key"), StatusCode::OK),
+            ("/api/metrics", Some("admin-key"), StatusCode::OK),
+            (
+                "/api/admin/nodes",
+                Some("observer-key"),
+                StatusCode::FORBIDDEN,
+            ),
+            ("/api/admin/nodes", Some("admin-key"), StatusCode::OK),
+        ];
+        for (path, key, expected) in cases {
+            let response = call_service(&app, status(path, key)).await;
+            assert_eq!(response.status(), expected, "{} with {:?}", path, key);
+        }
+    }
+}
@@ -0,0 +1,524 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Weight of a round's outcome in a node's reputation
+const REPUTATION_DECAY: f64 = 0.8;
+// Weight of the previous rounds in the running mean update
+const MEAN_DECAY: f32 = 0.5;
+// Floor of the norm spread, relative to the median, so near-identical updates are not outliers
+const MIN_SPREAD: f32 = 0.05;
+// Scales the median absolute deviation to a standard deviation for normal data
+const MAD_SCALE: f32 = 1.4826;
+
+// What happens to a flagged update
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreeningAction {
+    // Log and count it, but aggregate it as usual
+    Flag,
+    // Aggregate it with a reduced weight
+    DownWeight,
+    // Leave it out of the aggregate
+    Quarantine,
+}
+
+impl fmt::Display for ScreeningAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScreeningAction::Flag => "flag",
+            ScreeningAction::DownWeight => "down_weight",
+            ScreeningAction::Quarantine => "quarantine",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ScreeningAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "flag" => Ok(ScreeningAction::Flag),
+            "down_weight" | "downweight" => Ok(ScreeningAction::DownWeight),
+            "quarantine" => Ok(ScreeningAction::Quarantine),
+            other => Err(anyhow!("Unknown screening action: {}", other)),
+        }
+    }
+}
+
+// Settings of the update-screening stage
+#[derive(Clone, Debug)]
+pub struct ScreeningConfig {
+    pub action: ScreeningAction,
+    // Robust z-score of an update's norm above which it is an outlier among the round's updates
+    pub norm_threshold: f32,
+    // Cosine similarity to the running mean update below which an update is flagged
+    pub min_cosine: f32,
+    // Factor by which an update's norm may exceed the node's usual norm
+    pub history_factor: f32,
+    // Relative rise of the holdout loss an update may cause, checked when a holdout is loaded
+    pub max_loss_increase: f32,
+    // Weight of flagged updates with the down_weight action
+    pub down_weight: f32,
+    // Reputation below which all of a node's updates are quarantined, unless the action is flag
+    pub min_reputation: f64,
+}
+
+impl ScreeningConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.norm_threshold <= 0.0 || self.norm_threshold.is_nan() {
+            return Err(anyhow!("The norm outlier threshold must be positive"));
+        }
+        if !(-1.0..=1.0).contains(&self.min_cosine) {
+            return Err(anyhow!("The minimum cosine similarity must be in [-1, 1]"));
+        }
+        if self.history_factor < 1.0 || self.history_factor.is_nan() {
+            return Err(anyhow!("The history factor must be at least 1"));
+        }
+        if self.max_loss_increase < 0.0 || self.max_loss_increase.is_nan() {
+            return Err(anyhow!("The maximum loss increase must not be negative"));
+        }
+        if !(0.0..=1.0).contains(&self.down_weight) {
+            return Err(anyhow!("The down-weight must be in [0, 1]"));
+        }
+        if !(0.0..=1.0).contains(&self.min_reputation) {
+            return Err(anyhow!("The minimum reputation must be in [0, 1]"));
+        }
+        Ok(())
+    }
+}
+
+// A check an update failed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "check", rename_all = "snake_case")]
+pub enum Finding {
+    // The update's norm is far above the round's median
+    NormOutlier { norm: f32, median: f32, score: f32 },
+    // The update points away from the running mean update
+    LowSimilarity { cosine: f32 },
+    // The update's norm is far above the node's own history
+    HistoryDeviation { norm: f32, typical: f32 },
+    // Applying the update alone makes the holdout loss worse
+    LossIncrease { loss: f32, global_loss: f32 },
+    // The node has been flagged too often
+    LowReputation { reputation: f64 },
+}
+
+impl Finding {
+    // Short label used as the metrics key
+    pub fn check(&self) -> &'static str {
+        match self {
+            Finding::NormOutlier { .. } => "norm_outlier",
+            Finding::LowSimilarity { .. } => "low_similarity",
+            Finding::HistoryDeviation { .. } => "history_deviation",
+            Finding::LossIncrease { .. } => "loss_increase",
+            Finding::LowReputation { .. } => "low_reputation",
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::NormOutlier {
+                norm,
+                median,
+                score,
+            } => write!(
+                f,
+                "update norm {:.4} is an outlier (median {:.4}, robust z-score {:.1})",
+                norm, median, score
+            ),
+            Finding::LowSimilarity { cosine } => write!(
+                f,
+                "cosine similarity {:.3} to the running mean update",
+                cosine
+            ),
+            Finding::HistoryDeviation { norm, typical } => write!(
+                f,
+                "update norm {:.4} against the node's usual {:.4}",
+                norm, typical
+            ),
+            Finding::LossIncrease { loss, global_loss } => write!(
+                f,
+                "holdout loss {:.4} against {:.4} for the global model",
+                loss, global_loss
+            ),
+            Finding::LowReputation { reputation } => {
+                write!(f, "node reputation {:.3}", reputation)
+            }
+        }
+    }
+}
+
+// Outcome of screening one update
+#[derive(Clone, Debug)]
+pub struct Verdict {
+    pub findings: Vec<Finding>,
+    // Weight in the aggregate: 1 for clean updates, 0 when quarantined
+    pub weight: f32,
+}
+
+impl Verdict {
+    pub fn flagged(&self) -> bool {
+        !self.findings.is_empty()
+    }
+}
+
+// Screening record of a node, shown in the nodes API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NodeReputation {
+    // Moving average of clean rounds, from 1 (never flagged) towards 0
+    pub reputation: f64,
+    pub screened_updates: u64,
+    pub flagged_updates: u64,
+    pub quarantined: bool,
+    // Checks the node's last update failed
+    pub last_findings: Vec<Finding>,
+    // Moving average of the node's clean update norms
+    #[serde(skip)]
+    typical_norm: Option<f32>,
+}
+
+impl Default for NodeReputation {
+    fn default() -> Self {
+        Self {
+            reputation: 1.0,
+            screened_updates: 0,
+            flagged_updates: 0,
+            quarantined: false,
+            last_findings: Vec::new(),
+            typical_norm: None,
+        }
+    }
+}
+
+// Holdout losses of the global model and of each update applied alone
+pub struct HoldoutLosses {
+    pub global: f32,
+    pub updates: Vec<f32>,
+}
+
+// Screens each round's updates against each other, the federation's history and each node's history
+pub struct UpdateScreener {
+    config: ScreeningConfig,
+    // Moving average of the accepted updates' deltas to the global model
+    running_mean: Option<Vec<f32>>,
+    nodes: HashMap<String, NodeReputation>,
+}
+
+impl UpdateScreener {
+    pub fn new(config: ScreeningConfig) -> Self {
+        Self {
+            config,
+            running_mean: None,
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn reputation(&self, node_addr: &str) -> Option<&NodeReputation> {
+        self.nodes.get(node_addr)
+    }
+
+    // Screen a round's updates, returning one verdict per update in the same order
+    pub fn screen(
+        &mut self,
+        global: &[f32],
+        updates: &[(String, Vec<f32>)],
+        holdout: Option<&HoldoutLosses>,
+    ) -> Vec<Verdict> {
+        let deltas: Vec<Vec<f32>> = updates
+            .iter()
+            .map(|(_, params)| params.iter().zip(global).map(|(p, g)| p - g).collect())
+            .collect();
+        let norms: Vec<f32> = deltas.iter().map(|delta| norm(delta)).collect();
+
+        // Robust z-scores need a few updates to tell an outlier from the rest
+        let spread = (norms.len() >= 3).then(|| {
+            let median = median(&norms);
+            let deviations: Vec<f32> = norms.iter().map(|n| (n - median).abs()).collect();
+            let scale = (MAD_SCALE * self::median(&deviations)).max(MIN_SPREAD * median);
+            (median, scale)
+        });
+
+        let mut verdicts = Vec::with_capacity(updates.len());
+        for (i, (node_addr, _)) in updates.iter().enumerate() {
+            let norm = norms[i];
+            let mut findings = Vec::new();
+
+            if let Some((median, scale)) = spread {
+                let score = if scale > 0.0 {
+                    (norm - median) / scale
+                } else {
+                    0.0
+                };
+                if score > self.config.norm_threshold {
+                    findings.push(Finding::NormOutlier {
+                        norm,
+                        median,
+                        score,
+                    });
+                }
+            }
+
+            if let Some(cosine) = self
+                .running_mean
+                .as_ref()
+                .and_then(|mean| cosine(&deltas[i], mean))
+            {
+                if cosine < self.config.min_cosine {
+                    findings.push(Finding::LowSimilarity { cosine });
+                }
+            }
+
+            let record = self.nodes.entry(node_addr.clone()).or_default();
+            if let Some(typical) = record.typical_norm {
+                if typical > 0.0 && norm > self.config.history_factor * typical {
+                    findings.push(Finding::HistoryDeviation { norm, typical });
+                }
+            }
+
+            if let Some(holdout) = holdout {
+                let loss = holdout.updates[i];
+                let limit = holdout.global + self.config.max_loss_increase * holdout.global.abs();
+                // A NaN loss counts as an increase
+                if loss > limit || loss.is_nan() {
+                    findings.push(Finding::LossIncrease {
+                        loss,
+                        global_loss: holdout.global,
+                    });
+                }
+            }
+
+            // Reputation only follows the checks above, so a quarantined node can earn its way back
+            let flagged = !findings.is_empty();
+            let outcome = if flagged { 0.0 } else { 1.0 };
+            record.reputation =
+                REPUTATION_DECAY * record.reputation + (1.0 - REPUTATION_DECAY) * outcome;
+            record.screened_updates += 1;
+            if flagged {
+                record.flagged_updates += 1;
+            } else {
+                record.typical_norm = Some(match record.typical_norm {
+                    Some(typical) => MEAN_DECAY * typical + (1.0 - MEAN_DECAY) * norm,
+                    None => norm,
+                });
+            }
+
+            let enforce = self.config.action != ScreeningAction::Flag;
+            record.quarantined = enforce && record.reputation < self.config.min_reputation;
+            if record.quarantined {
+                findings.push(Finding::LowReputation {
+                    reputation: record.reputation,
+                });
+            }
+            record.last_findings = findings.clone();
+
+            let weight = match (self.config.action, record.quarantined, flagged) {
+                (_, true, _) => 0.0,
+                (_, false, false) | (ScreeningAction::Flag, _, _) => 1.0,
+                (ScreeningAction::DownWeight, _, true) => self.config.down_weight,
+                (ScreeningAction::Quarantine, _, true) => 0.0,
+            };
+            verdicts.push(Verdict { findings, weight });
+        }
+
+        self.update_running_mean(&deltas, &verdicts);
+        verdicts
+    }
+
+    // Fold the weighted mean of this round's deltas into the running mean
+    fn update_running_mean(&mut self, deltas: &[Vec<f32>], verdicts: &[Verdict]) {
+        let total: f32 = verdicts.iter().map(|verdict| verdict.weight).sum();
+        if total <= 0.0 {
+            return;
+        }
+        let mut mean = vec![0.0; deltas[0].len()];
+        for (delta, verdict) in deltas.iter().zip(verdicts) {
+            for (m, d) in mean.iter_mut().zip(delta) {
+                *m += verdict.weight * d / total;
+            }
+        }
+        self.running_mean = Some(match self.running_mean.take() {
+            Some(previous) => previous
+                .iter()
+                .zip(&mean)
+                .map(|(p, m)| MEAN_DECAY * p + (1.0 - MEAN_DECAY) * m)
+                .collect(),
+            None => mean,
+        });
+    }
+}
+
+fn norm(values: &[f32]) -> f32 {
+    values.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+// Cosine similarity, None when either vector is zero
+fn cosine(a: &[f32], b: &[f32]) -> Option<f32> {
+    let (norm_a, norm_b) = (norm(a), norm(b));
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    Some(dot / (norm_a * norm_b))
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screener(action: ScreeningAction) -> UpdateScreener {
+        UpdateScreener::new(ScreeningConfig {
+            action,
+            norm_threshold: 3.0,
+            min_cosine: 0.0,
+            history_factor: 10.0,
+            max_loss_increase: 0.1,
+            down_weight: 0.25,
+            min_reputation: 0.7,
+        })
+    }
+
+    fn updates(deltas: &[[f32; 2]]) -> Vec<(String, Vec<f32>)> {
+        deltas
+            .iter()
+            .enumerate()
+            .map(|(i, delta)| (format!("node-{}", i), delta.to_vec()))
+            .collect()
+    }
+
+    fn checks(verdict: &Verdict) -> Vec<&'static str> {
+        verdict.findings.iter().map(Finding::check).collect()
+    }
+
+    #[test]
+    fn flags_a_norm_outlier_among_three_or_more_updates() {
+        let mut screener = screener(ScreeningAction::Flag);
+        let round = updates(&[[1.0, 0.0], [1.1, 0.0], [0.9, 0.0], [10.0, 0.0]]);
+        let verdicts = screener.screen(&[0.0, 0.0], &round, None);
+        assert_eq!(
+            verdicts.iter().map(checks).collect::<Vec<_>>(),
+            vec![vec![], vec![], vec![], vec!["norm_outlier"]]
+        );
+
+        // Two updates are not enough to tell which one is the outlier
+        let mut screener = self::screener(ScreeningAction::Flag);
+        let verdicts = screener.screen(&[0.0, 0.0], &updates(&[[1.0, 0.0], [10.0, 0.0]]), None);
+        assert!(verdicts.iter().all(|verdict| !verdict.flagged()));
+    }
+
+    #[test]
+    fn flags_an_update_against_the_running_mean() {
+        let mut screener = screener(ScreeningAction::Flag);
+        let global = [1.0, 1.0];
+        let first = updates(&[[2.0, 1.0], [2.0, 1.0], [2.0, 1.0]]);
+        assert!(screener
+            .screen(&global, &first, None)
+            .iter()
+            .all(|verdict| !verdict.flagged()));
+
+        // The third update moves the model the other way
+        let second = updates(&[[2.0, 1.0], [2.0, 1.2], [0.0, 1.0]]);
+        let verdicts = screener.screen(&global, &second, None);
+        assert_eq!(
+            verdicts.iter().map(checks).collect::<Vec<_>>(),
+            vec![vec![], vec![], vec!["low_similarity"]]
+        );
+        assert_eq!(
+            verdicts[2].findings[0],
+            Finding::LowSimilarity { cosine: -1.0 }
+        );
+    }
+
+    #[test]
+    fn quarantines_a_node_with_low_reputation_until_it_recovers() {
+        let mut screener = screener(ScreeningAction::Quarantine);
+        let update = updates(&[[1.0, 0.0]]);
+        let mut screen = |loss: f32| {
+            let holdout = HoldoutLosses {
+                global: 1.0,
+                updates: vec![loss],
+            };
+            let verdict = screener.screen(&[0.0, 0.0], &update, Some(&holdout));
+            let record = screener.reputation("node-0").unwrap().clone();
+            (verdict[0].clone(), record)
+        };
+
+        // Four rounds that make the holdout loss worse: 0.8, 0.64, 0.512, 0.41
+        for round in 0..4 {
+            let (verdict, record) = screen(2.0);
+            assert_eq!(verdict.weight, 0.0);
+            assert!(checks(&verdict).contains(&"loss_increase"));
+            assert_eq!(record.quarantined, round >= 1, "round {}", round);
+        }
+
+        // Clean updates stay out of the aggregate until the reputation is back: 0.53, 0.62, 0.70
+        for _ in 0..3 {
+            let (verdict, record) = screen(1.0);
+            assert_eq!(checks(&verdict), vec!["low_reputation"]);
+            assert_eq!(verdict.weight, 0.0);
+            assert!(record.quarantined);
+        }
+        let (verdict, record) = screen(1.0);
+        assert!(!verdict.flagged());
+        assert_eq!(verdict.weight, 1.0);
+        assert!(!record.quarantined);
+        assert!(record.reputation > 0.7);
+        assert_eq!(record.screened_updates, 8);
+        assert_eq!(record.flagged_updates, 4);
+    }
+
+    #[test]
+    fn weights_flagged_updates_by_action() {
+        let round = updates(&[[1.0, 0.0], [1.1, 0.0], [0.9, 0.0], [10.0, 0.0]]);
+        let weights = |action| {
+            screener(action)
+                .screen(&[0.0, 0.0], &round, None)
+                .iter()
+                .map(|verdict| verdict.weight)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(weights(ScreeningAction::Flag), vec![1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(
+            weights(ScreeningAction::DownWeight),
+            vec![1.0, 1.0, 1.0, 0.25]
+        );
+        assert_eq!(
+            weights(ScreeningAction::Quarantine),
+            vec![1.0, 1.0, 1.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn flag_action_never_quarantines() {
+        let mut screener = screener(ScreeningAction::Flag);
+        let holdout = HoldoutLosses {
+            global: 1.0,
+            updates: vec![f32::NAN],
+        };
+        for _ in 0..5 {
+            let verdict =
+                &screener.screen(&[0.0], &[("node-0".to_string(), vec![1.0])], Some(&holdout))[0];
+            assert_eq!(checks(verdict), vec!["loss_increase"]);
+            assert_eq!(verdict.weight, 1.0);
+        }
+        let record = screener.reputation("node-0").unwrap();
+        assert!(record.reputation < 0.7);
+        assert!(!record.quarantined);
+    }
+}
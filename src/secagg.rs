@@ -0,0 +1,1042 @@
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Secure aggregation with pairwise masking (Bonawitz et al., 2017). Each node adds
+// a mask shared with every other node (which cancels in the sum) and a self mask,
+// and secret-shares both mask secrets so that the masks of nodes dropping out can
+// be removed. The server only ever sees masked vectors and their sum.
+
+// Fixed-point scale of quantized parameters
+const SCALE: f64 = (1u64 << 20) as f64;
+// Prime 2^61 - 1 of the field secrets are shared in
+const PRIME: u64 = (1 << 61) - 1;
+// Secret bytes packed into one field element
+const CHUNK: usize = 7;
+// Key derivation contexts of the two uses of a key agreement
+const SHARE_CONTEXT: &[u8] = b"secagg share encryption";
+const MASK_CONTEXT: &[u8] = b"secagg pairwise mask";
+
+// Secure aggregation settings, used by the server and every node
+#[derive(Clone, Debug)]
+pub struct SecAggConfig {
+    // Nodes that must stay until a round is aggregated: total nodes minus tolerated dropouts
+    pub threshold: usize,
+    // How long the server waits for a phase before going on without the missing nodes
+    pub phase_timeout: Duration,
+}
+
+impl SecAggConfig {
+    pub fn new(total_nodes: usize, max_dropouts: usize, phase_timeout: Duration) -> Result<Self> {
+        if total_nodes < 2 {
+            return Err(anyhow!("Secure aggregation needs at least 2 nodes"));
+        }
+        // A majority must survive so that the server cannot collect both mask
+        // secrets of a node, and a sum of one update would reveal it
+        let limit = ((total_nodes - 1) / 2).min(total_nodes - 2);
+        if max_dropouts > limit {
+            return Err(anyhow!(
+                "Secure aggregation of {} nodes tolerates at most {} dropouts, got {}",
+                total_nodes,
+                limit,
+                max_dropouts
+            ));
+        }
+        let threshold = total_nodes - max_dropouts;
+        Ok(Self {
+            threshold,
+            phase_timeout,
+        })
+    }
+}
+
+// Shares of several nodes' secrets, keyed by the node each secret belongs to
+pub type SharesByNode = BTreeMap<String, Share>;
+
+// Public keys a node generates for one round
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NodeKeys {
+    pub node_addr: String,
+    // Key agreement key for encrypting shares between nodes
+    pub cipher_key: [u8; 32],
+    // Key agreement key the pairwise masks are derived from
+    pub mask_key: [u8; 32],
+}
+
+// Shamir share of a 32-byte secret, one field element per chunk
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Share {
+    pub x: u64,
+    pub y: Vec<u64>,
+}
+
+// A node's shares for one other node, encrypted so that the relaying server cannot read them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedShares {
+    pub from: String,
+    pub to: String,
+    pub ciphertext: Vec<u8>,
+}
+
+// Plaintext of `EncryptedShares`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ShareBundle {
+    from: String,
+    to: String,
+    // Share of the sender's mask key
+    key_share: Share,
+    // Share of the sender's self mask seed
+    seed_share: Share,
+}
+
+// Protocol messages, relayed by the server between the nodes of a round
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum SecAggMessage {
+    // Node: fresh public keys, sent once local training is done
+    AdvertiseKeys {
+        keys: NodeKeys,
+        round: u64,
+        num_samples: usize,
+        num_steps: usize,
+        epsilon: Option<f64>,
+    },
+    // Server: keys of every node that advertised, ordered by address
+    Keys {
+        round: u64,
+        keys: Vec<NodeKeys>,
+    },
+    // Node: encrypted shares of its secrets for every other node
+    ShareKeys {
+        node_addr: String,
+        round: u64,
+        shares: Vec<EncryptedShares>,
+    },
+    // Server: the shares addressed to one node
+    Shares {
+        round: u64,
+        shares: Vec<EncryptedShares>,
+    },
+    // Node: quantized parameters plus all masks
+    MaskedInput {
+        node_addr: String,
+        round: u64,
+        masked: Vec<u64>,
+        // Node's signature over the round, its address and the masked input, when enrolled
+        #[serde(default)]
+        signature: Option<String>,
+    },
+    // Server: nodes whose masked input arrived
+    Unmask {
+        round: u64,
+        survivors: Vec<String>,
+    },
+    // Node: mask key shares of the dropped nodes and self mask seed shares of the survivors
+    UnmaskShares {
+        node_addr: String,
+        round: u64,
+        key_shares: SharesByNode,
+        seed_shares: SharesByNode,
+    },
+}
+
+impl SecAggMessage {
+    // Node a message to the server comes from
+    pub fn sender(&self) -> Option<&str> {
+        match self {
+            SecAggMessage::AdvertiseKeys { keys, .. } => Some(&keys.node_addr),
+            SecAggMessage::ShareKeys { node_addr, .. }
+            | SecAggMessage::MaskedInput { node_addr, .. }
+            | SecAggMessage::UnmaskShares { node_addr, .. } => Some(node_addr),
+            SecAggMessage::Keys { .. }
+            | SecAggMessage::Shares { .. }
+            | SecAggMessage::Unmask { .. } => None,
+        }
+    }
+
+    fn round(&self) -> u64 {
+        match self {
+            SecAggMessage::AdvertiseKeys { round, .. }
+            | SecAggMessage::Keys { round, .. }
+            | SecAggMessage::ShareKeys { round, .. }
+            | SecAggMessage::Shares { round, .. }
+            | SecAggMessage::MaskedInput { round, .. }
+            | SecAggMessage::Unmask { round, .. }
+            | SecAggMessage::UnmaskShares { round, .. } => *round,
+        }
+    }
+}
+
+// Protocol step a node has completed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Advertised,
+    Shared,
+    Masked,
+    Unmasked,
+}
+
+// A node's side of one round
+pub struct SecAggClient {
+    node_addr: String,
+    round: u64,
+    threshold: usize,
+    // Quantized parameters to aggregate
+    input: Vec<u64>,
+    cipher_secret: StaticSecret,
+    mask_secret: StaticSecret,
+    // Seed of the self mask
+    seed: [u8; 32],
+    stage: Stage,
+    // Nodes that advertised keys, ordered by address
+    keys: Vec<NodeKeys>,
+    // Shares entrusted to this node, keyed by the node that made them
+    held: BTreeMap<String, ShareBundle>,
+}
+
+impl SecAggClient {
+    pub fn new(config: &SecAggConfig, node_addr: &str, round: u64, params: &[f32]) -> Self {
+        let mut seed = [0; 32];
+        OsRng.fill_bytes(&mut seed);
+        Self {
+            node_addr: node_addr.to_string(),
+            round,
+            threshold: config.threshold,
+            input: params.iter().map(|&p| quantize(p)).collect(),
+            cipher_secret: StaticSecret::random_from_rng(OsRng),
+            mask_secret: StaticSecret::random_from_rng(OsRng),
+            seed,
+            stage: Stage::Advertised,
+            keys: Vec::new(),
+            held: BTreeMap::new(),
+        }
+    }
+
+    // First message of the round, carrying the node's public keys and training summary
+    pub fn advertise(
+        &self,
+        num_samples: usize,
+        num_steps: usize,
+        epsilon: Option<f64>,
+    ) -> SecAggMessage {
+        SecAggMessage::AdvertiseKeys {
+            keys: self.public_keys(),
+            round: self.round,
+            num_samples,
+            num_steps,
+            epsilon,
+        }
+    }
+
+    // Answer a server message with the next step, if it calls for one
+    pub fn handle(&mut self, msg: SecAggMessage) -> Result<Option<SecAggMessage>> {
+        if msg.round() != self.round {
+            return Err(anyhow!(
+                "Message for round {} while in round {}",
+                msg.round(),
+                self.round
+            ));
+        }
+        match msg {
+            SecAggMessage::Keys { keys, .. } => self.share_keys(keys).map(Some),
+            SecAggMessage::Shares { shares, .. } => self.masked_input(shares).map(Some),
+            SecAggMessage::Unmask { survivors, .. } => self.unmask(survivors).map(Some),
+            _ => Ok(None), // Meant for the server
+        }
+    }
+
+    fn public_keys(&self) -> NodeKeys {
+        NodeKeys {
+            node_addr: self.node_addr.clone(),
+            cipher_key: PublicKey::from(&self.cipher_secret).to_bytes(),
+            mask_key: PublicKey::from(&self.mask_secret).to_bytes(),
+        }
+    }
+
+    // Split both mask secrets among the advertised nodes and encrypt each node's shares
+    fn share_keys(&mut self, keys: Vec<NodeKeys>) -> Result<SecAggMessage> {
+        self.expect_stage(Stage::Advertised)?;
+        if !keys.windows(2).all(|w| w[0].node_addr < w[1].node_addr) {
+            return Err(anyhow!("Advertised keys are not ordered by node"));
+        }
+        if keys.len() < self.threshold {
+            return Err(anyhow!(
+                "Only {} nodes advertised keys, {} needed",
+                keys.len(),
+                self.threshold
+            ));
+        }
+        if !keys.contains(&self.public_keys()) {
+            return Err(anyhow!("The node's own keys are missing or altered"));
+        }
+
+        let key_shares = split(&self.mask_secret.to_bytes(), keys.len(), self.threshold);
+        let seed_shares = split(&self.seed, keys.len(), self.threshold);
+        let mut shares = Vec::with_capacity(keys.len() - 1);
+        for ((peer, key_share), seed_share) in keys.iter().zip(key_shares).zip(seed_shares) {
+            let bundle = ShareBundle {
+                from: self.node_addr.clone(),
+                to: peer.node_addr.clone(),
+                key_share,
+                seed_share,
+            };
+            if peer.node_addr == self.node_addr {
+                self.held.insert(self.node_addr.clone(), bundle);
+                continue;
+            }
+            let cipher = self.cipher(&peer.cipher_key)?;
+            let plaintext = serde_json::to_vec(&bundle)?;
+            let ciphertext = cipher
+                .encrypt(
+                    &nonce(&self.node_addr, &peer.node_addr),
+                    Payload {
+                        msg: &plaintext,
+                        aad: &self.round.to_le_bytes(),
+                    },
+                )
+                .map_err(|_| anyhow!("Failed to encrypt shares"))?;
+            shares.push(EncryptedShares {
+                from: self.node_addr.clone(),
+                to: peer.node_addr.clone(),
+                ciphertext,
+            });
+        }
+        self.keys = keys;
+        self.stage = Stage::Shared;
+
+        Ok(SecAggMessage::ShareKeys {
+            node_addr: self.node_addr.clone(),
+            round: self.round,
+            shares,
+        })
+    }
+
+    // Keep the shares of the nodes that shared and mask the input with a mask per such node
+    fn masked_input(&mut self, shares: Vec<EncryptedShares>) -> Result<SecAggMessage> {
+        self.expect_stage(Stage::Shared)?;
+        for share in shares {
+            let peer = self
+                .keys
+                .iter()
+                .find(|k| k.node_addr == share.from && k.node_addr != self.node_addr)
+                .ok_or_else(|| anyhow!("Shares from unknown node {}", share.from))?;
+            if share.to != self.node_addr || self.held.contains_key(&share.from) {
+                return Err(anyhow!("Unexpected shares from {}", share.from));
+            }
+            let plaintext = self
+                .cipher(&peer.cipher_key)?
+                .decrypt(
+                    &nonce(&share.from, &share.to),
+                    Payload {
+                        msg: &share.ciphertext,
+                        aad: &self.round.to_le_bytes(),
+                    },
+                )
+                .map_err(|_| anyhow!("Shares from {} failed to decrypt", share.from))?;
+            let bundle: ShareBundle = serde_json::from_slice(&plaintext)?;
+            if bundle.from != share.from || bundle.to != self.node_addr {
+                return Err(anyhow!("Shares from {} are mislabelled", share.from));
+            }
+            self.held.insert(share.from, bundle);
+        }
+        if self.held.len() < self.threshold {
+            return Err(anyhow!(
+                "Only {} nodes shared keys, {} needed",
+                self.held.len(),
+                self.threshold
+            ));
+        }
+
+        let mut masked = self.input.clone();
+        add_mask(&mut masked, &self.seed, false);
+        for peer in self.keys.iter().filter(|k| k.node_addr != self.node_addr) {
+            if self.held.contains_key(&peer.node_addr) {
+                let pair_seed = agree(&self.mask_secret, &peer.mask_key, MASK_CONTEXT)?;
+                add_mask(&mut masked, &pair_seed, self.node_addr > peer.node_addr);
+            }
+        }
+        self.stage = Stage::Masked;
+
+        Ok(SecAggMessage::MaskedInput {
+            node_addr: self.node_addr.clone(),
+            round: self.round,
+            masked,
+            signature: None,
+        })
+    }
+
+    // Reveal the shares that remove the masks, never both secrets of the same node
+    fn unmask(&mut self, survivors: Vec<String>) -> Result<SecAggMessage> {
+        self.expect_stage(Stage::Masked)?;
+        let survivors: BTreeSet<String> = survivors.into_iter().collect();
+        if survivors.len() < self.threshold {
+            return Err(anyhow!(
+                "Only {} nodes sent masked inputs, {} needed",
+                survivors.len(),
+                self.threshold
+            ));
+        }
+        if !survivors.contains(&self.node_addr)
+            || survivors.iter().any(|s| !self.held.contains_key(s))
+        {
+            return Err(anyhow!("Survivors include nodes that did not share keys"));
+        }
+
+        let mut key_shares = BTreeMap::new();
+        let mut seed_shares = BTreeMap::new();
+        for (owner, bundle) in &self.held {
+            if survivors.contains(owner) {
+                seed_shares.insert(owner.clone(), bundle.seed_share.clone());
+            } else {
+                key_shares.insert(owner.clone(), bundle.key_share.clone());
+            }
+        }
+        self.stage = Stage::Unmasked;
+
+        Ok(SecAggMessage::UnmaskShares {
+            node_addr: self.node_addr.clone(),
+            round: self.round,
+            key_shares,
+            seed_shares,
+        })
+    }
+
+    fn expect_stage(&self, stage: Stage) -> Result<()> {
+        if self.stage != stage {
+            return Err(anyhow!(
+                "Out of order message after the {:?} step",
+                self.stage
+            ));
+        }
+        Ok(())
+    }
+
+    fn cipher(&self, peer_key: &[u8; 32]) -> Result<ChaCha20Poly1305> {
+        let key = agree(&self.cipher_secret, peer_key, SHARE_CONTEXT)?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
+
+// Protocol phase the server is collecting messages for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    AdvertiseKeys,
+    ShareKeys,
+    MaskedInput,
+    Unmasking,
+}
+
+// What the server does after a phase
+pub enum Advance {
+    // Send these messages to the named nodes and collect the next phase
+    Send(Vec<(String, SecAggMessage)>),
+    // The round's average of the surviving nodes' parameters
+    Done { params: Vec<f32>, survivors: usize },
+}
+
+// The server's side of a round: relays keys and shares, then unmasks the sum
+pub struct SecAggServer {
+    config: SecAggConfig,
+    total_nodes: usize,
+    param_count: usize,
+    // Increases with every phase, so that timeouts of earlier phases are ignored
+    step: u64,
+    phase: Phase,
+    // Nodes that advertised keys
+    keys: BTreeMap<String, NodeKeys>,
+    // Nodes that shared their secrets, with their encrypted shares
+    shares: BTreeMap<String, Vec<EncryptedShares>>,
+    // Nodes that sent a masked input
+    masked: BTreeMap<String, Vec<u64>>,
+    // Nodes that sent unmasking shares: (mask key shares, self mask seed shares)
+    unmasking: BTreeMap<String, (SharesByNode, SharesByNode)>,
+}
+
+impl SecAggServer {
+    pub fn new(config: SecAggConfig, total_nodes: usize, param_count: usize) -> Self {
+        Self {
+            config,
+            total_nodes,
+            param_count,
+            step: 0,
+            phase: Phase::AdvertiseKeys,
+            keys: BTreeMap::new(),
+            shares: BTreeMap::new(),
+            masked: BTreeMap::new(),
+            unmasking: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &SecAggConfig {
+        &self.config
+    }
+
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    // Whether the round has not started yet, or is waiting for the nodes' keys
+    pub fn collecting_keys(&self) -> bool {
+        self.phase == Phase::AdvertiseKeys
+    }
+
+    // Nodes that answered the current phase
+    pub fn responses(&self) -> usize {
+        match self.phase {
+            Phase::AdvertiseKeys => self.keys.len(),
+            Phase::ShareKeys => self.shares.len(),
+            Phase::MaskedInput => self.masked.len(),
+            Phase::Unmasking => self.unmasking.len(),
+        }
+    }
+
+    // Record a node's message, returning whether every node expected in the phase has answered
+    pub fn receive(&mut self, round: u64, msg: SecAggMessage) -> Result<bool, String> {
+        if msg.round() != round {
+            return Err(format!(
+                "Secure aggregation message for round {} during round {}",
+                msg.round(),
+                round
+            ));
+        }
+        match (self.phase, msg) {
+            (Phase::AdvertiseKeys, SecAggMessage::AdvertiseKeys { keys, .. }) => {
+                if self.keys.contains_key(&keys.node_addr) {
+                    return Err(format!("Node {} already advertised keys", keys.node_addr));
+                }
+                self.keys.insert(keys.node_addr.clone(), keys);
+                Ok(self.keys.len() >= self.total_nodes)
+            }
+            (
+                Phase::ShareKeys,
+                SecAggMessage::ShareKeys {
+                    node_addr, shares, ..
+                },
+            ) => {
+                if !self.keys.contains_key(&node_addr) || self.shares.contains_key(&node_addr) {
+                    return Err(format!("Unexpected shares from node {}", node_addr));
+                }
+                // Exactly one share for every other node that advertised keys
+                let recipients: BTreeSet<&String> = shares.iter().map(|s| &s.to).collect();
+                let expected: BTreeSet<&String> =
+                    self.keys.keys().filter(|&k| k != &node_addr).collect();
+                if recipients != expected
+                    || shares.len() != expected.len()
+                    || shares.iter().any(|s| s.from != node_addr)
+                {
+                    return Err(format!(
+                        "Node {} did not send one share per other node",
+                        node_addr
+                    ));
+                }
+                self.shares.insert(node_addr, shares);
+                Ok(self.shares.len() == self.keys.len())
+            }
+            (
+                Phase::MaskedInput,
+                SecAggMessage::MaskedInput {
+                    node_addr, masked, ..
+                },
+            ) => {
+                if !self.shares.contains_key(&node_addr) || self.masked.contains_key(&node_addr) {
+                    return Err(format!("Unexpected masked input from node {}", node_addr));
+                }
+                if masked.len() != self.param_count {
+                    return Err(format!(
+                        "Expected {} masked parameters, got {}",
+                        self.param_count,
+                        masked.len()
+                    ));
+                }
+                self.masked.insert(node_addr, masked);
+                Ok(self.masked.len() == self.shares.len())
+            }
+            (
+                Phase::Unmasking,
+                SecAggMessage::UnmaskShares {
+                    node_addr,
+                    key_shares,
+                    seed_shares,
+                    ..
+                },
+            ) => {
+                if !self.masked.contains_key(&node_addr) || self.unmasking.contains_key(&node_addr)
+                {
+                    return Err(format!(
+                        "Unexpected unmasking shares from node {}",
+                        node_addr
+                    ));
+                }
+                let dropped: BTreeSet<&String> = self.dropped().collect();
+                if !key_shares.keys().eq(dropped.iter().copied())
+                    || !seed_shares.keys().eq(self.masked.keys())
+                {
+                    return Err(format!(
+                        "Node {} sent shares for the wrong nodes",
+                        node_addr
+                    ));
+                }
+                self.unmasking.insert(node_addr, (key_shares, seed_shares));
+                Ok(self.unmasking.len() == self.masked.len())
+            }
+            (phase, msg) => Err(format!(
+                "Unexpected {} while collecting {:?}",
+                message_name(&msg),
+                phase
+            )),
+        }
+    }
+
+    // Close the current phase with the nodes that answered. The state is cleared
+    // when the round is done or cannot continue.
+    pub fn advance(&mut self, round: u64) -> Result<Advance, String> {
+        let result = self.next_phase(round);
+        self.step += 1;
+        if !matches!(result, Ok(Advance::Send(_))) {
+            self.reset();
+        }
+        result
+    }
+
+    // Drop the round's state and wait for new keys
+    pub fn reset(&mut self) {
+        self.step += 1;
+        self.phase = Phase::AdvertiseKeys;
+        self.keys.clear();
+        self.shares.clear();
+        self.masked.clear();
+        self.unmasking.clear();
+    }
+
+    fn next_phase(&mut self, round: u64) -> Result<Advance, String> {
+        let responses = self.responses();
+        if responses < self.config.threshold {
+            return Err(format!(
+                "only {} nodes answered the {:?} phase, {} needed",
+                responses, self.phase, self.config.threshold
+            ));
+        }
+        match self.phase {
+            Phase::AdvertiseKeys => {
+                self.phase = Phase::ShareKeys;
+                let keys: Vec<NodeKeys> = self.keys.values().cloned().collect();
+                Ok(Advance::Send(
+                    self.keys
+                        .keys()
+                        .map(|node| {
+                            let msg = SecAggMessage::Keys {
+                                round,
+                                keys: keys.clone(),
+                            };
+                            (node.clone(), msg)
+                        })
+                        .collect(),
+                ))
+            }
+            Phase::ShareKeys => {
+                self.phase = Phase::MaskedInput;
+                Ok(Advance::Send(
+                    self.shares
+                        .keys()
+                        .map(|node| {
+                            let shares = self
+                                .shares
+                                .values()
+                                .flatten()
+                                .filter(|s| &s.to == node)
+                                .cloned()
+                                .collect();
+                            (node.clone(), SecAggMessage::Shares { round, shares })
+                        })
+                        .collect(),
+                ))
+            }
+            Phase::MaskedInput => {
+                self.phase = Phase::Unmasking;
+                let survivors: Vec<String> = self.masked.keys().cloned().collect();
+                Ok(Advance::Send(
+                    survivors
+                        .iter()
+                        .map(|node| {
+                            let msg = SecAggMessage::Unmask {
+                                round,
+                                survivors: survivors.clone(),
+                            };
+                            (node.clone(), msg)
+                        })
+                        .collect(),
+                ))
+            }
+            Phase::Unmasking => {
+                let sum = self.unmasked_sum().map_err(|e| e.to_string())?;
+                let survivors = self.masked.len();
+                Ok(Advance::Done {
+                    params: sum
+                        .into_iter()
+                        .map(|s| (dequantize(s) / survivors as f64) as f32)
+                        .collect(),
+                    survivors,
+                })
+            }
+        }
+    }
+
+    // Nodes that shared their secrets but sent no masked input
+    fn dropped(&self) -> impl Iterator<Item = &String> {
+        self.shares
+            .keys()
+            .filter(|node| !self.masked.contains_key(*node))
+    }
+
+    // Sum of the masked inputs with the self masks of the survivors and the
+    // pairwise masks shared with dropped nodes removed
+    fn unmasked_sum(&self) -> Result<Vec<u64>> {
+        let mut sum = vec![0u64; self.param_count];
+        for masked in self.masked.values() {
+            for (s, m) in sum.iter_mut().zip(masked) {
+                *s = s.wrapping_add(*m);
+            }
+        }
+
+        for node in self.masked.keys() {
+            let shares: Vec<Share> = self
+                .unmasking
+                .values()
+                .filter_map(|(_, seeds)| seeds.get(node).cloned())
+                .collect();
+            let seed = combine(&shares, self.config.threshold)?;
+            add_mask(&mut sum, &seed, true);
+        }
+
+        for dropped in self.dropped() {
+            let shares: Vec<Share> = self
+                .unmasking
+                .values()
+                .filter_map(|(keys, _)| keys.get(dropped).cloned())
+                .collect();
+            let secret = StaticSecret::from(combine(&shares, self.config.threshold)?);
+            if PublicKey::from(&secret).to_bytes() != self.keys[dropped].mask_key {
+                return Err(anyhow!(
+                    "Reconstructed mask key of {} does not match its public key",
+                    dropped
+                ));
+            }
+            // Undo the mask each survivor shares with the dropped node
+            for survivor in self.masked.keys() {
+                let pair_seed = agree(&secret, &self.keys[survivor].mask_key, MASK_CONTEXT)?;
+                add_mask(&mut sum, &pair_seed, survivor < dropped);
+            }
+        }
+        Ok(sum)
+    }
+}
+
+fn message_name(msg: &SecAggMessage) -> &'static str {
+    match msg {
+        SecAggMessage::AdvertiseKeys { .. } => "keys",
+        SecAggMessage::ShareKeys { .. } => "shares",
+        SecAggMessage::MaskedInput { .. } => "masked input",
+        SecAggMessage::UnmaskShares { .. } => "unmasking shares",
+        SecAggMessage::Keys { .. }
+        | SecAggMessage::Shares { .. }
+        | SecAggMessage::Unmask { .. } => "server message",
+    }
+}
+
+fn quantize(value: f32) -> u64 {
+    (value as f64 * SCALE).round() as i64 as u64
+}
+
+fn dequantize(value: u64) -> f64 {
+    value as i64 as f64 / SCALE
+}
+
+// Add (or subtract) the pseudorandom mask expanded from `seed`, modulo 2^64
+fn add_mask(values: &mut [u64], seed: &[u8; 32], subtract: bool) {
+    let mut rng = ChaCha20Rng::from_seed(*seed);
+    for value in values {
+        let mask = rng.next_u64();
+        *value = if subtract {
+            value.wrapping_sub(mask)
+        } else {
+            value.wrapping_add(mask)
+        };
+    }
+}
+
+// Diffie-Hellman on X25519, hashed with a context into a 32-byte key
+fn agree(secret: &StaticSecret, public: &[u8; 32], context: &[u8]) -> Result<[u8; 32]> {
+    let shared = secret.diffie_hellman(&PublicKey::from(*public));
+    if !shared.was_contributory() {
+        return Err(anyhow!("Rejected a low-order public key"));
+    }
+    Ok(Sha256::new()
+        .chain_update(context)
+        .chain_update(shared.as_bytes())
+        .finalize()
+        .into())
+}
+
+// Each pair of nodes shares one key and exchanges one message per direction,
+// so the direction alone makes the nonce unique
+fn nonce(from: &str, to: &str) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[0] = if from < to { 1 } else { 2 };
+    *Nonce::from_slice(&nonce)
+}
+
+// Shamir-share a secret among `holders` nodes so that any `threshold` of them can rebuild it
+fn split(secret: &[u8; 32], holders: usize, threshold: usize) -> Vec<Share> {
+    // One polynomial per chunk, with the chunk as the constant term
+    let polynomials: Vec<Vec<u64>> = secret
+        .chunks(CHUNK)
+        .map(|chunk| {
+            let constant = chunk.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64);
+            std::iter::once(constant)
+                .chain((1..threshold).map(|_| OsRng.gen_range(0..PRIME)))
+                .collect()
+        })
+        .collect();
+    (1..=holders as u64)
+        .map(|x| Share {
+            x,
+            y: polynomials
+                .iter()
+                .map(|coefficients| {
+                    coefficients
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, &c| field_add(field_mul(acc, x), c))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// Rebuild a secret from at least `threshold` shares by Lagrange interpolation at 0
+fn combine(shares: &[Share], threshold: usize) -> Result<[u8; 32]> {
+    if shares.len() < threshold {
+        return Err(anyhow!(
+            "Only {} shares of a secret, {} needed",
+            shares.len(),
+            threshold
+        ));
+    }
+    let shares = &shares[..threshold];
+    let xs: BTreeSet<u64> = shares.iter().map(|s| s.x).collect();
+    let chunks = 32usize.div_ceil(CHUNK);
+    if xs.len() != shares.len() || xs.contains(&0) || shares.iter().any(|s| s.y.len() != chunks) {
+        return Err(anyhow!("Malformed secret shares"));
+    }
+
+    let mut secret = [0; 32];
+    for (chunk, bytes) in secret.chunks_mut(CHUNK).enumerate() {
+        let value = shares.iter().fold(0, |acc, share| {
+            let basis = shares
+                .iter()
+                .filter(|o| o.x != share.x)
+                .fold(1, |basis, other| {
+                    field_mul(
+                        basis,
+                        field_mul(other.x, field_inverse(field_sub(other.x, share.x))),
+                    )
+                });
+            field_add(acc, field_mul(share.y[chunk], basis))
+        });
+        if value >> (8 * bytes.len()) != 0 {
+            return Err(anyhow!("Secret shares are inconsistent"));
+        }
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (value >> (8 * i)) as u8;
+        }
+    }
+    Ok(secret)
+}
+
+fn field_add(a: u64, b: u64) -> u64 {
+    (a + b) % PRIME
+}
+
+fn field_sub(a: u64, b: u64) -> u64 {
+    (a + PRIME - b) % PRIME
+}
+
+fn field_mul(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) % PRIME as u128) as u64
+}
+
+// Inverse by Fermat's little theorem
+fn field_inverse(a: u64) -> u64 {
+    let (mut base, mut exponent, mut result) = (a, PRIME - 2, 1);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = field_mul(result, base);
+        }
+        base = field_mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(i: usize) -> String {
+        format!("http://127.0.0.1:{}", 8002 + i)
+    }
+
+    fn clients(config: &SecAggConfig, params: &[Vec<f32>]) -> Vec<SecAggClient> {
+        params
+            .iter()
+            .enumerate()
+            .map(|(i, p)| SecAggClient::new(config, &node(i), 0, p))
+            .collect()
+    }
+
+    // Drive a whole round through the server; the `dropped` nodes leave after sharing their keys
+    fn run_round(params: &[Vec<f32>], max_dropouts: usize, dropped: &[usize]) -> (Vec<f32>, usize) {
+        let config =
+            SecAggConfig::new(params.len(), max_dropouts, Duration::from_secs(30)).unwrap();
+        let mut clients = clients(&config, params);
+        let mut server = SecAggServer::new(config, params.len(), params[0].len());
+        let mut replies: Vec<SecAggMessage> =
+            clients.iter().map(|c| c.advertise(1, 1, None)).collect();
+        loop {
+            for reply in replies.drain(..) {
+                server.receive(0, reply).unwrap();
+            }
+            match server.advance(0).unwrap() {
+                Advance::Done { params, survivors } => return (params, survivors),
+                Advance::Send(messages) => {
+                    for (addr, msg) in messages {
+                        let i = (0..params.len()).find(|&i| node(i) == addr).unwrap();
+                        let reply = clients[i].handle(msg).unwrap().unwrap();
+                        if dropped.contains(&i)
+                            && matches!(reply, SecAggMessage::MaskedInput { .. })
+                        {
+                            continue;
+                        }
+                        replies.push(reply);
+                    }
+                }
+            }
+        }
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= 1e-5, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn shamir_round_trip_at_threshold() {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let shares = split(&secret, 5, 3);
+        assert_eq!(combine(&shares[..3], 3).unwrap(), secret);
+        assert_eq!(combine(&shares[2..], 3).unwrap(), secret);
+    }
+
+    #[test]
+    fn shamir_fails_below_threshold() {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let shares = split(&secret, 5, 3);
+        assert!(combine(&shares[..2], 3).is_err());
+        // Interpolating fewer shares than the threshold does not give the secret back
+        assert!(!matches!(combine(&shares[..2], 2), Ok(rebuilt) if rebuilt == secret));
+    }
+
+    #[test]
+    fn pairwise_masks_cancel() {
+        let params = vec![
+            vec![0.5, -1.25, 3.0],
+            vec![2.0, 0.75, -0.5],
+            vec![-1.0, 1.0, 0.25],
+        ];
+        let config = SecAggConfig::new(3, 1, Duration::from_secs(30)).unwrap();
+        let mut clients = clients(&config, &params);
+        let keys: Vec<NodeKeys> = clients.iter().map(|c| c.public_keys()).collect();
+        let shares: Vec<EncryptedShares> = clients
+            .iter_mut()
+            .flat_map(|c| match c.share_keys(keys.clone()).unwrap() {
+                SecAggMessage::ShareKeys { shares, .. } => shares,
+                _ => unreachable!(),
+            })
+            .collect();
+
+        let mut sum = vec![0u64; 3];
+        for client in &mut clients {
+            let own = shares
+                .iter()
+                .filter(|s| s.to == client.node_addr)
+                .cloned()
+                .collect();
+            let SecAggMessage::MaskedInput { masked, .. } = client.masked_input(own).unwrap()
+            else {
+                unreachable!()
+            };
+            assert_ne!(masked, client.input);
+            for (s, m) in sum.iter_mut().zip(&masked) {
+                *s = s.wrapping_add(*m);
+            }
+            // Leave only the pairwise masks in the sum
+            add_mask(&mut sum, &client.seed, true);
+        }
+        let expected: Vec<u64> = (0..3)
+            .map(|j| {
+                clients
+                    .iter()
+                    .fold(0u64, |acc, c| acc.wrapping_add(c.input[j]))
+            })
+            .collect();
+        assert_eq!(sum, expected);
+    }
+
+    #[test]
+    fn aggregates_without_dropouts() {
+        let params = vec![
+            vec![0.5, -1.25, 3.0],
+            vec![2.0, 0.75, -0.5],
+            vec![-1.0, 1.0, 0.25],
+        ];
+        let (average, survivors) = run_round(&params, 1, &[]);
+        assert_eq!(survivors, 3);
+        assert_close(&average, &[0.5, 0.1666667, 0.9166667]);
+    }
+
+    #[test]
+    fn recovers_sum_after_dropout() {
+        let params = vec![
+            vec![0.5, -1.25, 3.0],
+            vec![2.0, 0.75, -0.5],
+            vec![-1.0, 1.0, 0.25],
+            vec![4.0, -2.0, 1.5],
+            vec![0.0, 0.5, -3.0],
+        ];
+        // Node 2 shares its keys but sends no masked input, so its pairwise masks are rebuilt
+        let (average, survivors) = run_round(&params, 2, &[2]);
+        assert_eq!(survivors, 4);
+        assert_close(&average, &[1.625, -0.5, 0.25]);
+    }
+
+    #[test]
+    fn quantization_is_accurate_to_scale() {
+        for value in [0.0f32, 1.0, -1.0, 0.123_456_7, -987.654_3, 1e-7, 12_345.678] {
+            let error = (dequantize(quantize(value)) - value as f64).abs();
+            assert!(error <= 1.0 / SCALE, "{} off by {}", value, error);
+        }
+        // Sums of negative and positive values survive the wrapping arithmetic
+        let sum = quantize(-2.5).wrapping_add(quantize(1.25));
+        assert_eq!(dequantize(sum), -1.25);
+    }
+}
@@ -0,0 +1,54 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// Independent random streams derived from the root seed
+#[derive(Clone, Copy, Debug)]
+pub enum Stream {
+    // Initial model weights
+    Init = 1,
+    // Synthetic data generation
+    Data,
+    // Division of a dataset among clients
+    Partition,
+    // A node's training run in one round
+    Round,
+    // Mini-batch order within a training run
+    Shuffle,
+    // Dropout masks within a training run
+    Dropout,
+    // Differential privacy noise of a server round, only with DP_SEEDED_NOISE
+    DpNoise,
+    // Local DP-SGD noise of a training run, only with DP_SGD_SEEDED_NOISE
+    DpSgdNoise,
+    // Train/validation split of a node's dataset
+    Split,
+}
+
+// Seed of `stream` number `index` under `parent`. Distinct inputs give unrelated
+// seeds, so streams never share random numbers.
+pub fn derive(parent: u64, stream: Stream, index: u64) -> u64 {
+    splitmix(splitmix(parent ^ splitmix(stream as u64)) ^ index)
+}
+
+// Random number generator for `stream` number `index` under `parent`
+pub fn rng(parent: u64, stream: Stream, index: u64) -> StdRng {
+    StdRng::seed_from_u64(derive(parent, stream, index))
+}
+
+// Seed of a node, derived from the root seed and its address so that every
+// node draws different numbers while runs stay reproducible
+pub fn node_seed(root: u64, node_addr: &str) -> u64 {
+    // FNV-1a hash of the address
+    let hash = node_addr.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    splitmix(root ^ hash)
+}
+
+// SplitMix64 finalizer
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
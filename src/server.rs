@@ -1,16 +1,147 @@
-use crate::messages::{GetModelParams, GetNodesRequest, NodeMessage, ServerMessage};
-use crate::model::{build_model, extract_params, update_model, SharedModel};
+use crate::evaluation::{load_holdout_file, EarlyStopping, EvaluationHistory};
+use crate::export::{load_safetensors_file, to_safetensors};
+use crate::layers::ModelSpec;
+use crate::messages::{
+    ConnectNode, ControlRound, GetGlobalModel, GetHistory, GetMetrics, GetModelOnnx,
+    GetModelParams, GetModelSafetensors, GetModelSpec, GetNodesRequest, GetPrivacy, GetProgress,
+    GetRoundConfig, NodeMessage, PredictBatch, ServerMessage, SetRoundConfig,
+};
+use crate::model::{
+    build_model, extract_params, param_schema, predict, share_model, update_model, FederatedModel,
+    PredictError, SharedModel,
+};
 use crate::network::NodeStatus;
+use crate::privacy::{DpConfig, DpFedAvg, PrivacyReport};
+use crate::screening::{HoldoutLosses, ScreeningConfig, UpdateScreener};
+use crate::secagg::{Advance, SecAggConfig, SecAggMessage, SecAggServer};
+use crate::seed::{self, Stream};
+use crate::signing::{self, ModelKind};
+use crate::tls;
+use crate::training::TrainingConfig;
+use crate::validation::{UpdateRejection, UpdateValidator};
 use actix::prelude::*;
 use anyhow::Result;
-use log::{error, info};
+use ed25519_dalek::SigningKey;
+use log::{error, info, warn};
+use ndarray::Array2;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
 
 pub struct CentralServer {
     nodes: Vec<String>,
-    aggregated_params: Option<Vec<f32>>,
+    // Nodes running in the same process, reached without HTTP
+    local_nodes: HashMap<String, Recipient<NodeMessage>>,
+    // Parameters received in the current round, keyed by node
+    pending_updates: Vec<(String, Vec<f32>)>,
+    // Round of the last update accepted from each node, to allow one update per node and round
+    last_update_round: HashMap<String, u64>,
     model: SharedModel,
     updates_received: usize,
-    total_nodes: usize,
+    config: ServerConfig,
+    // Current training round, starting at 0
+    round: u64,
+    // Whether the last round (or early stopping) has been reached
+    finished: bool,
+    validator: UpdateValidator,
+    metrics: ServerMetrics,
+    history: EvaluationHistory,
+    // Server-side holdout set evaluated after every aggregation
+    holdout: Option<(Array2<f32>, Array2<f32>)>,
+    // Differentially private aggregation and its accountant, if enabled
+    dp: Option<DpFedAvg>,
+    // Epsilon each node reported with its last update
+    node_epsilon: HashMap<String, f64>,
+    // Secure aggregation state of the current round, if enabled
+    secagg: Option<SecAggServer>,
+    // Key the global models sent to nodes are signed with
+    signing_key: Option<SigningKey>,
+    // Screening of updates before aggregation, if enabled
+    screener: Option<UpdateScreener>,
+}
+
+// Settings of the federation
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    // Root seed the server's randomness is derived from
+    pub seed: u64,
+    // Number of updates to collect before aggregating
+    pub total_nodes: usize,
+    // Upper bound on the L2 norm of a node's parameter vector
+    pub max_update_norm: f32,
+    // Training rounds to run, None to train until stopped; after the last one
+    // nodes only receive the final model
+    pub rounds: Option<u64>,
+    // Training settings sent with each round, None to let nodes use their own
+    pub round_training: Option<TrainingConfig>,
+    // Evaluate the global model on the nodes every this many rounds (and after
+    // the last one); 0 disables federated evaluation
+    pub eval_every: u64,
+    // Stop before `rounds` once the holdout metric plateaus
+    pub early_stopping: Option<EarlyStopping>,
+    // Client-level differential privacy (DP-FedAvg), None for plain FedAvg
+    pub dp: Option<DpConfig>,
+    // Aggregate masked updates so that only their sum is revealed
+    pub secure_aggregation: Option<SecAggConfig>,
+    // Screen updates for poisoning before aggregation
+    pub screening: Option<ScreeningConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            total_nodes: 2,
+            max_update_norm: 1000.0,
+            rounds: None,
+            round_training: None,
+            eval_every: 1,
+            early_stopping: None,
+            dp: None,
+            secure_aggregation: None,
+            screening: None,
+        }
+    }
+}
+
+// Round progress of the federation
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct RoundProgress {
+    // Rounds completed so far
+    pub round: u64,
+    pub finished: bool,
+}
+
+// Admin action on the federation's rounds
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RoundControl {
+    // Train this many more rounds after training has finished or was stopped
+    Start { rounds: u64 },
+    // Discard the current round's updates and have the nodes train it again
+    Restart,
+    // End training now and hand out the current global model
+    Stop,
+}
+
+// Counters exposed through the metrics API
+#[derive(Serialize, Clone, Default)]
+pub struct ServerMetrics {
+    pub updates_accepted: u64,
+    pub updates_rejected: u64,
+    // Rejected updates keyed by rejection code
+    pub rejections_by_reason: HashMap<String, u64>,
+    // Accepted updates the screening stage flagged, and what was done with them
+    pub updates_flagged: u64,
+    pub updates_down_weighted: u64,
+    pub updates_quarantined: u64,
+    // Failed screening checks keyed by check
+    pub flags_by_check: HashMap<String, u64>,
+    // Messages refused by the HTTP layer (oversized or rate limited) before reaching
+    // the server, keyed by reason; filled in by the metrics endpoint
+    pub messages_refused: u64,
+    pub refusals_by_reason: HashMap<String, u64>,
 }
 
 impl Actor for CentralServer {
@@ -19,16 +150,67 @@ impl Actor for CentralServer {
     fn started(&mut self, _: &mut Self::Context) {
         info!(
             "Central server started, expecting {} nodes",
-            self.total_nodes
+            self.config.total_nodes
         );
+        if let Some(early_stopping) = &self.config.early_stopping {
+            if self.holdout.is_some() {
+                info!(
+                    "Early stopping on holdout {} with patience {}",
+                    early_stopping.metric, early_stopping.patience
+                );
+            } else {
+                warn!("Early stopping is configured but no holdout set is loaded");
+            }
+        }
     }
 }
 
 impl Handler<ServerMessage> for CentralServer {
-    type Result = Result<(), String>;
+    type Result = Result<(), UpdateRejection>;
 
     fn handle(&mut self, msg: ServerMessage, _: &mut Self::Context) -> Self::Result {
-        info!("Received update from node: {}", msg.node_addr);
+        info!(
+            "Received round {} update from node: {} ({} samples, {} local steps)",
+            msg.round, msg.node_addr, msg.num_samples, msg.num_steps
+        );
+
+        // Reject updates that do not match the model schema before they reach the aggregate
+        let validation = match self.secagg {
+            // No round is open once training has ended, e.g. on an exhausted privacy budget
+            _ if self.finished => Err(UpdateRejection::TrainingFinished),
+            Some(_) => Err(UpdateRejection::PlaintextUpdate),
+            // The signature covers the round, so a replayed update from another round is refused here
+            None if msg.round != self.round => Err(UpdateRejection::StaleRound {
+                expected: self.round,
+                got: msg.round,
+            }),
+            // Each node contributes at most one update to a round
+            None if self.last_update_round.get(&msg.node_addr) == Some(&self.round) => {
+                Err(UpdateRejection::DuplicateUpdate { round: self.round })
+            }
+            None => self.validator.validate(&msg.params),
+        };
+
+        if let Err(rejection) = validation {
+            warn!("Rejected update from node {}: {}", msg.node_addr, rejection);
+            self.metrics.updates_rejected += 1;
+            *self
+                .metrics
+                .rejections_by_reason
+                .entry(rejection.code().to_string())
+                .or_insert(0) += 1;
+            return Err(rejection);
+        }
+        self.metrics.updates_accepted += 1;
+        self.last_update_round
+            .insert(msg.node_addr.clone(), self.round);
+        if let Some(epsilon) = msg.epsilon {
+            info!(
+                "Node {} has spent epsilon {:.4} on local DP-SGD",
+                msg.node_addr, epsilon
+            );
+            self.node_epsilon.insert(msg.node_addr.clone(), epsilon);
+        }
 
         // Add node if not already registered
         if !self.nodes.contains(&msg.node_addr) {
@@ -38,29 +220,23 @@ impl Handler<ServerMessage> for CentralServer {
 
         self.updates_received += 1;
 
-        // Aggregate parameters
-        if let Some(ref mut aggregated) = self.aggregated_params {
-            for (a, b) in aggregated.iter_mut().zip(msg.params.iter()) {
-                *a += *b;
-            }
-        } else {
-            self.aggregated_params = Some(msg.params);
-        }
+        // Keep the parameters for aggregation
+        self.pending_updates.push((msg.node_addr, msg.params));
 
         info!(
             "Received {}/{} updates",
-            self.updates_received, self.total_nodes
+            self.updates_received, self.config.total_nodes
         );
 
         // If we have updates from all nodes, perform FedAvg and broadcast
-        if self.updates_received >= self.total_nodes {
+        if self.updates_received >= self.config.total_nodes {
             match self.aggregate_and_broadcast() {
                 Ok(_) => {
                     info!("Aggregated and broadcasted model updates successfully");
                     self.updates_received = 0;
-                    self.aggregated_params = None;
                 }
-                Err(e) => error!("Failed to aggregate and broadcast: {}", e),
+                // The round's updates were consumed, so have the nodes send them again
+                Err(e) => self.restart_round(&format!("aggregation failed: {}", e)),
             }
         }
 
@@ -71,7 +247,7 @@ impl Handler<ServerMessage> for CentralServer {
 impl Handler<NodeMessage> for CentralServer {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: NodeMessage, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: NodeMessage, ctx: &mut Self::Context) -> Self::Result {
         match msg {
             NodeMessage::RegisterNode { addr } => {
                 if !self.nodes.contains(&addr) {
@@ -80,15 +256,48 @@ impl Handler<NodeMessage> for CentralServer {
                 }
                 Ok(())
             }
-            NodeMessage::UpdateModel { params } => {
-                // Create a server message and handle it
-                let server_msg = ServerMessage {
-                    node_addr: "direct".to_string(),
-                    params,
-                };
-                self.handle(server_msg, &mut Context::new())
+            NodeMessage::SubmitEvaluation {
+                node_addr,
+                round,
+                metrics,
+            } => {
+                info!(
+                    "Received round {} evaluation from node {}: {}",
+                    round, node_addr, metrics
+                );
+                // Only registered nodes report, and only on the model of the round just finished
+                if !self.nodes.contains(&node_addr) {
+                    warn!("Ignored evaluation from unregistered node {}", node_addr);
+                    return Err(format!("Node {} is not registered", node_addr));
+                }
+                if self.round.checked_sub(1) != Some(round) {
+                    warn!(
+                        "Ignored round {} evaluation from node {} after round {}",
+                        round, node_addr, self.round
+                    );
+                    return Err(format!(
+                        "Evaluation of round {} does not match the last finished round",
+                        round
+                    ));
+                }
+                let summary = self.history.record(round, node_addr, metrics);
+                if let Some(federated) = &summary.federated {
+                    info!(
+                        "Round {} federated evaluation from {} nodes: {}",
+                        summary.round,
+                        summary.nodes.len(),
+                        federated
+                    );
+                }
+                Ok(())
             }
-            _ => Ok(()), // Ignore other messages
+            NodeMessage::SecAgg(msg) => self.receive_secagg(*msg, ctx),
+            other => match ServerMessage::from_node_message(other) {
+                Ok(server_msg) => self
+                    .handle(server_msg, &mut Context::new())
+                    .map_err(|e| e.to_string()),
+                Err(_) => Ok(()), // Ignore other messages
+            },
         }
     }
 }
@@ -106,6 +315,12 @@ impl Handler<GetNodesRequest> for CentralServer {
             .map(|addr| NodeStatus {
                 address: addr.clone(),
                 status: "active".to_string(),
+                epsilon: self.node_epsilon.get(addr).copied(),
+                screening: self
+                    .screener
+                    .as_ref()
+                    .and_then(|screener| screener.reputation(addr))
+                    .cloned(),
             })
             .collect()
     }
@@ -122,58 +337,727 @@ impl Handler<GetModelParams> for CentralServer {
     }
 }
 
+impl Handler<GetGlobalModel> for CentralServer {
+    type Result = Result<NodeMessage, String>;
+
+    fn handle(&mut self, _: GetGlobalModel, _: &mut Self::Context) -> Self::Result {
+        let params = extract_params(&self.model)
+            .map_err(|e| format!("Failed to extract model parameters: {}", e))?;
+        Ok(NodeMessage::UpdateModel {
+            round: self.round,
+            signature: self.sign_model(ModelKind::Update, self.round, &params),
+            params,
+        })
+    }
+}
+
+impl Handler<GetModelSpec> for CentralServer {
+    type Result = Option<ModelSpec>;
+
+    fn handle(&mut self, _: GetModelSpec, _: &mut Self::Context) -> Self::Result {
+        self.model.lock().ok()?.architecture()
+    }
+}
+
+impl Handler<GetModelSafetensors> for CentralServer {
+    type Result = Result<Vec<u8>, String>;
+
+    fn handle(&mut self, _: GetModelSafetensors, _: &mut Self::Context) -> Self::Result {
+        let model = self
+            .model
+            .lock()
+            .map_err(|e| format!("Failed to lock model: {}", e))?;
+        to_safetensors(model.as_ref()).map_err(|e| format!("Failed to export model: {}", e))
+    }
+}
+
+impl Handler<GetModelOnnx> for CentralServer {
+    type Result = Result<Vec<u8>, String>;
+
+    fn handle(&mut self, _: GetModelOnnx, _: &mut Self::Context) -> Self::Result {
+        let model = self
+            .model
+            .lock()
+            .map_err(|e| format!("Failed to lock model: {}", e))?;
+        model
+            .to_onnx()
+            .map_err(|e| format!("Failed to export model: {}", e))
+    }
+}
+
+impl Handler<GetRoundConfig> for CentralServer {
+    type Result = Option<TrainingConfig>;
+
+    fn handle(&mut self, _: GetRoundConfig, _: &mut Self::Context) -> Self::Result {
+        self.config.round_training.clone()
+    }
+}
+
+impl Handler<SetRoundConfig> for CentralServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetRoundConfig, _: &mut Self::Context) -> Self::Result {
+        info!("Training settings for the next rounds: {:?}", msg.0);
+        self.config.round_training = msg.0;
+    }
+}
+
+impl Handler<PredictBatch> for CentralServer {
+    type Result = Result<Vec<Vec<f32>>, PredictError>;
+
+    fn handle(&mut self, msg: PredictBatch, _: &mut Self::Context) -> Self::Result {
+        predict(&self.model, &msg.0)
+    }
+}
+
+impl Handler<GetHistory> for CentralServer {
+    type Result = MessageResult<GetHistory>;
+
+    fn handle(&mut self, _: GetHistory, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.history.rounds().to_vec())
+    }
+}
+
+impl Handler<ConnectNode> for CentralServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ConnectNode, _: &mut Self::Context) -> Self::Result {
+        if !self.nodes.contains(&msg.addr) {
+            self.nodes.push(msg.addr.clone());
+        }
+        self.local_nodes.insert(msg.addr, msg.recipient);
+    }
+}
+
+impl Handler<GetProgress> for CentralServer {
+    type Result = MessageResult<GetProgress>;
+
+    fn handle(&mut self, _: GetProgress, _: &mut Self::Context) -> Self::Result {
+        MessageResult(RoundProgress {
+            round: self.round,
+            finished: self.finished,
+        })
+    }
+}
+
+impl Handler<ControlRound> for CentralServer {
+    type Result = Result<RoundProgress, String>;
+
+    fn handle(&mut self, msg: ControlRound, _: &mut Self::Context) -> Self::Result {
+        match msg.0 {
+            RoundControl::Start { rounds } => {
+                if !self.finished {
+                    return Err("Training is still running".to_string());
+                }
+                if rounds == 0 {
+                    return Err("At least one round must be started".to_string());
+                }
+                if self.dp.as_ref().is_some_and(|dp| !dp.can_continue()) {
+                    return Err("Another round would exceed the privacy budget".to_string());
+                }
+                self.config.rounds = Some(self.round + rounds);
+                self.finished = false;
+                info!(
+                    "Resuming training for {} more rounds, up to {}",
+                    rounds,
+                    self.round + rounds
+                );
+                self.open_round();
+            }
+            RoundControl::Restart => {
+                if self.finished {
+                    return Err("Training has finished, no round is open".to_string());
+                }
+                self.restart_round("restarted by an admin");
+            }
+            RoundControl::Stop => {
+                if self.finished {
+                    return Err("Training has already finished".to_string());
+                }
+                info!(
+                    "Stopping training in round {} on admin request",
+                    self.round + 1
+                );
+                self.finished = true;
+                self.discard_round();
+                let params = extract_params(&self.model)
+                    .map_err(|e| format!("Failed to read global model: {}", e))?;
+                self.broadcast(&NodeMessage::UpdateModel {
+                    round: self.round,
+                    signature: self.sign_model(ModelKind::Update, self.round, &params),
+                    params,
+                });
+            }
+        }
+        Ok(RoundProgress {
+            round: self.round,
+            finished: self.finished,
+        })
+    }
+}
+
+impl Handler<GetPrivacy> for CentralServer {
+    type Result = Option<PrivacyReport>;
+
+    fn handle(&mut self, _: GetPrivacy, _: &mut Self::Context) -> Self::Result {
+        self.dp.as_ref().map(DpFedAvg::report)
+    }
+}
+
+impl Handler<GetMetrics> for CentralServer {
+    type Result = MessageResult<GetMetrics>;
+
+    fn handle(&mut self, _: GetMetrics, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.metrics.clone())
+    }
+}
+
 impl CentralServer {
-    pub fn new(total_nodes: usize) -> Self {
-        let model = build_model();
+    pub fn new(config: ServerConfig, spec: &ModelSpec) -> Self {
+        let model = build_model(spec, config.seed);
+        Self::with_model(config, model)
+    }
+
+    // Create a server that federates a custom model architecture
+    pub fn with_model(config: ServerConfig, model: Box<dyn FederatedModel>) -> Self {
+        let model = share_model(model);
+        let schema = param_schema(&model).expect("Failed to read model schema");
+        let dp = config.dp.clone().map(DpFedAvg::new);
+        let param_count = schema.iter().map(|spec| spec.num_values()).sum();
+        let secagg = config
+            .secure_aggregation
+            .clone()
+            .map(|secagg| SecAggServer::new(secagg, config.total_nodes, param_count));
+        let screener = config.screening.clone().map(UpdateScreener::new);
 
         Self {
             nodes: Vec::new(),
-            aggregated_params: None,
+            local_nodes: HashMap::new(),
+            pending_updates: Vec::new(),
+            last_update_round: HashMap::new(),
             model,
             updates_received: 0,
-            total_nodes,
+            validator: UpdateValidator::new(schema, config.max_update_norm),
+            config,
+            round: 0,
+            // The first round starts with the nodes, so it must fit the privacy budget too
+            finished: dp.as_ref().is_some_and(|dp| !dp.can_continue()),
+            metrics: ServerMetrics::default(),
+            history: EvaluationHistory::default(),
+            holdout: None,
+            dp,
+            node_epsilon: HashMap::new(),
+            secagg,
+            signing_key: None,
+            screener,
         }
     }
 
+    // Sign every global model sent to nodes, so they can check it came from this server
+    pub fn set_signing_key(&mut self, key: SigningKey) {
+        self.signing_key = Some(key);
+    }
+
+    // Seed the global model from a safetensors file before the first round
+    pub fn seed_from_safetensors(&mut self, path: &Path) -> Result<()> {
+        let mut model = self
+            .model
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock model: {}", e))?;
+        load_safetensors_file(model.as_mut(), path)?;
+        info!("Seeded global model from {}", path.display());
+        Ok(())
+    }
+
+    // Load a held-out dataset to evaluate every new global model on
+    pub fn load_holdout(&mut self, path: &Path) -> Result<()> {
+        let model = self
+            .model
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock model: {}", e))?;
+        let (x, y) = load_holdout_file(path, model.as_ref())?;
+        info!(
+            "Loaded {} holdout samples from {}",
+            x.nrows(),
+            path.display()
+        );
+        drop(model);
+        self.holdout = Some((x, y));
+        Ok(())
+    }
+
+    // Evaluate the global model on the holdout set, returning true when early stopping triggers
+    fn evaluate_central(&mut self, round: u64) -> Result<bool, String> {
+        let Some((x, y)) = &self.holdout else {
+            return Ok(false);
+        };
+        let metrics = self
+            .model
+            .lock()
+            .map_err(|e| format!("Failed to lock model for evaluation: {}", e))?
+            .evaluate(x, y);
+        info!("Round {} central evaluation: {}", round, metrics);
+
+        let stop = match self.config.early_stopping.as_mut() {
+            Some(early_stopping) => early_stopping.should_stop(&metrics),
+            None => false,
+        };
+        self.history.record_central(round, metrics);
+        Ok(stop)
+    }
+
     fn aggregate_and_broadcast(&mut self) -> Result<(), String> {
-        if let Some(ref mut aggregated) = self.aggregated_params {
+        // Sum in node order so the result does not depend on arrival order
+        let mut updates = std::mem::take(&mut self.pending_updates);
+        updates.sort_by(|a, b| a.0.cmp(&b.0));
+        let updates = self.screen_updates(updates)?;
+        if updates.is_empty() {
+            return Err("No parameters to aggregate".to_string());
+        }
+
+        let aggregated = match self.dp.as_mut() {
+            // DP-FedAvg: clipped deltas to the current global model plus Gaussian noise
+            Some(dp) => {
+                let global = extract_params(&self.model)
+                    .map_err(|e| format!("Failed to read global model: {}", e))?;
+                // Noise drawn from the seed could be recomputed by anyone who knows it
+                let aggregated = if dp.config().seeded_noise {
+                    let mut rng = seed::rng(self.config.seed, Stream::DpNoise, self.round);
+                    dp.aggregate(&global, &updates, self.config.total_nodes, &mut rng)
+                } else {
+                    dp.aggregate(&global, &updates, self.config.total_nodes, &mut OsRng)
+                };
+                let report = dp.report();
+                info!(
+                    "Round {} privacy: epsilon {:.4} at delta {}, next clip norm {:.4}",
+                    self.round, report.epsilon, report.delta, report.clip_norm
+                );
+                aggregated
+            }
             // Apply FedAvg algorithm (simple averaging)
-            for param in aggregated.iter_mut() {
-                *param /= self.total_nodes as f32;
+            None => {
+                let count = updates.len();
+                let mut updates = updates.into_iter();
+                let mut aggregated = updates.next().unwrap_or_default();
+                for params in updates {
+                    for (a, b) in aggregated.iter_mut().zip(params.iter()) {
+                        *a += *b;
+                    }
+                }
+                for param in aggregated.iter_mut() {
+                    *param /= count as f32;
+                }
+                aggregated
             }
+        };
 
-            // Update central model
-            match update_model(&self.model, aggregated) {
-                Ok(_) => {
-                    info!("Central model updated successfully");
+        self.finish_round(aggregated)
+    }
+
+    // Screen the round's updates, pulling down-weighted ones towards the global model.
+    // Quarantined updates are left out, or replaced by the global model under DP-FedAvg,
+    // whose noise is calibrated to every node taking part.
+    fn screen_updates(
+        &mut self,
+        updates: Vec<(String, Vec<f32>)>,
+    ) -> Result<Vec<Vec<f32>>, String> {
+        if self.screener.is_none() {
+            return Ok(updates.into_iter().map(|(_, params)| params).collect());
+        }
+        let global = extract_params(&self.model)
+            .map_err(|e| format!("Failed to read global model: {}", e))?;
+        let holdout = self.holdout_losses(&global, &updates)?;
+        let Some(screener) = self.screener.as_mut() else {
+            return Err("Update screening is not enabled".to_string());
+        };
+        let verdicts = screener.screen(&global, &updates, holdout.as_ref());
+
+        let mut screened = Vec::with_capacity(updates.len());
+        for ((node_addr, params), verdict) in updates.into_iter().zip(verdicts) {
+            if verdict.flagged() {
+                self.metrics.updates_flagged += 1;
+                for finding in &verdict.findings {
+                    *self
+                        .metrics
+                        .flags_by_check
+                        .entry(finding.check().to_string())
+                        .or_insert(0) += 1;
+                }
+                let findings: Vec<String> =
+                    verdict.findings.iter().map(ToString::to_string).collect();
+                warn!(
+                    "Round {} update from node {} flagged (weight {}): {}",
+                    self.round + 1,
+                    node_addr,
+                    verdict.weight,
+                    findings.join("; ")
+                );
+            }
+            if verdict.weight == 1.0 {
+                screened.push(params);
+            } else if verdict.weight > 0.0 {
+                self.metrics.updates_down_weighted += 1;
+                let weight = verdict.weight;
+                screened.push(
+                    params
+                        .iter()
+                        .zip(&global)
+                        .map(|(p, g)| g + weight * (p - g))
+                        .collect(),
+                );
+            } else {
+                self.metrics.updates_quarantined += 1;
+                if self.dp.is_some() {
+                    screened.push(global.clone());
                 }
-                Err(e) => return Err(format!("Failed to update central model: {}", e)),
             }
+        }
+        if screened.is_empty() {
+            warn!("Every update of the round was quarantined; keeping the global model");
+            screened.push(global);
+        }
+        Ok(screened)
+    }
+
+    // Holdout loss of the global model and of each update on its own, if a holdout is loaded
+    fn holdout_losses(
+        &self,
+        global: &[f32],
+        updates: &[(String, Vec<f32>)],
+    ) -> Result<Option<HoldoutLosses>, String> {
+        let Some((x, y)) = &self.holdout else {
+            return Ok(None);
+        };
+        let mut model = self
+            .model
+            .lock()
+            .map_err(|e| format!("Failed to lock model for screening: {}", e))?;
+        let global_loss = model.evaluate(x, y).loss;
+        let mut losses = Vec::with_capacity(updates.len());
+        for (_, params) in updates {
+            model
+                .set_params(params)
+                .map_err(|e| format!("Failed to load update for screening: {}", e))?;
+            losses.push(model.evaluate(x, y).loss);
+        }
+        model
+            .set_params(global)
+            .map_err(|e| format!("Failed to restore global model: {}", e))?;
+        Ok(Some(HoldoutLosses {
+            global: global_loss,
+            updates: losses,
+        }))
+    }
+
+    // Apply an aggregated model, evaluate it and start the next round or hand it out
+    fn finish_round(&mut self, aggregated: Vec<f32>) -> Result<(), String> {
+        // Update central model
+        match update_model(&self.model, &aggregated) {
+            Ok(_) => {
+                info!("Central model updated successfully");
+            }
+            Err(e) => return Err(format!("Failed to update central model: {}", e)),
+        }
+
+        let completed = self.round;
+        self.round += 1;
+        let stopped_early = self.evaluate_central(completed)?;
+        if stopped_early {
+            info!(
+                "Stopping early after round {}: holdout metric has not improved",
+                completed + 1
+            );
+        }
+        let budget_spent = self.dp.as_ref().is_some_and(|dp| !dp.can_continue());
+        if budget_spent {
+            info!(
+                "Stopping after round {}: another round would exceed the privacy budget",
+                completed + 1
+            );
+        }
+        let last_round = stopped_early
+            || budget_spent
+            || self
+                .config
+                .rounds
+                .is_some_and(|rounds| self.round >= rounds);
+        self.finished = last_round;
 
-            // Broadcast to all nodes
-            let msg = NodeMessage::UpdateModel {
+        // Have the nodes evaluate the new global model on their held-out data
+        let eval_every = self.config.eval_every;
+        if eval_every > 0 && (last_round || self.round.is_multiple_of(eval_every)) {
+            self.broadcast(&NodeMessage::Evaluate {
+                round: completed,
+                signature: self.sign_model(ModelKind::Evaluate, completed, &aggregated),
                 params: aggregated.clone(),
-            };
+            });
+        }
+
+        // Start the next round on all nodes, or hand out the final model
+        let msg = if !last_round {
+            match self.config.rounds {
+                Some(rounds) => info!("Starting round {}/{}", self.round + 1, rounds),
+                None => info!("Starting round {}", self.round + 1),
+            }
+            NodeMessage::StartRound {
+                round: self.round,
+                signature: self.sign_model(ModelKind::StartRound, self.round, &aggregated),
+                params: aggregated.clone(),
+                training: self.config.round_training.clone().map(Box::new),
+            }
+        } else {
+            info!("Completed {} rounds", self.round);
+            NodeMessage::UpdateModel {
+                round: self.round,
+                signature: self.sign_model(ModelKind::Update, self.round, &aggregated),
+                params: aggregated,
+            }
+        };
+
+        self.broadcast(&msg);
 
-            for node in &self.nodes {
-                if node != "ping" && !node.is_empty() && node != "direct" {
-                    let node_addr = format!("{}/message", node);
-                    let msg_clone = msg.clone();
+        Ok(())
+    }
 
-                    // Use actix_web::rt::spawn instead of tokio::spawn
-                    actix_web::rt::spawn(async move {
-                        let client = awc::Client::default();
-                        match client.post(&node_addr).send_json(&msg_clone).await {
-                            Ok(_) => info!("Broadcast to {} successful", node_addr),
-                            Err(e) => error!("Failed to broadcast to {}: {}", node_addr, e),
-                        }
-                    });
+    // Record a secure aggregation message, moving to the next phase once every node has answered
+    fn receive_secagg(
+        &mut self,
+        msg: SecAggMessage,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), String> {
+        if let SecAggMessage::AdvertiseKeys {
+            keys,
+            round,
+            num_samples,
+            num_steps,
+            epsilon,
+        } = &msg
+        {
+            info!(
+                "Received round {} keys from node: {} ({} samples, {} local steps)",
+                round, keys.node_addr, num_samples, num_steps
+            );
+            if let Some(epsilon) = epsilon {
+                self.node_epsilon.insert(keys.node_addr.clone(), *epsilon);
+            }
+            if !self.nodes.contains(&keys.node_addr) {
+                info!("Registering new node: {}", keys.node_addr);
+                self.nodes.push(keys.node_addr.clone());
+            }
+        }
+
+        if self.finished {
+            return Err(UpdateRejection::TrainingFinished.to_string());
+        }
+        let secagg = self
+            .secagg
+            .as_mut()
+            .ok_or_else(|| "Secure aggregation is not enabled on this server".to_string())?;
+        // The first keys of a round start the clock on the nodes that are still training
+        let first_keys = secagg.collecting_keys() && secagg.responses() == 0;
+        if secagg.receive(self.round, msg)? {
+            self.advance_secagg(ctx);
+        } else if first_keys {
+            self.schedule_secagg_timeout(ctx);
+        }
+        Ok(())
+    }
+
+    // Close the current secure aggregation phase and start the next one
+    fn advance_secagg(&mut self, ctx: &mut Context<Self>) {
+        let Some(secagg) = self.secagg.as_mut() else {
+            return;
+        };
+        match secagg.advance(self.round) {
+            Ok(Advance::Send(messages)) => {
+                for (node, msg) in messages {
+                    self.send_to(&node, NodeMessage::SecAgg(Box::new(msg)));
+                }
+                self.schedule_secagg_timeout(ctx);
+            }
+            Ok(Advance::Done { params, survivors }) => {
+                info!(
+                    "Round {} securely aggregated from {} nodes",
+                    self.round + 1,
+                    survivors
+                );
+                self.metrics.updates_accepted += survivors as u64;
+                // The sum is all the server sees, so check it before it becomes the global model
+                if let Err(rejection) = self.validator.validate(&params) {
+                    self.restart_round(&format!(
+                        "secure aggregation produced an invalid aggregate: {}",
+                        rejection
+                    ));
+                    return;
+                }
+                match self.finish_round(params) {
+                    Ok(_) => info!("Aggregated and broadcasted model updates successfully"),
+                    Err(e) => error!("Failed to aggregate and broadcast: {}", e),
                 }
             }
+            Err(reason) => self.restart_round(&format!("secure aggregation failed: {}", reason)),
+        }
+    }
 
-            Ok(())
-        } else {
-            Err("No parameters to aggregate".to_string())
+    // Go on without the nodes missing from a phase, or restart the round when too few are left
+    fn schedule_secagg_timeout(&self, ctx: &mut Context<Self>) {
+        let Some(secagg) = &self.secagg else {
+            return;
+        };
+        let step = secagg.step();
+        ctx.run_later(secagg.config().phase_timeout, move |act, ctx| {
+            let Some(secagg) = act.secagg.as_ref().filter(|s| s.step() == step) else {
+                return;
+            };
+            warn!(
+                "Secure aggregation phase timed out with {} nodes answering",
+                secagg.responses()
+            );
+            act.advance_secagg(ctx);
+        });
+    }
+
+    // Abandon the round's updates and have the nodes train it again
+    fn restart_round(&mut self, reason: &str) {
+        warn!("Restarting round {}: {}", self.round + 1, reason);
+        self.open_round();
+    }
+
+    // Discard the current round's updates and send its global model to the nodes to train
+    fn open_round(&mut self) {
+        self.discard_round();
+        match extract_params(&self.model) {
+            Ok(params) => self.broadcast(&NodeMessage::StartRound {
+                round: self.round,
+                signature: self.sign_model(ModelKind::StartRound, self.round, &params),
+                params,
+                training: self.config.round_training.clone().map(Box::new),
+            }),
+            Err(e) => error!("Failed to read global model: {}", e),
+        }
+    }
+
+    // Drop the updates received in the current round, so that every node can send one again
+    fn discard_round(&mut self) {
+        self.pending_updates.clear();
+        self.updates_received = 0;
+        let round = self.round;
+        self.last_update_round.retain(|_, last| *last != round);
+        if let Some(secagg) = self.secagg.as_mut() {
+            secagg.reset();
+        }
+    }
+
+    // Server's signature of a global model, if it signs them
+    fn sign_model(&self, kind: ModelKind, round: u64, params: &[f32]) -> Option<String> {
+        self.signing_key
+            .as_ref()
+            .map(|key| signing::sign_model(key, kind, round, params))
+    }
+
+    // Send a message to every registered node
+    fn broadcast(&self, msg: &NodeMessage) {
+        for node in &self.nodes {
+            self.send_to(node, msg.clone());
+        }
+    }
+
+    // Send a message to one node, in memory or over HTTP
+    fn send_to(&self, node: &str, msg: NodeMessage) {
+        if let Some(recipient) = self.local_nodes.get(node) {
+            recipient.do_send(msg);
+        } else if node != "ping" && !node.is_empty() && node != "direct" {
+            let node_addr = format!("{}/message", node);
+
+            // Use actix_web::rt::spawn instead of tokio::spawn
+            actix_web::rt::spawn(async move {
+                let client = tls::http_client();
+                match client.post(&node_addr).send_json(&msg).await {
+                    Ok(_) => info!("Sent message to {}", node_addr),
+                    Err(e) => error!("Failed to send message to {}: {}", node_addr, e),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loss::Loss;
+    use crate::messages::{GetHistory, GetModelParams};
+    use crate::model::EvalMetrics;
+
+    fn metrics(loss: f32) -> EvalMetrics {
+        EvalMetrics {
+            loss,
+            accuracy: None,
+            rmse: Some(loss.sqrt()),
+            num_samples: 10,
+        }
+    }
+
+    fn evaluation(node_addr: &str, round: u64) -> NodeMessage {
+        NodeMessage::SubmitEvaluation {
+            node_addr: node_addr.to_string(),
+            round,
+            metrics: metrics(0.5),
         }
     }
+
+    #[actix_rt::test]
+    async fn evaluations_must_come_from_registered_nodes_for_the_last_round() {
+        let spec = ModelSpec::parse(2, "1:linear", Loss::Mse).unwrap();
+        let config = ServerConfig {
+            total_nodes: 1,
+            ..ServerConfig::default()
+        };
+        let server = CentralServer::new(config, &spec).start();
+
+        // Finish round 0, registering afterwards so the new model is not broadcast
+        let params = server.send(GetModelParams).await.unwrap().unwrap();
+        server
+            .send(NodeMessage::SubmitUpdate {
+                node_addr: "sim://a".to_string(),
+                round: 0,
+                params,
+                num_samples: 1,
+                num_steps: 1,
+                epsilon: None,
+                signature: None,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        server
+            .send(NodeMessage::RegisterNode {
+                addr: "sim://a".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Round 1 is still being trained
+        assert!(server
+            .send(evaluation("sim://a", 1))
+            .await
+            .unwrap()
+            .is_err());
+        assert!(server
+            .send(evaluation("sim://other", 0))
+            .await
+            .unwrap()
+            .is_err());
+        server
+            .send(evaluation("sim://a", 0))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let history = server.send(GetHistory).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].round, 0);
+        assert_eq!(history[0].nodes.len(), 1);
+        assert_eq!(history[0].nodes[0].node_addr, "sim://a");
+    }
 }
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+// Domain tags, so that a signature over one kind of message is never valid for another
+const UPDATE_CONTEXT: &[u8] = b"fedlearn/update/v1";
+
+// Name of the server in the signed digest of a global model
+const SERVER: &str = "server";
+
+// Message a global model is sent in. Each kind has its own domain tag, so a model
+// signed for evaluation cannot be replayed as a round to train or a final model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelKind {
+    StartRound,
+    Evaluate,
+    // Final model and `/api/model/params`
+    Update,
+}
+
+impl ModelKind {
+    fn context(self) -> &'static [u8] {
+        match self {
+            ModelKind::StartRound => b"fedlearn/global-model/start-round/v1",
+            ModelKind::Evaluate => b"fedlearn/global-model/evaluate/v1",
+            ModelKind::Update => b"fedlearn/global-model/update/v1",
+        }
+    }
+}
+
+// Create a fresh ed25519 key
+pub fn generate_key() -> SigningKey {
+    let mut secret = [0; 32];
+    OsRng.fill_bytes(&mut secret);
+    SigningKey::from_bytes(&secret)
+}
+
+pub fn encode_signing_key(key: &SigningKey) -> String {
+    hex::encode(key.to_bytes())
+}
+
+pub fn encode_verifying_key(key: &VerifyingKey) -> String {
+    hex::encode(key.to_bytes())
+}
+
+pub fn parse_signing_key(hex_key: &str) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&decode_array(hex_key)?))
+}
+
+pub fn parse_verifying_key(hex_key: &str) -> Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&decode_array(hex_key)?)
+        .map_err(|e| anyhow!("Invalid ed25519 public key: {}", e))
+}
+
+// Hash of a plaintext parameter vector
+pub fn params_hash(params: &[f32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for value in params {
+        hasher.update(value.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+// Hash of a masked secure aggregation input
+pub fn masked_hash(masked: &[u64]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for value in masked {
+        hasher.update(value.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+// Sign a node's update for a round
+pub fn sign_update(key: &SigningKey, round: u64, node_addr: &str, payload: [u8; 32]) -> String {
+    let digest = digest(UPDATE_CONTEXT, round, node_addr, payload);
+    hex::encode(key.sign(&digest).to_bytes())
+}
+
+pub fn verify_update(
+    key: &VerifyingKey,
+    round: u64,
+    node_addr: &str,
+    payload: [u8; 32],
+    signature: Option<&str>,
+) -> bool {
+    verify(
+        key,
+        &digest(UPDATE_CONTEXT, round, node_addr, payload),
+        signature,
+    )
+}
+
+// Sign a global model broadcast by the server
+pub fn sign_model(key: &SigningKey, kind: ModelKind, round: u64, params: &[f32]) -> String {
+    let digest = digest(kind.context(), round, SERVER, params_hash(params));
+    hex::encode(key.sign(&digest).to_bytes())
+}
+
+pub fn verify_model(
+    key: &VerifyingKey,
+    kind: ModelKind,
+    round: u64,
+    params: &[f32],
+    signature: Option<&str>,
+) -> bool {
+    verify(
+        key,
+        &digest(kind.context(), round, SERVER, params_hash(params)),
+        signature,
+    )
+}
+
+// SHA-256 over the context, the round, the length-prefixed signer and the payload hash
+fn digest(context: &[u8], round: u64, signer: &str, payload: [u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(context);
+    hasher.update(round.to_le_bytes());
+    hasher.update((signer.len() as u64).to_le_bytes());
+    hasher.update(signer.as_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+fn verify(key: &VerifyingKey, digest: &[u8; 32], signature: Option<&str>) -> bool {
+    let Some(Ok(bytes)) = signature.map(decode_array::<64>) else {
+        return false;
+    };
+    key.verify_strict(digest, &Signature::from_bytes(&bytes))
+        .is_ok()
+}
+
+fn decode_array<const N: usize>(value: &str) -> Result<[u8; N]> {
+    hex::decode(value)
+        .map_err(|e| anyhow!("Invalid hex: {}", e))?
+        .try_into()
+        .map_err(|_| anyhow!("Expected {} bytes", N))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: &str = "https://node-a.example:8002";
+
+    #[test]
+    fn update_signatures_are_bound_to_round_signer_and_payload() {
+        let key = generate_key();
+        let public = key.verifying_key();
+        let payload = params_hash(&[1.0, 2.0]);
+        let signature = sign_update(&key, 3, NODE, payload);
+
+        assert!(verify_update(&public, 3, NODE, payload, Some(&signature)));
+        assert!(!verify_update(&public, 4, NODE, payload, Some(&signature)));
+        assert!(!verify_update(
+            &public,
+            3,
+            "https://node-b.example:8002",
+            payload,
+            Some(&signature)
+        ));
+        let other_payload = params_hash(&[1.0, 2.5]);
+        assert!(!verify_update(
+            &public,
+            3,
+            NODE,
+            other_payload,
+            Some(&signature)
+        ));
+        let other_key = generate_key().verifying_key();
+        assert!(!verify_update(
+            &other_key,
+            3,
+            NODE,
+            payload,
+            Some(&signature)
+        ));
+        assert!(!verify_update(&public, 3, NODE, payload, None));
+        assert!(!verify_update(&public, 3, NODE, payload, Some("00")));
+    }
+
+    #[test]
+    fn model_signatures_are_bound_to_kind_and_round() {
+        let key = generate_key();
+        let public = key.verifying_key();
+        let params = [0.5, -1.0, 2.0];
+        let kinds = [
+            ModelKind::StartRound,
+            ModelKind::Evaluate,
+            ModelKind::Update,
+        ];
+        for kind in kinds {
+            let signature = sign_model(&key, kind, 2, &params);
+            assert!(verify_model(&public, kind, 2, &params, Some(&signature)));
+            assert!(!verify_model(&public, kind, 3, &params, Some(&signature)));
+            assert!(!verify_model(
+                &public,
+                kind,
+                2,
+                &[0.5, -1.0],
+                Some(&signature)
+            ));
+            for other in kinds.into_iter().filter(|&k| k != kind) {
+                assert!(!verify_model(&public, other, 2, &params, Some(&signature)));
+            }
+        }
+    }
+
+    #[test]
+    fn update_and_model_signatures_do_not_verify_as_each_other() {
+        let key = generate_key();
+        let public = key.verifying_key();
+        let params = [1.0, 2.0];
+
+        // A node named like the server signs the same round and parameters
+        let update = sign_update(&key, 1, SERVER, params_hash(&params));
+        assert!(!verify_model(
+            &public,
+            ModelKind::Update,
+            1,
+            &params,
+            Some(&update)
+        ));
+        let model = sign_model(&key, ModelKind::Update, 1, &params);
+        assert!(!verify_update(
+            &public,
+            1,
+            SERVER,
+            params_hash(&params),
+            Some(&model)
+        ));
+    }
+
+    #[test]
+    fn keys_round_trip_through_hex() {
+        let key = generate_key();
+        let parsed = parse_signing_key(&encode_signing_key(&key)).unwrap();
+        assert_eq!(parsed.to_bytes(), key.to_bytes());
+        let public = parse_verifying_key(&encode_verifying_key(&key.verifying_key())).unwrap();
+        assert_eq!(public, key.verifying_key());
+        assert!(parse_verifying_key("abcd").is_err());
+        assert!(parse_signing_key("not hex").is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::dataset::LocalDataset;
+use crate::layers::ModelSpec;
+use crate::messages::{
+    ConnectNode, GetHistory, GetModelParams, GetModelSafetensors, GetNodeStatus, GetProgress,
+    LoadDataset, NodeMessage,
+};
+use crate::model::EvalMetrics;
+use crate::node::{NodeActor, ServerLink};
+use crate::privacy::DpSgdConfig;
+use crate::secagg::SecAggConfig;
+use crate::server::CentralServer;
+use crate::training::TrainingConfig;
+use actix::prelude::*;
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// How often the runner checks the server's round progress
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Settings of an in-process simulation run
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    // Worker threads the simulated nodes are spread over
+    pub threads: usize,
+    // File the per-round metrics are written to, one JSON object per line
+    pub metrics_path: PathBuf,
+    // Give up when no round completes for this long
+    pub round_timeout: Duration,
+    // Where to save the final global model in safetensors format
+    pub model_path: Option<PathBuf>,
+    // Local DP-SGD settings of every simulated node
+    pub dp_sgd: Option<DpSgdConfig>,
+    // Secure aggregation settings of every simulated node
+    pub secure_aggregation: Option<SecAggConfig>,
+}
+
+// One line of the metrics file
+#[derive(Serialize)]
+struct RoundMetrics<'a> {
+    round: u64,
+    // Seconds from the start of the run until the round was aggregated
+    elapsed_secs: f64,
+    federated: Option<&'a EvalMetrics>,
+    central: Option<&'a EvalMetrics>,
+    // Nodes that reported a federated evaluation for the round
+    reporting_nodes: usize,
+}
+
+// Run a server and one node per dataset in this process until the last round,
+// then write the per-round metrics. Nodes and server talk through actor mailboxes.
+pub async fn run(
+    config: &SimulationConfig,
+    server: CentralServer,
+    spec: &ModelSpec,
+    training: &TrainingConfig,
+    datasets: Vec<LocalDataset>,
+    root_seed: u64,
+) -> Result<()> {
+    let server = server.start();
+    let initial_params = server.send(GetModelParams).await?.map_err(|e| anyhow!(e))?;
+
+    let arbiters: Vec<Arbiter> = (0..config.threads.max(1)).map(|_| Arbiter::new()).collect();
+    info!(
+        "Simulating {} nodes on {} threads",
+        datasets.len(),
+        arbiters.len()
+    );
+
+    let mut nodes = Vec::with_capacity(datasets.len());
+    for (i, arbiter) in (0..datasets.len()).zip(arbiters.iter().cycle()) {
+        let node_addr = node_addr(i);
+        let link = ServerLink::InMemory(server.clone().recipient());
+        let (spec, training, addr) = (spec.clone(), training.clone(), node_addr.clone());
+        let (dp_sgd, secure_aggregation) =
+            (config.dp_sgd.clone(), config.secure_aggregation.clone());
+        let node = NodeActor::start_in_arbiter(&arbiter.handle(), move |_| {
+            let mut node = NodeActor::new(link, addr, &spec, training, root_seed);
+            if let Some(config) = dp_sgd {
+                node = node.with_dp_sgd(config);
+            }
+            if let Some(config) = secure_aggregation {
+                node = node.with_secure_aggregation(config);
+            }
+            node
+        });
+
+        server
+            .send(ConnectNode {
+                addr: node_addr,
+                recipient: node.clone().recipient(),
+            })
+            .await?;
+        // Every node starts from the same global model
+        node.send(NodeMessage::UpdateModel {
+            params: initial_params.clone(),
+            round: 0,
+            signature: None,
+        })
+        .await?
+        .map_err(|e| anyhow!(e))?;
+        nodes.push(node);
+    }
+
+    let start = Instant::now();
+    // Loading its dataset makes a node train the first round; all nodes are
+    // queued before any result is awaited so they train in parallel
+    let loads: Vec<_> = nodes
+        .iter()
+        .zip(datasets)
+        .map(|(node, dataset)| node.send(LoadDataset(dataset)))
+        .collect();
+    for load in loads {
+        load.await?.map_err(|e| anyhow!(e))?;
+    }
+
+    let completed_at = wait_for_rounds(&server, start, config.round_timeout).await;
+    if completed_at.is_ok() {
+        // A reply from every node means its last evaluation is already queued at the server
+        for node in &nodes {
+            node.send(GetNodeStatus).await?;
+        }
+    }
+    for arbiter in &arbiters {
+        arbiter.stop();
+    }
+    let completed_at = completed_at?;
+    let history = server.send(GetHistory).await?;
+
+    let file = File::create(&config.metrics_path)
+        .map_err(|e| anyhow!("Failed to create {}: {}", config.metrics_path.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    for (round, elapsed) in completed_at.iter().enumerate() {
+        let evaluation = history.iter().find(|r| r.round == round as u64);
+        let line = RoundMetrics {
+            round: round as u64,
+            elapsed_secs: elapsed.as_secs_f64(),
+            federated: evaluation.and_then(|r| r.federated.as_ref()),
+            central: evaluation.and_then(|r| r.central.as_ref()),
+            reporting_nodes: evaluation.map_or(0, |r| r.nodes.len()),
+        };
+        serde_json::to_writer(&mut writer, &line)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    if let Some(path) = &config.model_path {
+        let bytes = server
+            .send(GetModelSafetensors)
+            .await?
+            .map_err(|e| anyhow!(e))?;
+        std::fs::write(path, bytes)
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+        info!("Final global model saved to {}", path.display());
+    }
+
+    info!(
+        "Simulation finished after {} rounds, metrics written to {}",
+        completed_at.len(),
+        config.metrics_path.display()
+    );
+    Ok(())
+}
+
+// Address of the simulated node with zero-based `index`
+pub fn node_addr(index: usize) -> String {
+    format!("sim://node-{}", index + 1)
+}
+
+// Wait until the server has run its last round, returning when each round completed
+async fn wait_for_rounds(
+    server: &Addr<CentralServer>,
+    start: Instant,
+    round_timeout: Duration,
+) -> Result<Vec<Duration>> {
+    let mut last_progress = Instant::now();
+    let mut completed_at = Vec::new();
+    loop {
+        let progress = server.send(GetProgress).await?;
+        if completed_at.len() < progress.round as usize {
+            completed_at.resize(progress.round as usize, start.elapsed());
+            last_progress = Instant::now();
+        }
+        if progress.finished {
+            return Ok(completed_at);
+        }
+        if last_progress.elapsed() > round_timeout {
+            return Err(anyhow!(
+                "Round {} did not complete within {:?}",
+                progress.round + 1,
+                round_timeout
+            ));
+        }
+        actix_web::rt::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loss::Loss;
+    use crate::partition::{self, PartitionStrategy};
+    use crate::seed::{self, Stream};
+    use crate::server::ServerConfig;
+
+    // Run a small simulation and return its final model and its metrics without timings
+    async fn simulate(root_seed: u64, name: &str) -> (Vec<u8>, Vec<serde_json::Value>) {
+        let spec = ModelSpec::parse(3, "4:relu,1:linear", Loss::Mse).unwrap();
+        let training = TrainingConfig {
+            epochs: 2,
+            ..TrainingConfig::default()
+        };
+        let partition_seed = seed::derive(root_seed, Stream::Partition, 0);
+        let samples =
+            partition::generate_data(60, &spec, &mut seed::rng(root_seed, Stream::Data, 0));
+        let datasets =
+            partition::partition(&samples, &spec, &PartitionStrategy::Iid, 3, partition_seed)
+                .unwrap()
+                .clients
+                .into_iter()
+                .map(|samples| LocalDataset::split(samples, 3, 0.25, partition_seed).unwrap())
+                .collect();
+
+        let dir = std::env::temp_dir().join(format!("simulation-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = SimulationConfig {
+            threads: 2,
+            metrics_path: dir.join("metrics.jsonl"),
+            round_timeout: Duration::from_secs(30),
+            model_path: Some(dir.join("model.safetensors")),
+            dp_sgd: None,
+            secure_aggregation: None,
+        };
+        let server = CentralServer::new(
+            ServerConfig {
+                seed: root_seed,
+                total_nodes: 3,
+                rounds: Some(3),
+                ..ServerConfig::default()
+            },
+            &spec,
+        );
+        run(&config, server, &spec, &training, datasets, root_seed)
+            .await
+            .unwrap();
+
+        let model = std::fs::read(dir.join("model.safetensors")).unwrap();
+        let metrics = std::fs::read_to_string(dir.join("metrics.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut value: serde_json::Value = serde_json::from_str(line).unwrap();
+                value.as_object_mut().unwrap().remove("elapsed_secs");
+                value
+            })
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        (model, metrics)
+    }
+
+    #[actix_rt::test]
+    async fn runs_with_the_same_root_seed_are_identical() {
+        let (model, metrics) = simulate(42, "first").await;
+        assert_eq!(metrics.len(), 3);
+        assert!(metrics.iter().all(|m| m["federated"].is_object()));
+
+        let (repeat_model, repeat_metrics) = simulate(42, "second").await;
+        assert_eq!(model, repeat_model);
+        assert_eq!(metrics, repeat_metrics);
+
+        let (other_model, _) = simulate(7, "other").await;
+        assert_ne!(model, other_model);
+    }
+}
@@ -0,0 +1,221 @@
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::any::Any;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+// Timeout of outgoing requests, as for awc's default client
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Client TLS settings shared by every outgoing request of the process
+static CLIENT_CONFIG: OnceCell<Arc<ClientConfig>> = OnceCell::new();
+
+// TLS settings of a server or node
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    // PEM certificate chain presented to peers, both when serving and as a client certificate
+    pub cert_path: PathBuf,
+    // PEM private key of the certificate
+    pub key_path: PathBuf,
+    // PEM CA certificates peers are verified against; None trusts the public web PKI
+    pub ca_path: Option<PathBuf>,
+    // Require clients to present a certificate signed by the CA (mutual TLS)
+    pub client_auth: bool,
+}
+
+// Identity of a peer authenticated by mutual TLS: the common name of its client certificate
+#[derive(Clone, Debug)]
+pub struct PeerIdentity(pub String);
+
+// Settings of the HTTPS listener
+pub fn server_config(tls: &TlsConfig) -> Result<ServerConfig> {
+    let builder = if tls.client_auth {
+        let ca_path = tls
+            .ca_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("Client certificate authentication needs a CA"))?;
+        let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(ca_path)?))
+            .build()
+            .map_err(|e| anyhow!("Invalid client CA {}: {}", ca_path.display(), e))?;
+        ServerConfig::builder().with_client_cert_verifier(verifier)
+    } else {
+        ServerConfig::builder().with_no_client_auth()
+    };
+    builder
+        .with_single_cert(load_certs(&tls.cert_path)?, load_key(&tls.key_path)?)
+        .map_err(|e| anyhow!("Invalid certificate or key: {}", e))
+}
+
+// Set up outgoing HTTPS: peers are verified against the CA (or the public roots),
+// and the process certificate is presented to servers that ask for one
+pub fn init_client(tls: Option<&TlsConfig>) -> Result<()> {
+    let roots = match tls.and_then(|tls| tls.ca_path.as_ref()) {
+        Some(ca_path) => load_roots(ca_path)?,
+        None => RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    };
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = match tls {
+        Some(tls) => builder
+            .with_client_auth_cert(load_certs(&tls.cert_path)?, load_key(&tls.key_path)?)
+            .map_err(|e| anyhow!("Invalid certificate or key: {}", e))?,
+        None => builder.with_no_client_auth(),
+    };
+    CLIENT_CONFIG
+        .set(Arc::new(config))
+        .map_err(|_| anyhow!("Client TLS is already initialized"))
+}
+
+// HTTP client for requests to the server or the nodes, speaking TLS to https:// addresses
+pub fn http_client() -> awc::Client {
+    match CLIENT_CONFIG.get() {
+        Some(config) => awc::Client::builder()
+            .connector(awc::Connector::new().rustls_0_23(config.clone()))
+            .timeout(REQUEST_TIMEOUT)
+            .finish(),
+        None => awc::Client::default(),
+    }
+}
+
+// Record the common name of a mutual TLS client certificate with the connection
+pub fn peer_identity(connection: &dyn Any, extensions: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let common_name = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(common_name);
+    if let Some(name) = common_name {
+        extensions.insert(PeerIdentity(name));
+    }
+}
+
+// Host part of a node address such as https://node-a.example:8002
+pub fn host(addr: &str) -> &str {
+    let rest = addr.split_once("://").map_or(addr, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    match authority.strip_prefix('[') {
+        // IPv6 literal
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(ipv6),
+        None => authority.split(':').next().unwrap_or(authority),
+    }
+}
+
+fn common_name(cert: &CertificateDer) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    let name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(name.to_string())
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(anyhow!("{} contains no certificates", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|e| anyhow!("Failed to read private key from {}: {}", path.display(), e))?
+        .ok_or_else(|| anyhow!("{} contains no private key", path.display()))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| anyhow!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    Ok(roots)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+    Ok(BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Self-signed certificate with subject O=Federation, CN=node-a.example
+    const WITH_CN: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBszCCAVmgAwIBAgIURIlOhnAQ3TXJSWQ06TT35FIPE9EwCgYIKoZIzj0EAwIw
+LjETMBEGA1UECgwKRmVkZXJhdGlvbjEXMBUGA1UEAwwObm9kZS1hLmV4YW1wbGUw
+IBcNMjYxMDE4MTkxMzE4WhgPMjEyNjA5MjQxOTEzMThaMC4xEzARBgNVBAoMCkZl
+ZGVyYXRpb24xFzAVBgNVBAMMDm5vZGUtYS5leGFtcGxlMFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAE+XhQ3tZ9wkcj43I5i0MH9MIkAiC1AAd4qeM6CfyZx7HWoYo2
+JMK3poIBg26Z2Xr37r7paK6h1y5dG2sHVSUc1aNTMFEwHQYDVR0OBBYEFFAQbGag
+9JS3p7112d9UA8Rv4jr8MB8GA1UdIwQYMBaAFFAQbGag9JS3p7112d9UA8Rv4jr8
+MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIgNOUjqVVuwjYHwT7n
+SkOu1uRX9bnoT6S1EnNyuNe4nT4CIQD2WNEN4kmpOuQ3sRBUPd8RRgYsX31THmIa
+dhwLpWqK/Q==
+-----END CERTIFICATE-----
+";
+
+    // Self-signed certificate with subject O=Federation and no common name
+    const WITHOUT_CN: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBgDCCASegAwIBAgIUaS4AB2gPa6BcxBuoshqFjSF5yjcwCgYIKoZIzj0EAwIw
+FTETMBEGA1UECgwKRmVkZXJhdGlvbjAgFw0yNjEwMTgxOTEzMThaGA8yMTI2MDky
+NDE5MTMxOFowFTETMBEGA1UECgwKRmVkZXJhdGlvbjBZMBMGByqGSM49AgEGCCqG
+SM49AwEHA0IABFYtDzX86FvFb358RuQNPxiegxENYQqIARTEJWq3sdVmeS6eR5+r
+ym0tSNQMu9SEfydRaYwvwg1oSBLtPxUsEJujUzBRMB0GA1UdDgQWBBTIy1vPs6ao
+qgeI1oH84xxN/1bf7DAfBgNVHSMEGDAWgBTIy1vPs6aoqgeI1oH84xxN/1bf7DAP
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0cAMEQCIEt0EIfZRanJ0NVCFaQl
+7XTo1fBbVdBUTBQMsYLKEinWAiBoR/ZWAdaZcczUYp1yutaj+Y2kJ04klUsKSqZ2
+9yHt9A==
+-----END CERTIFICATE-----
+";
+
+    fn cert(pem: &str) -> CertificateDer<'static> {
+        rustls_pemfile::certs(&mut pem.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_the_common_name_of_a_certificate() {
+        assert_eq!(
+            common_name(&cert(WITH_CN)).as_deref(),
+            Some("node-a.example")
+        );
+        assert_eq!(common_name(&cert(WITHOUT_CN)), None);
+        assert_eq!(common_name(&CertificateDer::from(vec![0u8; 16])), None);
+    }
+
+    #[test]
+    fn plain_connections_have_no_peer_identity() {
+        let mut extensions = Extensions::new();
+        peer_identity(&(), &mut extensions);
+        assert!(extensions.get::<PeerIdentity>().is_none());
+    }
+
+    #[test]
+    fn host_strips_scheme_port_and_path() {
+        assert_eq!(
+            host("https://node-a.example:8002/message"),
+            "node-a.example"
+        );
+        assert_eq!(host("node-a.example"), "node-a.example");
+        assert_eq!(host("https://[::1]:8002"), "::1");
+    }
+}
@@ -0,0 +1,287 @@
+use crate::model::TensorSpec;
+use crate::optim::{LrSchedule, OptimizerConfig};
+use anyhow::{anyhow, Result};
+use ndarray::{Array2, Axis};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+// Local training settings applied by every node in a round
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    // Passes over the local dataset per round
+    pub epochs: usize,
+    // Samples per gradient step; 0 trains on the full batch
+    pub batch_size: usize,
+    // Seed for shuffling, mixed with the node's per-round seed; epoch `e` uses `shuffle_seed + e`
+    pub shuffle_seed: u64,
+    // Base learning rate before warmup and decay
+    pub learning_rate: f32,
+    pub optimizer: OptimizerConfig,
+    pub schedule: LrSchedule,
+    // Steps of linear warmup before the schedule starts
+    pub warmup_steps: usize,
+    // Whether optimizer moments survive from one round to the next
+    pub optimizer_state: OptimizerState,
+    // L2 penalty on weight matrices (biases are not penalized); 0 disables it
+    pub l2: f32,
+    // Probability of dropping a hidden unit during training; 0 disables dropout
+    pub dropout: f32,
+    // Gradients with a larger L2 norm are scaled down to it before each step
+    pub max_grad_norm: Option<f32>,
+    // Share of a node's synthetic data held out for federated evaluation
+    pub eval_fraction: f32,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            epochs: 10,
+            batch_size: 32,
+            shuffle_seed: 0,
+            learning_rate: 0.01,
+            optimizer: OptimizerConfig::default(),
+            schedule: LrSchedule::default(),
+            warmup_steps: 0,
+            optimizer_state: OptimizerState::default(),
+            l2: 0.0,
+            dropout: 0.0,
+            max_grad_norm: None,
+            eval_fraction: 0.2,
+        }
+    }
+}
+
+impl TrainingConfig {
+    // Reject settings that would break or silently disable training
+    pub fn validate(&self) -> Result<()> {
+        if !(self.learning_rate.is_finite() && self.learning_rate > 0.0) {
+            return Err(anyhow!("learning_rate must be positive"));
+        }
+        if !(self.l2.is_finite() && self.l2 >= 0.0) {
+            return Err(anyhow!("l2 must be zero or positive"));
+        }
+        if !(0.0..1.0).contains(&self.dropout) {
+            return Err(anyhow!("dropout must be in [0, 1)"));
+        }
+        if !(0.0..1.0).contains(&self.eval_fraction) {
+            return Err(anyhow!("eval_fraction must be in [0, 1)"));
+        }
+        if let Some(max_norm) = self.max_grad_norm {
+            if !(max_norm.is_finite() && max_norm > 0.0) {
+                return Err(anyhow!("max_grad_norm must be positive"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Lifetime of client optimizer state
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizerState {
+    // Start every round from fresh moments and restart the schedule
+    #[default]
+    Reset,
+    // Keep moments and the schedule position across rounds
+    Persist,
+}
+
+// Split a dataset into mini-batches for one epoch, shuffled with `seed`
+pub fn epoch_batches(
+    x: &Array2<f32>,
+    y: &Array2<f32>,
+    batch_size: usize,
+    seed: u64,
+) -> Vec<(Array2<f32>, Array2<f32>)> {
+    let n = x.nrows();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(&mut StdRng::seed_from_u64(seed));
+
+    let batch_size = if batch_size == 0 { n } else { batch_size };
+
+    order
+        .chunks(batch_size)
+        .map(|rows| (x.select(Axis(0), rows), y.select(Axis(0), rows)))
+        .collect()
+}
+
+// Inverted dropout: kept units are scaled by 1 / (1 - rate) so inference needs no rescaling
+pub struct Dropout<'a> {
+    pub rate: f32,
+    pub rng: &'a mut StdRng,
+}
+
+impl Dropout<'_> {
+    // Random keep mask for a batch of activations
+    pub fn mask(&mut self, shape: (usize, usize)) -> Array2<f32> {
+        let scale = 1.0 / (1.0 - self.rate);
+        let rate = self.rate;
+        Array2::from_shape_fn(shape, |_| {
+            if self.rng.gen::<f32>() < rate {
+                0.0
+            } else {
+                scale
+            }
+        })
+    }
+}
+
+// Scale gradients down so their global L2 norm is at most `max_norm`
+pub fn clip_grad_norm(grads: &mut [f32], max_norm: f32) {
+    let norm = grads.iter().map(|g| g * g).sum::<f32>().sqrt();
+    if norm > max_norm {
+        let scale = max_norm / norm;
+        grads.iter_mut().for_each(|g| *g *= scale);
+    }
+}
+
+// Add the L2 penalty gradient to every weight tensor (rank 2 or more)
+pub fn add_l2_gradient(grads: &mut [f32], params: &[f32], schema: &[TensorSpec], l2: f32) {
+    let mut offset = 0;
+    for tensor in schema {
+        let len = tensor.num_values();
+        if tensor.shape.len() >= 2 {
+            for (g, p) in grads[offset..offset + len]
+                .iter_mut()
+                .zip(&params[offset..offset + len])
+            {
+                *g += l2 * p;
+            }
+        }
+        offset += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rows tagged with their index so batches can be traced back
+    fn indexed(n: usize) -> (Array2<f32>, Array2<f32>) {
+        let x = Array2::from_shape_fn((n, 2), |(i, j)| (i * 2 + j) as f32);
+        let y = Array2::from_shape_fn((n, 1), |(i, _)| i as f32);
+        (x, y)
+    }
+
+    fn rows(batches: &[(Array2<f32>, Array2<f32>)]) -> Vec<usize> {
+        batches
+            .iter()
+            .flat_map(|(_, y)| y.iter().map(|&v| v as usize).collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn epoch_batches_are_seeded_and_cover_every_row_once() {
+        let (x, y) = indexed(10);
+        let batches = epoch_batches(&x, &y, 3, 7);
+
+        assert_eq!(
+            batches.iter().map(|(x, _)| x.nrows()).collect::<Vec<_>>(),
+            vec![3, 3, 3, 1]
+        );
+        // Features stay paired with their labels
+        for (bx, by) in &batches {
+            for (row, label) in bx.rows().into_iter().zip(by.iter()) {
+                assert_eq!(row[0], label * 2.0);
+            }
+        }
+        let mut seen = rows(&batches);
+        let order = seen.clone();
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+
+        assert_eq!(rows(&epoch_batches(&x, &y, 3, 7)), order);
+        assert_ne!(rows(&epoch_batches(&x, &y, 3, 8)), order);
+    }
+
+    #[test]
+    fn epoch_batches_without_batch_size_use_the_full_batch() {
+        let (x, y) = indexed(5);
+        let batches = epoch_batches(&x, &y, 0, 1);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].0.nrows(), 5);
+
+        let (x, y) = indexed(0);
+        assert!(epoch_batches(&x, &y, 4, 1).is_empty());
+    }
+
+    #[test]
+    fn clip_grad_norm_scales_only_large_gradients() {
+        let mut grads = vec![3.0, 4.0];
+        clip_grad_norm(&mut grads, 10.0);
+        assert_eq!(grads, vec![3.0, 4.0]);
+
+        clip_grad_norm(&mut grads, 1.0);
+        assert!((grads[0] - 0.6).abs() < 1e-6);
+        assert!((grads[1] - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn dropout_mask_is_inverted() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mask = Dropout {
+            rate: 0.25,
+            rng: &mut rng,
+        }
+        .mask((100, 100));
+
+        // Kept units are scaled so the expected activation is unchanged
+        assert!(mask
+            .iter()
+            .all(|&m| m == 0.0 || (m - 1.0 / 0.75).abs() < 1e-6));
+        let dropped = mask.iter().filter(|&&m| m == 0.0).count() as f32 / 10_000.0;
+        assert!((dropped - 0.25).abs() < 0.02, "dropped {}", dropped);
+        assert!((mask.mean().unwrap() - 1.0).abs() < 0.03);
+    }
+
+    #[test]
+    fn l2_gradient_skips_biases() {
+        let schema = [TensorSpec::new("w", &[2, 2]), TensorSpec::new("b", &[2])];
+        let params = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut grads = [1.0; 6];
+        add_l2_gradient(&mut grads, &params, &schema, 0.5);
+        assert_eq!(grads, [1.5, 2.0, 2.5, 3.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_settings() {
+        assert!(TrainingConfig::default().validate().is_ok());
+
+        let invalid = [
+            TrainingConfig {
+                learning_rate: 0.0,
+                ..Default::default()
+            },
+            TrainingConfig {
+                learning_rate: f32::NAN,
+                ..Default::default()
+            },
+            TrainingConfig {
+                l2: -0.1,
+                ..Default::default()
+            },
+            TrainingConfig {
+                dropout: 1.0,
+                ..Default::default()
+            },
+            TrainingConfig {
+                eval_fraction: -0.1,
+                ..Default::default()
+            },
+            TrainingConfig {
+                max_grad_norm: Some(0.0),
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use crate::model::TensorSpec;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Reason an incoming model update was rejected
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum UpdateRejection {
+    // Total number of parameters does not match the model schema
+    LengthMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    // A tensor is missing values or has values left over
+    ShapeMismatch {
+        tensor: String,
+        expected_shape: Vec<usize>,
+        actual_values: usize,
+    },
+    // A parameter is NaN or infinite
+    NonFinite {
+        tensor: String,
+        index: usize,
+    },
+    // The L2 norm of the update is out of bounds
+    NormExceeded {
+        norm: f32,
+        max_norm: f32,
+    },
+    // A plaintext update while the server only accepts securely aggregated ones
+    PlaintextUpdate,
+    // The node already sent an update in this round
+    DuplicateUpdate {
+        round: u64,
+    },
+    // Training has ended and no round is open
+    TrainingFinished,
+    // The update belongs to another round than the current one
+    StaleRound {
+        expected: u64,
+        got: u64,
+    },
+}
+
+impl UpdateRejection {
+    // Short label used as the metrics key
+    pub fn code(&self) -> &'static str {
+        match self {
+            UpdateRejection::LengthMismatch { .. } => "length_mismatch",
+            UpdateRejection::ShapeMismatch { .. } => "shape_mismatch",
+            UpdateRejection::NonFinite { .. } => "non_finite",
+            UpdateRejection::NormExceeded { .. } => "norm_exceeded",
+            UpdateRejection::PlaintextUpdate => "plaintext_update",
+            UpdateRejection::DuplicateUpdate { .. } => "duplicate_update",
+            UpdateRejection::TrainingFinished => "training_finished",
+            UpdateRejection::StaleRound { .. } => "stale_round",
+        }
+    }
+}
+
+impl fmt::Display for UpdateRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateRejection::LengthMismatch { expected, actual } => {
+                write!(f, "Expected {} parameters, got {}", expected, actual)
+            }
+            UpdateRejection::ShapeMismatch {
+                tensor,
+                expected_shape,
+                actual_values,
+            } => write!(
+                f,
+                "Tensor {} expects shape {:?}, got {} values",
+                tensor, expected_shape, actual_values
+            ),
+            UpdateRejection::NonFinite { tensor, index } => {
+                write!(
+                    f,
+                    "Tensor {} has a non-finite value at index {}",
+                    tensor, index
+                )
+            }
+            UpdateRejection::NormExceeded { norm, max_norm } => write!(
+                f,
+                "Update has L2 norm {} above the limit of {}",
+                norm, max_norm
+            ),
+            UpdateRejection::PlaintextUpdate => write!(
+                f,
+                "Secure aggregation is enabled, updates must be sent masked"
+            ),
+            UpdateRejection::DuplicateUpdate { round } => {
+                write!(f, "An update was already received in round {}", round)
+            }
+            UpdateRejection::TrainingFinished => {
+                write!(f, "Training has finished, no round is open")
+            }
+            UpdateRejection::StaleRound { expected, got } => {
+                write!(
+                    f,
+                    "Update is for round {}, the current round is {}",
+                    got, expected
+                )
+            }
+        }
+    }
+}
+
+// Checks incoming parameter vectors against the model schema
+#[derive(Clone, Debug)]
+pub struct UpdateValidator {
+    schema: Vec<TensorSpec>,
+    max_norm: f32,
+}
+
+impl UpdateValidator {
+    pub fn new(schema: Vec<TensorSpec>, max_norm: f32) -> Self {
+        Self { schema, max_norm }
+    }
+
+    // Validate a flat parameter vector laid out in schema order
+    pub fn validate(&self, params: &[f32]) -> Result<(), UpdateRejection> {
+        let expected: usize = self.schema.iter().map(TensorSpec::num_values).sum();
+
+        // Check per-tensor shapes first so the error names the offending tensor
+        let mut offset = 0;
+        for spec in &self.schema {
+            let size = spec.num_values();
+            let available = params.len().saturating_sub(offset);
+            let is_last = offset + size == expected;
+            if available < size || (is_last && available > size) {
+                return Err(UpdateRejection::ShapeMismatch {
+                    tensor: spec.name.clone(),
+                    expected_shape: spec.shape.clone(),
+                    actual_values: available,
+                });
+            }
+            offset += size;
+        }
+
+        if params.len() != expected {
+            return Err(UpdateRejection::LengthMismatch {
+                expected,
+                actual: params.len(),
+            });
+        }
+
+        // Reject NaN/Inf values, naming the tensor they belong to
+        let mut offset = 0;
+        for spec in &self.schema {
+            let size = spec.num_values();
+            let values = &params[offset..offset + size];
+            if let Some(index) = values.iter().position(|v| !v.is_finite()) {
+                return Err(UpdateRejection::NonFinite {
+                    tensor: spec.name.clone(),
+                    index,
+                });
+            }
+            offset += size;
+        }
+
+        let norm = params.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if !norm.is_finite() || norm > self.max_norm {
+            return Err(UpdateRejection::NormExceeded {
+                norm,
+                max_norm: self.max_norm,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // w: 2x2, b: 2, so six values in total
+    fn validator(max_norm: f32) -> UpdateValidator {
+        UpdateValidator::new(
+            vec![TensorSpec::new("w", &[2, 2]), TensorSpec::new("b", &[2])],
+            max_norm,
+        )
+    }
+
+    #[test]
+    fn accepts_a_valid_update() {
+        assert_eq!(validator(10.0).validate(&[0.5; 6]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_length_mismatch() {
+        // Every tensor is checked for its shape first, so only values beyond an empty schema remain
+        let validator = UpdateValidator::new(Vec::new(), 10.0);
+        let rejection = validator.validate(&[1.0, 2.0]).unwrap_err();
+        assert_eq!(
+            rejection,
+            UpdateRejection::LengthMismatch {
+                expected: 0,
+                actual: 2
+            }
+        );
+        assert_eq!(rejection.code(), "length_mismatch");
+    }
+
+    #[test]
+    fn rejects_shape_mismatch() {
+        let rejection = validator(10.0).validate(&[0.0; 3]).unwrap_err();
+        assert_eq!(
+            rejection,
+            UpdateRejection::ShapeMismatch {
+                tensor: "w".to_string(),
+                expected_shape: vec![2, 2],
+                actual_values: 3,
+            }
+        );
+        assert_eq!(rejection.code(), "shape_mismatch");
+
+        // Values left over after the last tensor are blamed on that tensor
+        let rejection = validator(10.0).validate(&[0.0; 7]).unwrap_err();
+        assert_eq!(
+            rejection,
+            UpdateRejection::ShapeMismatch {
+                tensor: "b".to_string(),
+                expected_shape: vec![2],
+                actual_values: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_non_finite() {
+        let mut params = [0.0; 6];
+        params[5] = f32::NAN;
+        let rejection = validator(10.0).validate(&params).unwrap_err();
+        assert_eq!(
+            rejection,
+            UpdateRejection::NonFinite {
+                tensor: "b".to_string(),
+                index: 1
+            }
+        );
+        assert_eq!(rejection.code(), "non_finite");
+
+        params[5] = 0.0;
+        params[0] = f32::INFINITY;
+        assert_eq!(
+            validator(10.0).validate(&params),
+            Err(UpdateRejection::NonFinite {
+                tensor: "w".to_string(),
+                index: 0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_norm_exceeded() {
+        // Norm of six ones is sqrt(6) ≈ 2.449
+        let rejection = validator(2.0).validate(&[1.0; 6]).unwrap_err();
+        match &rejection {
+            UpdateRejection::NormExceeded { norm, max_norm } => {
+                assert!((norm - 6f32.sqrt()).abs() < 1e-6);
+                assert_eq!(*max_norm, 2.0);
+            }
+            other => panic!("unexpected rejection {:?}", other),
+        }
+        assert_eq!(rejection.code(), "norm_exceeded");
+        assert_eq!(validator(2.5).validate(&[1.0; 6]), Ok(()));
+    }
+}